@@ -0,0 +1,76 @@
+//! Chooses the SPIR-V module embedded in the executable.
+//!
+//! By default this is the prebuilt module at `$NANNOU_RAYTRACER_SPV`, or
+//! `nannou-raytracer-shader.spv` in the repository root, so the build doesn't run rust-gpu. The
+//! latter must have been built from the current sources of the `shader` and `shared` crates, see
+//! `shader_sources`. With `hot-reload`, or either rust-gpu feature, the shader crate is built here
+//! instead.
+
+use std::error::Error;
+use std::path::PathBuf;
+
+#[cfg(not(feature = "spirv-builder"))]
+#[path = "src/shader_sources.rs"]
+mod shader_sources;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    #[cfg(not(feature = "spirv-builder"))]
+    let module = prebuilt_module()?;
+    #[cfg(feature = "spirv-builder")]
+    let module = build_module()?;
+    println!("cargo:rustc-env=NANNOU_RAYTRACER_SHADER_SPV={}", module.display());
+    Ok(())
+}
+
+#[cfg(not(feature = "spirv-builder"))]
+fn prebuilt_module() -> Result<PathBuf, Box<dyn Error>> {
+    println!("cargo:rerun-if-env-changed=NANNOU_RAYTRACER_SPV");
+    // A module given explicitly is embedded as is.
+    if let Some(path) = std::env::var_os("NANNOU_RAYTRACER_SPV") {
+        let module = PathBuf::from(path);
+        println!("cargo:rerun-if-changed={}", module.display());
+        return Ok(module);
+    }
+
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..");
+    let module = root.join(shader_sources::PREBUILT_MODULE);
+    let stamp = shader_sources::stamp_path(&module);
+    println!("cargo:rerun-if-changed={}", module.display());
+    println!("cargo:rerun-if-changed={}", stamp.display());
+    for source in shader_sources::SOURCES {
+        println!("cargo:rerun-if-changed={}", root.join(source).display());
+    }
+    let update = "rebuild it with `--update-prebuilt-shader`, see the README, or enable \
+                  `hot-reload` to build the shader from source";
+    if !module.is_file() {
+        return Err(format!("no prebuilt shader at {}: {}", module.display(), update).into());
+    }
+    let recorded = std::fs::read_to_string(&stamp).unwrap_or_default();
+    if recorded.trim() != shader_sources::fingerprint(&root)? {
+        return Err(format!(
+            "the prebuilt shader at {} wasn't built from the current sources of `shader` and \
+             `shared`: {}",
+            module.display(),
+            update
+        )
+        .into());
+    }
+    Ok(module)
+}
+
+// Keep in step with `hot_reload::compile_and_exit`, which rebuilds the shader while running.
+#[cfg(feature = "spirv-builder")]
+fn build_module() -> Result<PathBuf, Box<dyn Error>> {
+    use spirv_builder::{Capability, MetadataPrintout, SpirvBuilder};
+
+    let crate_path = [env!("CARGO_MANIFEST_DIR"), "..", "shader"]
+        .iter()
+        .collect::<PathBuf>();
+    let result = SpirvBuilder::new(crate_path, "spirv-unknown-vulkan1.1")
+        .print_metadata(MetadataPrintout::DependencyOnly)
+        // Seems to be needed to handle conditions within functions?
+        // Error was confusing but adding this worked.
+        .capability(Capability::Int8)
+        .build()?;
+    Ok(result.module.unwrap_single().to_path_buf())
+}
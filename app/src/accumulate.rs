@@ -0,0 +1,158 @@
+//! GPU resources and passes for the progressive accumulation in `shared::accumulate`.
+
+use crate::shaders;
+use nannou::wgpu;
+use shared::ShaderConstants;
+
+/// The format of the accumulated mean radiance and sample count, and of the luminance moments.
+pub const ACCUMULATION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+
+pub struct Accumulator {
+    pipeline: wgpu::RenderPipeline,
+    // Reads the radiance written by `main_fs` and the current accumulation.
+    bind_group: wgpu::BindGroup,
+    // Binds the current accumulation to `main_fs`, which skips the pixels that have converged.
+    scene_bind_group: wgpu::BindGroup,
+    /// Mean radiance in `rgb` and the number of samples in `a`.
+    pub color: wgpu::Texture,
+    moments: wgpu::Texture,
+    // Written by the accumulate pass and then copied over `color` and `moments`.
+    next_color: wgpu::Texture,
+    next_moments: wgpu::Texture,
+    frames: u32,
+    // The constants that the accumulated frames were rendered with, see `reset_key`.
+    key: Option<Vec<u8>>,
+}
+
+impl Accumulator {
+    /// `color` is the radiance written by `main_fs`, which is added to the accumulation by
+    /// `encode`. `scene_layout` is the layout of `main_fs`'s second bind group.
+    pub fn new(
+        device: &wgpu::Device,
+        shader_mod: &wgpu::ShaderModule,
+        scene_layout: &wgpu::BindGroupLayout,
+        size: [u32; 2],
+        color: &wgpu::Texture,
+    ) -> Self {
+        let texture = |usage| {
+            wgpu::TextureBuilder::new()
+                .size(size)
+                .usage(wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED | usage)
+                .format(ACCUMULATION_FORMAT)
+                .build(device)
+        };
+        let copy_src = wgpu::TextureUsage::COPY_SRC;
+        let copy_dst = wgpu::TextureUsage::COPY_DST;
+        let accum_color = texture(copy_src | copy_dst);
+        let moments = texture(copy_dst);
+        let next_color = texture(copy_src);
+        let next_moments = texture(copy_src);
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("nannou-raytracer-accumulate-sampler"),
+            ..Default::default()
+        });
+        let layout = crate::texture_bind_group_layout(device, 3);
+        let textures = [color, &accum_color, &moments];
+        let bind_group = crate::texture_bind_group(device, &layout, &sampler, &textures);
+        let textures = [&accum_color, &moments];
+        let scene_bind_group = crate::texture_bind_group(device, scene_layout, &sampler, &textures);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("nannou-raytracer-accumulate-pipeline-layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[wgpu::PushConstantRange {
+                stages: wgpu::ShaderStage::all(),
+                range: 0..std::mem::size_of::<ShaderConstants>() as u32,
+            }],
+        });
+        let target = wgpu::ColorTargetState {
+            format: ACCUMULATION_FORMAT,
+            blend: None,
+            write_mask: wgpu::ColorWrite::ALL,
+        };
+        let pipeline = crate::fullscreen_pipeline(
+            device,
+            &pipeline_layout,
+            shader_mod,
+            shaders::accumulate_fs,
+            &[target.clone(), target],
+        );
+
+        Accumulator {
+            pipeline,
+            bind_group,
+            scene_bind_group,
+            color: accum_color,
+            moments,
+            next_color,
+            next_moments,
+            frames: 0,
+            key: None,
+        }
+    }
+
+    /// The bind group of the current accumulation for `main_fs`.
+    pub fn scene_bind_group(&self) -> &wgpu::BindGroup {
+        &self.scene_bind_group
+    }
+
+    /// Prepare the constants for this frame, discarding the accumulation if anything affecting the
+    /// render has changed. Call once per frame.
+    pub fn update(&mut self, push_constants: &mut ShaderConstants) {
+        if push_constants.accumulate == 0 {
+            self.reset();
+            return;
+        }
+        let key = reset_key(push_constants);
+        if self.key.as_ref() != Some(&key) {
+            self.frames = 0;
+            self.key = Some(key);
+        }
+        push_constants.accumulated_frames = self.frames;
+        push_constants.frame = self.frames;
+        self.frames = self.frames.saturating_add(1);
+    }
+
+    /// Discard the accumulation, e.g. after changing something outside of the constants.
+    pub fn reset(&mut self) {
+        self.frames = 0;
+        self.key = None;
+    }
+
+    /// Add the radiance written by `main_fs` this frame to the accumulation.
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, push_constants: &ShaderConstants) {
+        let color_view = self.next_color.view().build();
+        let moments_view = self.next_moments.view().build();
+        {
+            let mut render_pass = wgpu::RenderPassBuilder::new()
+                .color_attachment(&color_view, |attachment| attachment)
+                .color_attachment(&moments_view, |attachment| attachment)
+                .begin(encoder);
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            let pc_bytes = bytemuck::bytes_of(push_constants);
+            render_pass.set_push_constants(wgpu::ShaderStage::all(), 0, pc_bytes);
+            render_pass.draw(0..3, 0..1);
+        }
+        crate::copy_texture(encoder, &self.next_color, &self.color);
+        crate::copy_texture(encoder, &self.next_moments, &self.moments);
+    }
+}
+
+// The bytes of the constants that affect the radiance of each frame, i.e. all but those that
+// only change how the accumulation is displayed or that vary per frame anyway.
+fn reset_key(push_constants: &ShaderConstants) -> Vec<u8> {
+    let pc = ShaderConstants {
+        mouse_pixels: [0.0; 2],
+        frame: 0,
+        compute_region: [0; 4],
+        accumulated_frames: 0,
+        adaptive_threshold: 0.0,
+        exposure: 0.0,
+        tonemap: 0,
+        aov: 0,
+        ..*push_constants
+    };
+    bytemuck::bytes_of(&pc).to_vec()
+}
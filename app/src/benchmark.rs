@@ -0,0 +1,91 @@
+//! Timing the renderers against each other on identical frames, run with `--benchmark`.
+
+use crate::tiles::Tile;
+use crate::{env_map, scene, Config, Graphics, Renderer};
+use nannou::wgpu;
+use shared::ShaderConstants;
+use std::time::Instant;
+
+// The frames timed for each renderer, after one untimed frame to warm up.
+const FRAMES: u32 = 16;
+
+/// Whether the `--benchmark` argument was given.
+pub fn requested() -> bool {
+    std::env::args().skip(1).any(|arg| arg == "--benchmark")
+}
+
+/// Trace the same frame of the scene with each renderer in turn, printing the mean time per frame
+/// and the paths traced per second.
+///
+/// The animation and the noise are frozen so that every renderer traces the same paths, and the
+/// device is waited on before stopping the clock so that the times cover the GPU's work.
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    graphics: &Graphics,
+    env_map: &env_map::Bindings,
+    scene: &scene::Scene,
+    scene_bindings: &mut scene::Bindings,
+    mut push_constants: ShaderConstants,
+    config: &Config,
+) {
+    let [w, h] = graphics.scaled_texture.size();
+    push_constants.view_size_pixels = [w, h];
+    push_constants.env_map_size = env_map.size;
+    let (sun_elevation, sun_azimuth) = shared::sky::sun_position(config.time_of_day);
+    push_constants.sun_elevation = sun_elevation;
+    push_constants.sun_azimuth = sun_azimuth;
+    push_constants.time = 0.0;
+    scene.camera.aim(&mut push_constants);
+    push_constants.frame = 0;
+    push_constants.accumulate = 0;
+    push_constants.debug_mode = shared::debug::OFF;
+    push_constants.sphere_count = scene.spheres.len() as u32;
+    scene_bindings.upload(queue, scene, push_constants.time);
+
+    let tiles = [Tile { x: 0, y: 0, w, h }];
+    let scaled_view = graphics.scaled_texture.view().build();
+    let gbuffer_views = graphics.gbuffer.views();
+    let desc = wgpu::CommandEncoderDescriptor {
+        label: Some("nannou-raytracer-benchmark"),
+    };
+    let frame = |renderer| {
+        let mut encoder = device.create_command_encoder(&desc);
+        crate::encode_scene(
+            &mut encoder,
+            &graphics.pipelines,
+            renderer,
+            &scene_bindings.bind_group,
+            graphics.accumulator.scene_bind_group(),
+            &graphics.compute_bind_group,
+            &push_constants,
+            &tiles,
+            &scaled_view,
+            &gbuffer_views,
+        );
+        queue.submit(Some(encoder.finish()));
+    };
+
+    println!(
+        "Benchmarking {}x{} pixels at {} rays per pixel and {} bounces",
+        w, h, push_constants.rays_per_pixel, push_constants.ray_bounce_limit,
+    );
+    let paths = w as f64 * h as f64 * push_constants.rays_per_pixel as f64;
+    for &renderer in &Renderer::ALL {
+        frame(renderer);
+        device.poll(wgpu::Maintain::Wait);
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            frame(renderer);
+        }
+        device.poll(wgpu::Maintain::Wait);
+        let secs = start.elapsed().as_secs_f64() / FRAMES as f64;
+        println!(
+            "{:?}: {:.2} ms per frame, {:.1} million paths per second",
+            renderer,
+            secs * 1e3,
+            paths / secs / 1e6,
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! Reading textures back from the GPU and writing them to image files.
+
+use nannou::wgpu;
+use std::num::NonZeroU32;
+use std::path::Path;
+
+/// A texture copied into a mappable buffer, waiting to be read on the CPU.
+pub struct Readback {
+    buffer: wgpu::Buffer,
+    size: [u32; 2],
+    bytes_per_pixel: u32,
+    padded_bytes_per_row: u32,
+}
+
+impl Readback {
+    /// Encode a copy of `texture` into a new buffer.
+    ///
+    /// Rows are padded to `wgpu::COPY_BYTES_PER_ROW_ALIGNMENT`, which `read` strips again.
+    pub fn new(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        bytes_per_pixel: u32,
+    ) -> Self {
+        let size = texture.size();
+        let [w, h] = size;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = (w * bytes_per_pixel + align - 1) / align * align;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("nannou-raytracer-readback"),
+            size: padded_bytes_per_row as u64 * h as u64,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: NonZeroU32::new(h),
+                },
+            },
+            wgpu::Extent3d {
+                width: w,
+                height: h,
+                depth_or_array_layers: 1,
+            },
+        );
+        Self {
+            buffer,
+            size,
+            bytes_per_pixel,
+            padded_bytes_per_row,
+        }
+    }
+
+    pub fn size(&self) -> [u32; 2] {
+        self.size
+    }
+
+    /// Wait for the copy to complete and return the tightly packed rows.
+    ///
+    /// Blocks until the buffer is mapped, which only happens while the device is polled. Call this
+    /// from another thread than the one polling the device.
+    pub fn read(self) -> Result<Vec<u8>, wgpu::BufferAsyncError> {
+        let slice = self.buffer.slice(..);
+        futures::executor::block_on(slice.map_async(wgpu::MapMode::Read))?;
+        let row_bytes = (self.size[0] * self.bytes_per_pixel) as usize;
+        let mut bytes = Vec::with_capacity(row_bytes * self.size[1] as usize);
+        let mapped = slice.get_mapped_range();
+        for row in mapped.chunks(self.padded_bytes_per_row as usize) {
+            bytes.extend_from_slice(&row[..row_bytes]);
+        }
+        Ok(bytes)
+    }
+}
+
+/// Write `Rgba8UnormSrgb` texels to a PNG, along with text `metadata` as key and value pairs.
+pub fn save_png(
+    path: &Path,
+    [w, h]: [u32; 2],
+    rgba8: Vec<u8>,
+    metadata: &[(&str, &str)],
+) -> Result<(), png::EncodingError> {
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut encoder = png::Encoder::new(file, w, h);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    for (key, value) in metadata {
+        // An uncompressed international text chunk, which unlike `tEXt` holds UTF-8: the keyword,
+        // the compression flag and method, and an empty language tag and translated keyword.
+        let mut data = key.as_bytes().to_vec();
+        data.extend_from_slice(&[0, 0, 0, 0, 0]);
+        data.extend_from_slice(value.as_bytes());
+        writer.write_chunk(*b"iTXt", &data)?;
+    }
+    writer.write_image_data(&rgba8)
+}
+
+/// A group of channels in a layered EXR, each taken from a component of the texels in order.
+pub struct ExrLayer {
+    /// Full channel names, e.g. `albedo.R`, with unprefixed names forming the default layer.
+    pub channels: &'static [&'static str],
+    pub texels: Vec<[f32; 4]>,
+}
+
+/// Write the layers to a single-part EXR, grouping channels by the prefix of their name, along with
+/// text `metadata` as key and value pairs.
+///
+/// EXR text is Latin-1, so metadata with other characters is left out.
+pub fn save_exr_layers(
+    path: &Path,
+    [w, h]: [u32; 2],
+    layers: Vec<ExrLayer>,
+    metadata: &[(&str, &str)],
+) -> exr::error::UnitResult {
+    use exr::prelude::*;
+    let channels: SmallVec<[AnyChannel<FlatSamples>; 4]> = layers
+        .iter()
+        .flat_map(|layer| {
+            layer.channels.iter().enumerate().map(move |(i, name)| {
+                let samples = layer.texels.iter().map(|texel| texel[i]).collect();
+                AnyChannel::new(*name, FlatSamples::F32(samples))
+            })
+        })
+        .collect();
+    let mut attributes = LayerAttributes::default();
+    for (key, value) in metadata {
+        if let (Some(key), Some(value)) = (Text::new_or_none(key), Text::new_or_none(value)) {
+            attributes.other.insert(key, AttributeValue::Text(value));
+        }
+    }
+    let layer = Layer::new(
+        (w as usize, h as usize),
+        attributes,
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(channels),
+    );
+    Image::from_layer(layer).write().to_file(path)
+}
+
+/// Decode `Rgba16Float` texels as read back from the GPU.
+pub fn rgba16f_texels(bytes: &[u8]) -> Vec<[f32; 4]> {
+    let half = |b: &[u8]| exr::prelude::f16::from_bits(u16::from_le_bytes([b[0], b[1]])).to_f32();
+    bytes
+        .chunks_exact(8)
+        .map(|t| [half(&t[0..2]), half(&t[2..4]), half(&t[4..6]), half(&t[6..8])])
+        .collect()
+}
+
+/// Decode `Rgba32Float` texels as read back from the GPU.
+pub fn rgba32f_texels(bytes: &[u8]) -> Vec<[f32; 4]> {
+    let float = |b: &[u8]| f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+    bytes
+        .chunks_exact(16)
+        .map(|t| [float(&t[0..4]), float(&t[4..8]), float(&t[8..12]), float(&t[12..16])])
+        .collect()
+}
@@ -0,0 +1,129 @@
+//! Tracing the scene with the `main_cs` compute shader in place of the `main_fs` draw.
+//!
+//! Both write the same radiance and AOVs, so the passes that follow don't depend on which traced
+//! the scene. The raster path remains the default as it needs no storage texture support.
+
+use crate::gbuffer::{self, GBuffer};
+use crate::tiles::Tile;
+use crate::{shaders, HDR_TEXTURE_FORMAT};
+use nannou::wgpu;
+use shared::ShaderConstants;
+
+/// The width and height of the workgroups declared by `main_cs`.
+const WORKGROUP_SIZE: u32 = 8;
+
+pub struct ComputeRenderer {
+    pipeline: wgpu::ComputePipeline,
+    // The storage images written by `main_cs`, following the scene and accumulation.
+    bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl ComputeRenderer {
+    /// `scene_layout` and `accum_layout` are the layouts of the bind groups shared with `main_fs`.
+    pub fn new(
+        device: &wgpu::Device,
+        shader_mod: &wgpu::ShaderModule,
+        scene_layout: &wgpu::BindGroupLayout,
+        accum_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let storage_entry = |binding, format| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("nannou-raytracer-compute-bind-group-layout"),
+            entries: &[
+                storage_entry(0, HDR_TEXTURE_FORMAT),
+                storage_entry(1, gbuffer::NORMAL_DEPTH_FORMAT),
+                storage_entry(2, gbuffer::ALBEDO_FORMAT),
+                storage_entry(3, gbuffer::MATERIAL_FORMAT),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("nannou-raytracer-compute-pipeline-layout"),
+            bind_group_layouts: &[scene_layout, accum_layout, &bind_group_layout],
+            push_constant_ranges: &[wgpu::PushConstantRange {
+                stages: wgpu::ShaderStage::COMPUTE,
+                range: 0..std::mem::size_of::<ShaderConstants>() as u32,
+            }],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("nannou-raytracer-compute-pipeline"),
+            layout: Some(&pipeline_layout),
+            module: shader_mod,
+            entry_point: shaders::main_cs,
+        });
+        ComputeRenderer {
+            pipeline,
+            bind_group_layout,
+        }
+    }
+
+    /// The layout of the storage images written by `main_cs`.
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    /// Bind the HDR `color` texture and the `gbuffer` as the targets of `main_cs`.
+    ///
+    /// The textures must have the `STORAGE` usage.
+    pub fn bind_group(
+        &self,
+        device: &wgpu::Device,
+        color: &wgpu::Texture,
+        gbuffer: &GBuffer,
+    ) -> wgpu::BindGroup {
+        let textures = [color, &gbuffer.normal_depth, &gbuffer.albedo, &gbuffer.material];
+        let views: Vec<_> = textures.iter().map(|texture| texture.view().build()).collect();
+        let entries: Vec<_> = views
+            .iter()
+            .enumerate()
+            .map(|(binding, view)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: wgpu::BindingResource::TextureView(view),
+            })
+            .collect();
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("nannou-raytracer-compute-bind-group"),
+            layout: &self.bind_group_layout,
+            entries: &entries,
+        })
+    }
+
+    /// Ray trace the given `tiles` of the scene into the targets bound by `bind_group`, leaving
+    /// the rest of the textures as they are.
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        scene_bind_group: &wgpu::BindGroup,
+        accum_bind_group: &wgpu::BindGroup,
+        bind_group: &wgpu::BindGroup,
+        push_constants: &ShaderConstants,
+        tiles: &[Tile],
+    ) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("nannou-raytracer-compute-pass"),
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, scene_bind_group, &[]);
+        compute_pass.set_bind_group(1, accum_bind_group, &[]);
+        compute_pass.set_bind_group(2, bind_group, &[]);
+        for tile in tiles {
+            let constants = ShaderConstants {
+                compute_region: [tile.x, tile.y, tile.w, tile.h],
+                ..*push_constants
+            };
+            let pc_bytes = bytemuck::bytes_of(&constants);
+            compute_pass.set_push_constants(0, pc_bytes);
+            let groups_x = (tile.w + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+            let groups_y = (tile.h + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+            compute_pass.dispatch(groups_x, groups_y, 1);
+        }
+    }
+}
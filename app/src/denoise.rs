@@ -0,0 +1,261 @@
+//! GPU resources and passes for the spatio-temporal denoiser in `shared::denoise`.
+
+use crate::gbuffer::{self, GBuffer};
+use crate::{shaders, HDR_TEXTURE_FORMAT};
+use nannou::wgpu;
+use serde::{Deserialize, Serialize};
+use shared::denoise::DenoiseConstants;
+use shared::ShaderConstants;
+
+// The weight of the current frame once the history has saturated.
+const ALPHA: f32 = 0.2;
+const MOMENTS_ALPHA: f32 = 0.2;
+
+/// The maximum number of à-trous iterations, covering a 61x61 pixel footprint.
+pub const MAX_ITERATIONS: u32 = 5;
+
+/// User controls for the denoiser.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub enabled: bool,
+    /// The number of à-trous iterations in `1..=MAX_ITERATIONS`.
+    pub iterations: u32,
+    pub phi_color: f32,
+    pub phi_normal: f32,
+    pub phi_depth: f32,
+}
+
+pub struct Denoiser {
+    temporal_pipeline: wgpu::RenderPipeline,
+    atrous_pipeline: wgpu::RenderPipeline,
+    // Indexed by `parity`, each reading the moments written in the other.
+    temporal_bind_groups: [wgpu::BindGroup; 2],
+    // Reading `integrated`, `ping_pong[0]` and `ping_pong[1]` respectively.
+    atrous_bind_groups: [wgpu::BindGroup; 3],
+    // The demodulated illumination and variance accumulated this frame.
+    integrated: wgpu::Texture,
+    // Luminance moments and history length, alternately read and written.
+    moments: [wgpu::Texture; 2],
+    // The output of the first à-trous iteration, fed back as next frame's illumination history.
+    history: wgpu::Texture,
+    prev_normal_depth: wgpu::Texture,
+    ping_pong: [wgpu::Texture; 2],
+    constants: DenoiseConstants,
+    parity: usize,
+    has_history: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            enabled: false,
+            iterations: 4,
+            phi_color: 4.0,
+            phi_normal: 128.0,
+            phi_depth: 1.0,
+        }
+    }
+}
+
+impl Denoiser {
+    /// `color` is the noisy radiance written by `main_fs`, which is overwritten by `encode`.
+    pub fn new(
+        device: &wgpu::Device,
+        shader_mod: &wgpu::ShaderModule,
+        size: [u32; 2],
+        color: &wgpu::Texture,
+        gbuffer: &GBuffer,
+    ) -> Self {
+        let texture = |format, usage| {
+            wgpu::TextureBuilder::new()
+                .size(size)
+                .usage(wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED | usage)
+                .format(format)
+                .build(device)
+        };
+        let copy_src = wgpu::TextureUsage::COPY_SRC;
+        let copy_dst = wgpu::TextureUsage::COPY_DST;
+        let integrated = texture(HDR_TEXTURE_FORMAT, copy_src);
+        let moments = [
+            texture(HDR_TEXTURE_FORMAT, wgpu::TextureUsage::empty()),
+            texture(HDR_TEXTURE_FORMAT, wgpu::TextureUsage::empty()),
+        ];
+        let history = texture(HDR_TEXTURE_FORMAT, copy_dst);
+        let prev_normal_depth = texture(gbuffer::NORMAL_DEPTH_FORMAT, copy_dst);
+        let ping_pong = [
+            texture(HDR_TEXTURE_FORMAT, copy_src),
+            texture(HDR_TEXTURE_FORMAT, wgpu::TextureUsage::empty()),
+        ];
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("nannou-raytracer-denoise-sampler"),
+            ..Default::default()
+        });
+
+        let temporal_layout = crate::texture_bind_group_layout(device, 6);
+        let temporal_bind_group = |prev_moments: &wgpu::Texture| {
+            crate::texture_bind_group(
+                device,
+                &temporal_layout,
+                &sampler,
+                &[
+                    color,
+                    &gbuffer.normal_depth,
+                    &gbuffer.albedo,
+                    &history,
+                    prev_moments,
+                    &prev_normal_depth,
+                ],
+            )
+        };
+        let temporal_bind_groups = [
+            temporal_bind_group(&moments[1]),
+            temporal_bind_group(&moments[0]),
+        ];
+
+        let atrous_layout = crate::texture_bind_group_layout(device, 3);
+        let atrous_bind_group = |illumination: &wgpu::Texture| {
+            let textures = [illumination, &gbuffer.normal_depth, &gbuffer.albedo];
+            crate::texture_bind_group(device, &atrous_layout, &sampler, &textures)
+        };
+        let atrous_bind_groups = [
+            atrous_bind_group(&integrated),
+            atrous_bind_group(&ping_pong[0]),
+            atrous_bind_group(&ping_pong[1]),
+        ];
+
+        let hdr_target = wgpu::ColorTargetState {
+            format: HDR_TEXTURE_FORMAT,
+            blend: None,
+            write_mask: wgpu::ColorWrite::ALL,
+        };
+        let temporal_pipeline = crate::fullscreen_pipeline(
+            device,
+            &pipeline_layout(device, &temporal_layout),
+            shader_mod,
+            shaders::denoise_temporal_fs,
+            &[hdr_target.clone(), hdr_target.clone()],
+        );
+        let atrous_pipeline = crate::fullscreen_pipeline(
+            device,
+            &pipeline_layout(device, &atrous_layout),
+            shader_mod,
+            shaders::denoise_atrous_fs,
+            &[hdr_target],
+        );
+
+        Denoiser {
+            temporal_pipeline,
+            atrous_pipeline,
+            temporal_bind_groups,
+            atrous_bind_groups,
+            integrated,
+            moments,
+            history,
+            prev_normal_depth,
+            ping_pong,
+            constants: DenoiseConstants {
+                view_size_pixels: size,
+                ..Default::default()
+            },
+            parity: 0,
+            has_history: false,
+        }
+    }
+
+    /// Prepare the constants for this frame. Call once per frame while the denoiser is enabled.
+    pub fn update(&mut self, settings: &Settings, push_constants: &ShaderConstants) {
+        let c = &mut self.constants;
+        c.history_valid = self.has_history as u32;
+        c.prev_camera_from = c.camera_from;
+        c.prev_camera_to = c.camera_to;
+        c.camera_from = push_constants.camera_from;
+        c.camera_to = push_constants.camera_to;
+        c.prev_vfov = c.vfov;
+        c.vfov = push_constants.vfov;
+        c.alpha = ALPHA;
+        c.moments_alpha = MOMENTS_ALPHA;
+        c.phi_color = settings.phi_color;
+        c.phi_normal = settings.phi_normal;
+        c.phi_depth = settings.phi_depth;
+        self.parity = 1 - self.parity;
+        self.has_history = true;
+    }
+
+    /// Discard the history, e.g. while the denoiser is disabled.
+    pub fn reset(&mut self) {
+        self.has_history = false;
+    }
+
+    /// Filter the radiance written by `main_fs`, writing the result back to `color`.
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        settings: &Settings,
+        color: &wgpu::TextureView,
+        gbuffer: &GBuffer,
+    ) {
+        let integrated_view = self.integrated.view().build();
+        let moments_view = self.moments[self.parity].view().build();
+        {
+            let mut render_pass = wgpu::RenderPassBuilder::new()
+                .color_attachment(&integrated_view, |attachment| attachment)
+                .color_attachment(&moments_view, |attachment| attachment)
+                .begin(encoder);
+            render_pass.set_pipeline(&self.temporal_pipeline);
+            render_pass.set_bind_group(0, &self.temporal_bind_groups[self.parity], &[]);
+            set_push_constants(&mut render_pass, &self.constants);
+            render_pass.draw(0..3, 0..1);
+        }
+
+        let iterations = settings.iterations.max(1).min(MAX_ITERATIONS);
+        if iterations == 1 {
+            crate::copy_texture(encoder, &self.integrated, &self.history);
+        }
+        let ping_pong_views = [self.ping_pong[0].view().build(), self.ping_pong[1].view().build()];
+        for i in 0..iterations {
+            let last = i + 1 == iterations;
+            let target = if last { color } else { &ping_pong_views[i as usize % 2] };
+            let bind_group = match i {
+                0 => &self.atrous_bind_groups[0],
+                _ => &self.atrous_bind_groups[1 + (i as usize - 1) % 2],
+            };
+            let constants = DenoiseConstants {
+                step_size: 1 << i,
+                remodulate: last as u32,
+                ..self.constants
+            };
+            {
+                let mut render_pass = wgpu::RenderPassBuilder::new()
+                    .color_attachment(target, |attachment| attachment)
+                    .begin(encoder);
+                render_pass.set_pipeline(&self.atrous_pipeline);
+                render_pass.set_bind_group(0, bind_group, &[]);
+                set_push_constants(&mut render_pass, &constants);
+                render_pass.draw(0..3, 0..1);
+            }
+            if i == 0 && !last {
+                crate::copy_texture(encoder, &self.ping_pong[0], &self.history);
+            }
+        }
+
+        crate::copy_texture(encoder, &gbuffer.normal_depth, &self.prev_normal_depth);
+    }
+}
+
+fn set_push_constants(render_pass: &mut wgpu::RenderPass, constants: &DenoiseConstants) {
+    let pc_bytes = bytemuck::bytes_of(constants);
+    render_pass.set_push_constants(wgpu::ShaderStage::all(), 0, pc_bytes);
+}
+
+fn pipeline_layout(device: &wgpu::Device, layout: &wgpu::BindGroupLayout) -> wgpu::PipelineLayout {
+    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("nannou-raytracer-denoise-pipeline-layout"),
+        bind_group_layouts: &[layout],
+        push_constant_ranges: &[wgpu::PushConstantRange {
+            stages: wgpu::ShaderStage::all(),
+            range: 0..std::mem::size_of::<DenoiseConstants>() as u32,
+        }],
+    })
+}
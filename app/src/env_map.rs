@@ -0,0 +1,235 @@
+//! Loading of equirectangular environment maps and their GPU resources.
+
+use nannou::wgpu;
+use nannou::wgpu::util::DeviceExt;
+use std::fmt;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// An equirectangular environment map with linear RGBA texels in row-major order.
+pub struct EnvMap {
+    pub name: String,
+    /// The file the map was loaded from, if any.
+    pub path: Option<PathBuf>,
+    pub width: u32,
+    pub height: u32,
+    pub texels: Vec<[f32; 4]>,
+}
+
+/// The GPU resources bound for environment lighting, see `scene::bind_group_layout`.
+pub struct Bindings {
+    pub texture_view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+    pub cdf_buffer: wgpu::Buffer,
+    pub size: [u32; 2],
+    pub name: Option<String>,
+    pub path: Option<PathBuf>,
+    _texture: wgpu::Texture,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    Hdr(image::ImageError),
+    Exr(exr::error::Error),
+    UnknownFormat(PathBuf),
+}
+
+impl EnvMap {
+    /// A single white texel, used until an environment map is loaded.
+    pub fn placeholder() -> Self {
+        Self {
+            name: String::new(),
+            path: None,
+            width: 1,
+            height: 1,
+            texels: vec![[1.0; 4]],
+        }
+    }
+
+    /// Load a Radiance `.hdr` or OpenEXR `.exr` file.
+    pub fn load(path: &Path) -> Result<Self, LoadError> {
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        let (width, height, texels) = match ext.as_deref() {
+            Some("hdr") => load_hdr(path)?,
+            Some("exr") => load_exr(path)?,
+            _ => return Err(LoadError::UnknownFormat(path.to_path_buf())),
+        };
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(Self {
+            name,
+            path: Some(path.to_path_buf()),
+            width,
+            height,
+            texels,
+        })
+    }
+
+    /// Build the importance sampling table expected by `shared::environment::EnvMapDistribution`.
+    pub fn distribution(&self) -> Vec<f32> {
+        let (w, h) = (self.width as usize, self.height as usize);
+        let mut cdf = Vec::with_capacity(h * (w + 1) + h + 1);
+        let mut row_integrals = Vec::with_capacity(h);
+        for y in 0..h {
+            // Account for the stretching of rows towards the poles.
+            let sin_theta = ((y as f32 + 0.5) / h as f32 * std::f32::consts::PI).sin();
+            let row = &self.texels[y * w..(y + 1) * w];
+            let weights = row.iter().map(|&[r, g, b, _]| luminance(r, g, b).max(0.0) * sin_theta);
+            row_integrals.push(push_cdf(&mut cdf, w, weights));
+        }
+        push_cdf(&mut cdf, h, row_integrals.into_iter());
+        cdf
+    }
+}
+
+impl Bindings {
+    /// Upload the environment map and its importance sampling table.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        env_map: &EnvMap,
+    ) -> Self {
+        let size = [env_map.width, env_map.height];
+        let texture = wgpu::TextureBuilder::new()
+            .size(size)
+            .format(wgpu::TextureFormat::Rgba32Float)
+            .usage(wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST)
+            .build(device);
+        let texel_bytes: &[u8] = bytemuck::cast_slice(&env_map.texels);
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            texel_bytes,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(env_map.width * 16),
+                rows_per_image: std::num::NonZeroU32::new(env_map.height),
+            },
+            wgpu::Extent3d {
+                width: env_map.width,
+                height: env_map.height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        let cdf = env_map.distribution();
+        let cdf_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("nannou-raytracer-env-cdf"),
+            contents: bytemuck::cast_slice(&cdf),
+            usage: wgpu::BufferUsage::STORAGE,
+        });
+
+        // `Rgba32Float` is not filterable, so sample the nearest texel.
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("nannou-raytracer-env-sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let texture_view = texture.view().build();
+        let name = Some(env_map.name.clone()).filter(|name| !name.is_empty());
+        Self {
+            texture_view,
+            sampler,
+            cdf_buffer,
+            size,
+            name,
+            path: env_map.path.clone(),
+            _texture: texture,
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "failed to read environment map: {}", err),
+            LoadError::Hdr(err) => write!(f, "failed to decode `.hdr`: {}", err),
+            LoadError::Exr(err) => write!(f, "failed to decode `.exr`: {}", err),
+            LoadError::UnknownFormat(path) => {
+                write!(f, "expected a `.hdr` or `.exr` file, found {}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+    fn from(err: std::io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+impl From<image::ImageError> for LoadError {
+    fn from(err: image::ImageError) -> Self {
+        LoadError::Hdr(err)
+    }
+}
+
+impl From<exr::error::Error> for LoadError {
+    fn from(err: exr::error::Error) -> Self {
+        LoadError::Exr(err)
+    }
+}
+
+fn load_hdr(path: &Path) -> Result<(u32, u32, Vec<[f32; 4]>), LoadError> {
+    let file = std::fs::File::open(path)?;
+    let decoder = image::codecs::hdr::HdrDecoder::new(BufReader::new(file))?;
+    let meta = decoder.metadata();
+    let texels = decoder
+        .read_image_hdr()?
+        .into_iter()
+        .map(|image::Rgb([r, g, b])| [r, g, b, 1.0])
+        .collect();
+    Ok((meta.width, meta.height, texels))
+}
+
+fn load_exr(path: &Path) -> Result<(u32, u32, Vec<[f32; 4]>), LoadError> {
+    let image = exr::prelude::read_first_rgba_layer_from_file(
+        path,
+        |resolution, _channels| {
+            let texels = vec![[0.0; 4]; resolution.width() * resolution.height()];
+            (resolution.width(), texels)
+        },
+        |(width, texels), pos, (r, g, b, a): (f32, f32, f32, f32)| {
+            texels[pos.y() * *width + pos.x()] = [r, g, b, a];
+        },
+    )?;
+    let size = image.layer_data.size;
+    let (_, texels) = image.layer_data.channel_data.pixels;
+    Ok((size.width() as u32, size.height() as u32, texels))
+}
+
+// Append a normalised CDF over `weights` to `cdf`, returning the integral of the weights.
+//
+// Falls back to a uniform distribution if all weights are zero.
+fn push_cdf(cdf: &mut Vec<f32>, count: usize, weights: impl Iterator<Item = f32>) -> f32 {
+    let start = cdf.len();
+    let mut sum = 0.0;
+    cdf.push(0.0);
+    for weight in weights {
+        sum += weight / count as f32;
+        cdf.push(sum);
+    }
+    for (i, c) in cdf[start..].iter_mut().enumerate() {
+        *c = if sum > 0.0 { *c / sum } else { i as f32 / count as f32 };
+    }
+    sum
+}
+
+fn luminance(r: f32, g: f32, b: f32) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
@@ -0,0 +1,65 @@
+//! The auxiliary outputs written by `main_fs` alongside the radiance of each pixel.
+//!
+//! Besides being viewable and exported as layers, these guide the denoiser.
+
+use nannou::wgpu;
+
+/// Normal in `rgb` and distance to the primary hit in `a`, zero where the primary ray escaped.
+pub const NORMAL_DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+/// Albedo of the primary hit in `rgb`.
+pub const ALBEDO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// Material kind + 1 and index of the primary hit in `rg` and the mean bounce count in `b`.
+pub const MATERIAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+
+pub struct GBuffer {
+    pub normal_depth: wgpu::Texture,
+    pub albedo: wgpu::Texture,
+    pub material: wgpu::Texture,
+}
+
+impl GBuffer {
+    /// `usage` is added to the `RENDER_ATTACHMENT | SAMPLED` usage of every texture.
+    pub fn new(device: &wgpu::Device, size: [u32; 2], usage: wgpu::TextureUsage) -> Self {
+        let texture = |format| {
+            wgpu::TextureBuilder::new()
+                .size(size)
+                .usage(wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED | usage)
+                .format(format)
+                .build(device)
+        };
+        GBuffer {
+            normal_depth: texture(NORMAL_DEPTH_FORMAT),
+            albedo: texture(ALBEDO_FORMAT),
+            material: texture(MATERIAL_FORMAT),
+        }
+    }
+
+    /// The targets following the radiance target of the `main_fs` pipeline, in output order.
+    pub fn color_targets() -> [wgpu::ColorTargetState; 3] {
+        let target = |format| wgpu::ColorTargetState {
+            format,
+            blend: None,
+            write_mask: wgpu::ColorWrite::ALL,
+        };
+        [
+            target(NORMAL_DEPTH_FORMAT),
+            target(ALBEDO_FORMAT),
+            target(MATERIAL_FORMAT),
+        ]
+    }
+
+    pub fn views(&self) -> Views {
+        Views {
+            normal_depth: self.normal_depth.view().build(),
+            albedo: self.albedo.view().build(),
+            material: self.material.view().build(),
+        }
+    }
+}
+
+/// Views of the `GBuffer` textures for use as render pass attachments.
+pub struct Views {
+    pub normal_depth: wgpu::TextureView,
+    pub albedo: wgpu::TextureView,
+    pub material: wgpu::TextureView,
+}
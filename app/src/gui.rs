@@ -1,8 +1,9 @@
-use crate::Config;
+use crate::{scene, timeline, Config, Renderer};
 use fps_ticker::Fps;
 use nannou::prelude::*;
 use nannou::ui::prelude::*;
 use shared::ShaderConstants;
+use std::path::{Path, PathBuf};
 
 pub const WIN_W: u32 = 280;
 pub const WIN_X: i32 = PAD as i32;
@@ -14,43 +15,157 @@ const DEFAULT_WIDGET_H: Scalar = 30.0;
 widget_ids! {
     pub struct Ids {
         background,
+        scrollbar,
         title_text,
+        shader_status_text,
+        scene_status_text,
         scene_fps_text,
         scene_fps_avg_text,
         scene_fps_min_text,
         scene_fps_max_text,
+        scene_text,
+        scene_drop_down,
+        time_slider,
+        play_button,
+        loop_button,
+        preset_text,
+        preset_drop_down,
+        save_preset_button,
+        preset_status_text,
         render_text,
         render_scale_slider,
         rays_per_pixel_slider,
         ray_bounce_limit_slider,
+        roulette_depth_slider,
+        radiance_clamp_slider,
+        tiles_button,
+        tile_size_slider,
+        tiles_per_frame_slider,
+        tile_progress_background,
+        tile_progress_bar,
+        tile_progress_text,
         seed_rng_with_time_button,
+        sampler_drop_down,
+        spectral_button,
+        renderer_drop_down,
+        debug_mode_drop_down,
+        accumulate_text,
+        accumulate_button,
+        adaptive_threshold_slider,
+        sample_map_button,
+        denoise_text,
+        denoise_button,
+        denoise_iterations_slider,
+        denoise_phi_color_slider,
+        denoise_phi_normal_slider,
+        denoise_phi_depth_slider,
         camera_text,
         camera_vfov_slider,
         camera_aperture_slider,
         camera_focus_dist_slider,
+        environment_text,
+        environment_button,
+        environment_hint_text,
+        time_of_day_slider,
+        turbidity_slider,
+        env_rotation_slider,
+        env_intensity_slider,
+        display_text,
+        exposure_slider,
+        tonemap_button,
+        aov_drop_down,
+        offline_rays_per_pixel_slider,
+        offline_scale_slider,
+        render_offline_button,
+        screenshot_button,
+        record_fps_slider,
+        record_duration_slider,
+        record_ffmpeg_button,
+        record_button,
+        offline_status_text,
     }
 }
 
+/// Requests from the user interface that are handled by the app.
+pub enum Action {
+    /// Render the current view at full resolution, or a multiple of it, and write it to disk.
+    /// Cancels the render in progress, if any.
+    RenderOffline,
+    /// Write the scene window's contents to disk.
+    Screenshot,
+    /// Record the scene's animation to an image sequence at a fixed frame rate. Cancels the
+    /// recording in progress, if any.
+    Record,
+    /// Switch to the scene in the given file, resetting the camera.
+    LoadScene(PathBuf),
+    /// Write the current settings to a new preset.
+    SavePreset,
+    /// Apply the preset in the given file, switching to its scene.
+    LoadPreset(PathBuf),
+}
+
 /// Update the user interface.
+#[allow(clippy::too_many_arguments)]
 pub fn update(
     ref mut ui: UiCell,
     ids: &Ids,
     scene_fps: &Fps,
+    env_map_name: Option<&str>,
+    offline_status: Option<&str>,
+    offline_progress: Option<f32>,
+    record_progress: Option<(u32, u32)>,
+    shader_status: Option<&str>,
+    scene_status: Option<&str>,
+    scene_path: &Path,
+    presets: &[PathBuf],
+    preset_status: Option<&str>,
+    tile_progress: f32,
+    scene_duration: Option<f32>,
+    playback: &mut timeline::Playback,
     config: &mut Config,
     push_constants: &mut ShaderConstants,
-) {
+) -> Vec<Action> {
+    let mut actions = vec![];
+
     widget::Canvas::new()
         .border(0.0)
         .rgb(0.1, 0.1, 0.1)
         .pad(PAD)
+        .scroll_kids_vertically()
         .set(ids.background, ui);
 
+    widget::Scrollbar::y_axis(ids.background)
+        .auto_hide(true)
+        .set(ids.scrollbar, ui);
+
     // Title
 
     text("NANNOU RAYTRACER")
         .mid_top_of(ids.background)
         .set(ids.title_text, ui);
 
+    // Shader rebuilds in progress or failed. The last good shader keeps rendering meanwhile.
+    if let Some(status) = shader_status {
+        widget::Text::new(status)
+            .mid_left_of(ids.background)
+            .down(PAD)
+            .w(COL_W)
+            .font_size(LABEL_FONT_SIZE)
+            .color(color::LIGHT_RED)
+            .set(ids.shader_status_text, ui);
+    }
+
+    // Scene files that failed to reload. The last good scene keeps rendering meanwhile.
+    if let Some(status) = scene_status {
+        widget::Text::new(status)
+            .mid_left_of(ids.background)
+            .down(PAD)
+            .w(COL_W)
+            .font_size(LABEL_FONT_SIZE)
+            .color(color::LIGHT_RED)
+            .set(ids.scene_status_text, ui);
+    }
+
     // Scene FPS
 
     fn fps_to_rgb(fps: f64) -> (f32, f32, f32) {
@@ -91,6 +206,123 @@ pub fn update(
         .rgb(r, g, b)
         .set(ids.scene_fps_max_text, ui);
 
+    // Scene
+
+    widget::Text::new("Scene")
+        .mid_left_of(ids.background)
+        .down(PAD * 1.5)
+        .font_size(16)
+        .color(color::WHITE)
+        .set(ids.scene_text, ui);
+
+    // The scenes of the library, followed by the loaded scene if it's from elsewhere. Selecting
+    // the loaded scene again reloads it.
+    let mut scenes: Vec<String> = scene::LIBRARY
+        .iter()
+        .map(|(name, _)| format!("Scene: {}", name))
+        .collect();
+    let library_index = scene::LIBRARY
+        .iter()
+        .position(|(_, file)| scene::library_path(file) == scene_path);
+    let selected = library_index.unwrap_or_else(|| {
+        let name = scene_path.file_name().unwrap_or_default().to_string_lossy();
+        scenes.push(format!("Scene: {}", name));
+        scenes.len() - 1
+    });
+    let scenes: Vec<&str> = scenes.iter().map(String::as_str).collect();
+    for selected in drop_down(&scenes, Some(selected))
+        .down(PAD)
+        .set(ids.scene_drop_down, ui)
+    {
+        let path = match scene::LIBRARY.get(selected) {
+            Some((_, file)) => scene::library_path(file),
+            None => scene_path.to_path_buf(),
+        };
+        actions.push(Action::LoadScene(path));
+    }
+
+    // Scrubbing moves the animation while paused, as while playing. Without keyframes the
+    // scrubber grows to follow the time.
+    let (end, label) = match scene_duration {
+        Some(duration) => (duration, format!("Time: {:.2} / {:.2} s", playback.time, duration)),
+        None => (
+            timeline::DEFAULT_DURATION.max(playback.time),
+            format!("Time: {:.2} s", playback.time),
+        ),
+    };
+    for time in slider(playback.time.min(end), 0.0, end)
+        .label(&label)
+        .down(PAD * 0.5)
+        .set(ids.time_slider, ui)
+    {
+        playback.time = time;
+    }
+
+    let label = match playback.playing {
+        true => "Pause",
+        false => "Play",
+    };
+    for _click in button()
+        .label(label)
+        .down(PAD * 0.5)
+        .set(ids.play_button, ui)
+    {
+        match playback.playing {
+            true => playback.playing = false,
+            false => playback.play(scene_duration),
+        }
+    }
+
+    let (label, color) = match playback.looping {
+        true => ("ON", ui::color::BLUE),
+        false => ("OFF", ui::color::DARK_CHARCOAL),
+    };
+    let label = format!("Loop: {}", label);
+    for _click in button()
+        .label(&label)
+        .color(color)
+        .down(PAD * 0.5)
+        .set(ids.loop_button, ui)
+    {
+        playback.looping = !playback.looping;
+    }
+
+    // Presets
+
+    widget::Text::new("Presets")
+        .mid_left_of(ids.background)
+        .down(PAD * 1.5)
+        .font_size(16)
+        .color(color::WHITE)
+        .set(ids.preset_text, ui);
+
+    let names: Vec<String> = presets
+        .iter()
+        .map(|path| path.file_stem().unwrap_or_default().to_string_lossy().into_owned())
+        .collect();
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    for selected in drop_down(&names, None)
+        .label("Load preset")
+        .down(PAD)
+        .set(ids.preset_drop_down, ui)
+    {
+        actions.push(Action::LoadPreset(presets[selected].clone()));
+    }
+
+    for _click in button()
+        .label("Save preset")
+        .down(PAD * 0.5)
+        .set(ids.save_preset_button, ui)
+    {
+        actions.push(Action::SavePreset);
+    }
+
+    text(preset_status.unwrap_or("Saves the settings and scene to ./presets"))
+        .font_size(LABEL_FONT_SIZE)
+        .w(COL_W)
+        .down(PAD * 0.5)
+        .set(ids.preset_status_text, ui);
+
     // Render
 
     widget::Text::new("Render Control")
@@ -135,6 +367,91 @@ pub fn update(
         push_constants.ray_bounce_limit = limit.round() as u32;
     }
 
+    let min = 0.0;
+    let max = 16.0;
+    let label = match push_constants.roulette_depth {
+        0 => "Russian roulette: OFF".to_string(),
+        depth => format!("Russian roulette after: {}", depth),
+    };
+    for depth in slider(push_constants.roulette_depth as f32, min, max)
+        .label(&label)
+        .down(PAD * 0.5)
+        .set(ids.roulette_depth_slider, ui)
+    {
+        push_constants.roulette_depth = depth.round() as u32;
+    }
+
+    let min = 0.0;
+    let max = 100.0;
+    let label = match push_constants.radiance_clamp {
+        c if c > 0.0 => format!("Radiance clamp: {:.1}", c),
+        _ => "Radiance clamp: OFF".to_string(),
+    };
+    for clamp in slider(push_constants.radiance_clamp, min, max)
+        .label(&label)
+        .down(PAD * 0.5)
+        .set(ids.radiance_clamp_slider, ui)
+    {
+        push_constants.radiance_clamp = clamp;
+    }
+
+    let tiles = &mut config.tiles;
+    let (label, color) = match tiles.enabled {
+        true => ("ON", ui::color::BLUE),
+        false => ("OFF", ui::color::DARK_CHARCOAL),
+    };
+    let label = format!("Tiled: {}", label);
+    for _click in button()
+        .label(&label)
+        .color(color)
+        .down(PAD * 0.5)
+        .set(ids.tiles_button, ui)
+    {
+        tiles.enabled = !tiles.enabled;
+    }
+
+    let min = 16.0;
+    let max = 512.0;
+    let label = format!("Tile size: {} px", tiles.tile_size);
+    for value in slider(tiles.tile_size as f32, min, max)
+        .label(&label)
+        .down(PAD * 0.5)
+        .skew(2.0)
+        .set(ids.tile_size_slider, ui)
+    {
+        tiles.tile_size = value.round() as u32;
+    }
+
+    let min = 1.0;
+    let max = 64.0;
+    let label = format!("Tiles per frame: {}", tiles.tiles_per_frame);
+    for value in slider(tiles.tiles_per_frame as f32, min, max)
+        .label(&label)
+        .down(PAD * 0.5)
+        .skew(2.0)
+        .set(ids.tiles_per_frame_slider, ui)
+    {
+        tiles.tiles_per_frame = value.round() as u32;
+    }
+
+    let bar_h = DEFAULT_WIDGET_H * 0.25;
+    widget::Rectangle::fill([COL_W, bar_h])
+        .color(color::DARK_CHARCOAL)
+        .down(PAD * 0.5)
+        .set(ids.tile_progress_background, ui);
+
+    let bar_w = (COL_W * tile_progress as Scalar).max(1.0);
+    widget::Rectangle::fill([bar_w, bar_h])
+        .color(ui::color::BLUE)
+        .top_left_of(ids.tile_progress_background)
+        .set(ids.tile_progress_bar, ui);
+
+    let label = format!("Image progress: {:.0}%", tile_progress * 100.0);
+    text(&label)
+        .font_size(LABEL_FONT_SIZE)
+        .down_from(ids.tile_progress_background, PAD * 0.5)
+        .set(ids.tile_progress_text, ui);
+
     let (label, color) = match config.seed_rng_with_time {
         true => ("ON", ui::color::BLUE),
         false => ("OFF", ui::color::DARK_CHARCOAL),
@@ -149,6 +466,190 @@ pub fn update(
         config.seed_rng_with_time = !config.seed_rng_with_time;
     }
 
+    // Indexed by the `shared::sampler` constants.
+    let samplers = [
+        "Sampler: PCG",
+        "Sampler: Sobol",
+        "Sampler: Halton",
+        "Sampler: Blue noise",
+    ];
+    for selected in drop_down(&samplers, Some(push_constants.sampler as usize))
+        .down(PAD * 0.5)
+        .set(ids.sampler_drop_down, ui)
+    {
+        push_constants.sampler = selected as u32;
+    }
+
+    let (label, color) = match push_constants.spectral != 0 {
+        true => ("ON", ui::color::BLUE),
+        false => ("OFF", ui::color::DARK_CHARCOAL),
+    };
+    let label = format!("Spectral: {}", label);
+    for _click in button()
+        .label(&label)
+        .color(color)
+        .down(PAD * 0.5)
+        .set(ids.spectral_button, ui)
+    {
+        push_constants.spectral = if push_constants.spectral != 0 { 0 } else { 1 };
+    }
+
+    // Ordered as `Renderer::ALL`.
+    let renderers = [
+        "Renderer: Fragment shader",
+        "Renderer: Compute shader",
+        "Renderer: Wavefront",
+    ];
+    for selected in drop_down(&renderers, Some(config.renderer as usize))
+        .down(PAD * 0.5)
+        .set(ids.renderer_drop_down, ui)
+    {
+        config.renderer = Renderer::ALL[selected];
+    }
+
+    // Indexed by the `shared::debug` constants.
+    let debug_modes = [
+        "Debug: Off",
+        "Debug: Normals",
+        "Debug: Hit distance",
+        "Debug: Bounces",
+        "Debug: Test cost",
+        "Debug: NaN / Inf",
+        "Debug: Variance",
+    ];
+    for selected in drop_down(&debug_modes, Some(push_constants.debug_mode as usize))
+        .down(PAD * 0.5)
+        .set(ids.debug_mode_drop_down, ui)
+    {
+        push_constants.debug_mode = selected as u32;
+    }
+
+    // Accumulation
+
+    widget::Text::new("Accumulation")
+        .mid_left_of(ids.background)
+        .down(PAD * 1.5)
+        .font_size(16)
+        .color(color::WHITE)
+        .set(ids.accumulate_text, ui);
+
+    let frames = push_constants.accumulated_frames + 1;
+    let (label, color) = match push_constants.accumulate != 0 {
+        true => (format!("Accumulate: ON ({} frames)", frames), ui::color::BLUE),
+        false => ("Accumulate: OFF".to_string(), ui::color::DARK_CHARCOAL),
+    };
+    for _click in button()
+        .label(&label)
+        .color(color)
+        .down(PAD)
+        .set(ids.accumulate_button, ui)
+    {
+        push_constants.accumulate = if push_constants.accumulate != 0 { 0 } else { 1 };
+    }
+
+    let min = 0.0;
+    let max = 0.2;
+    let label = match push_constants.adaptive_threshold {
+        t if t > 0.0 => format!("Adaptive error: {:.1}%", t * 100.0),
+        _ => "Adaptive error: OFF".to_string(),
+    };
+    for value in slider(push_constants.adaptive_threshold, min, max)
+        .label(&label)
+        .down(PAD * 0.5)
+        .skew(2.0)
+        .set(ids.adaptive_threshold_slider, ui)
+    {
+        push_constants.adaptive_threshold = value;
+    }
+
+    let (label, color) = match push_constants.aov == shared::aov::SAMPLES {
+        true => ("ON", ui::color::BLUE),
+        false => ("OFF", ui::color::DARK_CHARCOAL),
+    };
+    let label = format!("Sample map: {}", label);
+    for _click in button()
+        .label(&label)
+        .color(color)
+        .down(PAD * 0.5)
+        .set(ids.sample_map_button, ui)
+    {
+        push_constants.aov = match push_constants.aov {
+            shared::aov::SAMPLES => shared::aov::BEAUTY,
+            _ => shared::aov::SAMPLES,
+        };
+    }
+
+    // Denoiser
+
+    widget::Text::new("Denoiser")
+        .mid_left_of(ids.background)
+        .down(PAD * 1.5)
+        .font_size(16)
+        .color(color::WHITE)
+        .set(ids.denoise_text, ui);
+
+    let denoise = &mut config.denoise;
+    let (label, color) = match denoise.enabled {
+        true => ("ON", ui::color::BLUE),
+        false => ("OFF", ui::color::DARK_CHARCOAL),
+    };
+    let label = format!("Denoise: {}", label);
+    for _click in button()
+        .label(&label)
+        .color(color)
+        .down(PAD)
+        .set(ids.denoise_button, ui)
+    {
+        denoise.enabled = !denoise.enabled;
+    }
+
+    let min = 1.0;
+    let max = crate::denoise::MAX_ITERATIONS as f32;
+    let label = format!("Filter iterations: {}", denoise.iterations);
+    for value in slider(denoise.iterations as f32, min, max)
+        .label(&label)
+        .down(PAD * 0.5)
+        .set(ids.denoise_iterations_slider, ui)
+    {
+        denoise.iterations = value.round() as u32;
+    }
+
+    let min = 0.1;
+    let max = 16.0;
+    let label = format!("Colour weight: {:.2}", denoise.phi_color);
+    for value in slider(denoise.phi_color, min, max)
+        .label(&label)
+        .down(PAD * 0.5)
+        .skew(2.0)
+        .set(ids.denoise_phi_color_slider, ui)
+    {
+        denoise.phi_color = value;
+    }
+
+    let min = 1.0;
+    let max = 256.0;
+    let label = format!("Normal weight: {:.0}", denoise.phi_normal);
+    for value in slider(denoise.phi_normal, min, max)
+        .label(&label)
+        .down(PAD * 0.5)
+        .skew(2.0)
+        .set(ids.denoise_phi_normal_slider, ui)
+    {
+        denoise.phi_normal = value;
+    }
+
+    let min = 0.1;
+    let max = 8.0;
+    let label = format!("Depth weight: {:.2}", denoise.phi_depth);
+    for value in slider(denoise.phi_depth, min, max)
+        .label(&label)
+        .down(PAD * 0.5)
+        .skew(2.0)
+        .set(ids.denoise_phi_depth_slider, ui)
+    {
+        denoise.phi_depth = value;
+    }
+
     // Camera
 
     widget::Text::new("Camera")
@@ -182,6 +683,248 @@ pub fn update(
         push_constants.aperture = value;
     }
 
+    let min = 0.1;
+    let max = 20.0;
+    let label = format!("Focus distance: {:.2}", push_constants.focus_dist);
+    for value in slider(push_constants.focus_dist, min, max)
+        .label(&label)
+        .down(PAD * 0.5)
+        .skew(2.0)
+        .set(ids.camera_focus_dist_slider, ui)
+    {
+        push_constants.focus_dist = value;
+    }
+
+    // Environment
+
+    widget::Text::new("Environment")
+        .mid_left_of(ids.background)
+        .down(PAD * 1.5)
+        .font_size(16)
+        .color(color::WHITE)
+        .set(ids.environment_text, ui);
+
+    let label = match push_constants.environment {
+        shared::environment::MAP => format!("Map: {}", env_map_name.unwrap_or("none loaded")),
+        _ => "Sky".to_string(),
+    };
+    for _click in button()
+        .label(&label)
+        .down(PAD)
+        .set(ids.environment_button, ui)
+    {
+        push_constants.environment = match push_constants.environment {
+            shared::environment::MAP => shared::environment::SKY,
+            _ => shared::environment::MAP,
+        };
+    }
+
+    text("Drop an .hdr or .exr onto the scene window")
+        .font_size(LABEL_FONT_SIZE)
+        .down(PAD * 0.5)
+        .set(ids.environment_hint_text, ui);
+
+    let hours = config.time_of_day.floor();
+    let minutes = (config.time_of_day - hours) * 60.0;
+    let label = format!("Time of day: {:02}:{:02}", hours as u32, minutes as u32);
+    for value in slider(config.time_of_day, 0.0, 24.0)
+        .label(&label)
+        .down(PAD * 0.5)
+        .set(ids.time_of_day_slider, ui)
+    {
+        config.time_of_day = value;
+    }
+
+    let min = 1.7;
+    let max = 10.0;
+    let label = format!("Turbidity: {:.2}", push_constants.turbidity);
+    for value in slider(push_constants.turbidity, min, max)
+        .label(&label)
+        .down(PAD * 0.5)
+        .set(ids.turbidity_slider, ui)
+    {
+        push_constants.turbidity = value;
+    }
+
+    let max = 2.0 * pi;
+    let label = format!("Rotation: {:.3} radians", push_constants.env_rotation);
+    for value in slider(push_constants.env_rotation, 0.0, max)
+        .label(&label)
+        .down(PAD * 0.5)
+        .set(ids.env_rotation_slider, ui)
+    {
+        push_constants.env_rotation = value;
+    }
+
+    let min = 0.0;
+    let max = 8.0;
+    let label = format!("Intensity: {:.3}", push_constants.env_intensity);
+    for value in slider(push_constants.env_intensity, min, max)
+        .label(&label)
+        .down(PAD * 0.5)
+        .skew(2.0)
+        .set(ids.env_intensity_slider, ui)
+    {
+        push_constants.env_intensity = value;
+    }
+
+    // Display
+
+    widget::Text::new("Display")
+        .mid_left_of(ids.background)
+        .down(PAD * 1.5)
+        .font_size(16)
+        .color(color::WHITE)
+        .set(ids.display_text, ui);
+
+    let min = -8.0;
+    let max = 8.0;
+    let label = format!("Exposure: {:+.2} EV", push_constants.exposure);
+    for value in slider(push_constants.exposure, min, max)
+        .label(&label)
+        .down(PAD)
+        .set(ids.exposure_slider, ui)
+    {
+        push_constants.exposure = value;
+    }
+
+    let label = match push_constants.tonemap {
+        shared::tonemap::REINHARD => "Tone map: Reinhard",
+        shared::tonemap::ACES_FILMIC => "Tone map: ACES Filmic",
+        shared::tonemap::AGX => "Tone map: AgX",
+        _ => "Tone map: Clamp",
+    };
+    for _click in button()
+        .label(label)
+        .down(PAD * 0.5)
+        .set(ids.tonemap_button, ui)
+    {
+        push_constants.tonemap = match push_constants.tonemap {
+            shared::tonemap::CLAMP => shared::tonemap::REINHARD,
+            shared::tonemap::REINHARD => shared::tonemap::ACES_FILMIC,
+            shared::tonemap::ACES_FILMIC => shared::tonemap::AGX,
+            _ => shared::tonemap::CLAMP,
+        };
+    }
+
+    // Indexed by the `shared::aov` constants.
+    let aovs = [
+        "View: Beauty",
+        "View: Albedo",
+        "View: Normal",
+        "View: Depth",
+        "View: Material",
+        "View: Bounces",
+        "View: Samples",
+    ];
+    for selected in drop_down(&aovs, Some(push_constants.aov as usize))
+        .down(PAD * 0.5)
+        .set(ids.aov_drop_down, ui)
+    {
+        push_constants.aov = selected as u32;
+    }
+
+    let min = 1.0;
+    let max = 1024.0;
+    let label = format!("Offline max rays per pixel: {}", config.offline_rays_per_pixel);
+    for rays in slider(config.offline_rays_per_pixel as f32, min, max)
+        .label(&label)
+        .skew(2.0)
+        .down(PAD * 0.5)
+        .set(ids.offline_rays_per_pixel_slider, ui)
+    {
+        config.offline_rays_per_pixel = rays.round() as u32;
+    }
+
+    let min = 1.0;
+    let max = crate::offline::MAX_SCALE as f32;
+    let label = format!("Offline resolution: {}x window", config.offline_scale);
+    for scale in slider(config.offline_scale as f32, min, max)
+        .label(&label)
+        .down(PAD * 0.5)
+        .set(ids.offline_scale_slider, ui)
+    {
+        config.offline_scale = scale.round() as u32;
+    }
+
+    let label = match offline_progress {
+        Some(progress) => format!("Cancel render: {:.0}%", progress * 100.0),
+        None => "Render to disk".to_string(),
+    };
+    for _click in button()
+        .label(&label)
+        .down(PAD * 0.5)
+        .set(ids.render_offline_button, ui)
+    {
+        actions.push(Action::RenderOffline);
+    }
+
+    for _click in button()
+        .label("Screenshot (F12)")
+        .down(PAD * 0.5)
+        .set(ids.screenshot_button, ui)
+    {
+        actions.push(Action::Screenshot);
+    }
+
+    // Recordings render each frame like the render to disk.
+    let recording = &mut config.recording;
+    let min = 1.0;
+    let max = crate::record::MAX_FPS as f32;
+    let label = format!("Recording frame rate: {} fps", recording.fps);
+    for fps in slider(recording.fps as f32, min, max)
+        .label(&label)
+        .down(PAD * 0.5)
+        .set(ids.record_fps_slider, ui)
+    {
+        recording.fps = fps.round() as u32;
+    }
+
+    let min = 0.1;
+    let max = crate::record::MAX_DURATION;
+    let label = format!("Recording duration: {:.1} s", recording.duration);
+    for duration in slider(recording.duration, min, max)
+        .label(&label)
+        .skew(2.0)
+        .down(PAD * 0.5)
+        .set(ids.record_duration_slider, ui)
+    {
+        recording.duration = duration;
+    }
+
+    let (label, color) = match recording.ffmpeg {
+        true => ("ON", ui::color::BLUE),
+        false => ("OFF", ui::color::DARK_CHARCOAL),
+    };
+    let label = format!("Encode video with ffmpeg: {}", label);
+    for _click in button()
+        .label(&label)
+        .color(color)
+        .down(PAD * 0.5)
+        .set(ids.record_ffmpeg_button, ui)
+    {
+        recording.ffmpeg = !recording.ffmpeg;
+    }
+
+    let label = match record_progress {
+        Some((frame, frames)) => format!("Cancel recording: frame {} of {}", frame + 1, frames),
+        None => format!("Record {} frames", recording.frame_count()),
+    };
+    for _click in button()
+        .label(&label)
+        .down(PAD * 0.5)
+        .set(ids.record_button, ui)
+    {
+        actions.push(Action::Record);
+    }
+
+    text(offline_status.unwrap_or("Writes PNG and EXR to ./renders"))
+        .font_size(LABEL_FONT_SIZE)
+        .w(COL_W)
+        .down(PAD * 0.5)
+        .set(ids.offline_status_text, ui);
+
+    actions
 }
 
 fn text(s: &str) -> widget::Text {
@@ -205,3 +948,15 @@ fn slider(val: f32, min: f32, max: f32) -> widget::Slider<'static, f32> {
         .label_color(color::WHITE)
         .border(0.0)
 }
+
+fn drop_down<'a>(
+    items: &'a [&'a str],
+    selected: Option<usize>,
+) -> widget::DropDownList<'a, &'a str> {
+    widget::DropDownList::new(items, selected)
+        .w_h(COL_W, DEFAULT_WIDGET_H)
+        .label_font_size(LABEL_FONT_SIZE)
+        .color(color::DARK_CHARCOAL)
+        .label_color(color::WHITE)
+        .border(0.0)
+}
@@ -0,0 +1,193 @@
+//! Rebuilding the shader in the background whenever its sources change.
+//!
+//! `SpirvBuilder` leaves cargo to print compile errors to stderr. To show them in the GUI, the
+//! shader is built by running this executable again with `COMPILE_ARG`, capturing its output.
+//!
+//! Only executables built with the `hot-reload` feature, or either rust-gpu feature, rebuild the
+//! shader. Others keep the embedded prebuilt one.
+
+use nannou::wgpu;
+use std::borrow::Cow;
+use std::sync::mpsc;
+#[cfg(feature = "spirv-builder")]
+use {
+    crate::shader_sources,
+    crate::watch::Watcher,
+    spirv_builder::{Capability, MetadataPrintout, SpirvBuilder},
+    std::path::{Path, PathBuf},
+    std::process::Command,
+    std::time::Duration,
+};
+
+/// The argument that makes the executable build the shader and print the module's path.
+#[cfg(feature = "spirv-builder")]
+pub const COMPILE_ARG: &str = "--compile-shader";
+
+/// The argument that makes the executable build the shader like `COMPILE_ARG`, and copy the module
+/// over the prebuilt one along with the fingerprint of its sources.
+#[cfg(feature = "spirv-builder")]
+pub const UPDATE_ARG: &str = "--update-prebuilt-shader";
+
+// How often the shader sources are checked for changes.
+#[cfg(feature = "spirv-builder")]
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// The most lines of compiler output kept for display.
+#[cfg(feature = "spirv-builder")]
+const MAX_ERROR_LINES: usize = 24;
+
+/// Progress of a rebuild of the shader, sent by `spawn`.
+#[cfg_attr(not(feature = "spirv-builder"), allow(dead_code))]
+pub enum Event {
+    /// The sources changed and the shader is being rebuilt.
+    Compiling,
+    /// The rebuilt SPIR-V module.
+    Compiled(Vec<u8>),
+    /// The rebuild failed with the given compiler output.
+    Failed(String),
+}
+
+/// Whether the executable was run with `COMPILE_ARG` or `UPDATE_ARG`.
+#[cfg(feature = "spirv-builder")]
+pub fn compile_requested() -> bool {
+    std::env::args()
+        .skip(1)
+        .any(|arg| arg == COMPILE_ARG || arg == UPDATE_ARG)
+}
+
+/// Build the shader crate, printing the path of the SPIR-V module on success, and with
+/// `UPDATE_ARG` updating the prebuilt module. This is run in a child process by `compile`.
+// Keep in step with `build_module` in `build.rs`, which builds the embedded shader.
+#[cfg(feature = "spirv-builder")]
+pub fn compile_and_exit() -> ! {
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let crate_path = [manifest_dir, "..", "shader"]
+        .iter()
+        .copied()
+        .collect::<PathBuf>();
+    let compile_result = SpirvBuilder::new(crate_path, "spirv-unknown-vulkan1.1")
+        .print_metadata(MetadataPrintout::None)
+        // Seems to be needed to handle conditions within functions?
+        // Error was confusing but adding this worked.
+        .capability(Capability::Int8)
+        .build();
+    match compile_result {
+        Ok(result) => {
+            let module = result.module.unwrap_single();
+            if std::env::args().skip(1).any(|arg| arg == UPDATE_ARG) {
+                if let Err(err) = update_prebuilt(module) {
+                    eprintln!("failed to update the prebuilt shader: {}", err);
+                    std::process::exit(1);
+                }
+            }
+            println!("{}", module.display());
+            std::process::exit(0);
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Copy the module over the prebuilt one in the repository root, recording the sources it was
+// built from for `build.rs` to check.
+#[cfg(feature = "spirv-builder")]
+fn update_prebuilt(module: &Path) -> std::io::Result<()> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+    let prebuilt = root.join(shader_sources::PREBUILT_MODULE);
+    std::fs::copy(module, &prebuilt)?;
+    let fingerprint = shader_sources::fingerprint(&root)?;
+    std::fs::write(shader_sources::stamp_path(&prebuilt), fingerprint + "\n")
+}
+
+/// Build the shader crate and read the SPIR-V module, or return the compiler's errors.
+#[cfg(feature = "spirv-builder")]
+pub fn compile() -> Result<Vec<u8>, String> {
+    let exe = std::env::current_exe()
+        .map_err(|err| format!("failed to find the shader compiler: {}", err))?;
+    let output = Command::new(exe)
+        .arg(COMPILE_ARG)
+        .output()
+        .map_err(|err| format!("failed to run the shader compiler: {}", err))?;
+    if !output.status.success() {
+        return Err(errors(&String::from_utf8_lossy(&output.stderr)));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let module_path = stdout
+        .lines()
+        .last()
+        .ok_or_else(|| "the shader compiler didn't output a module".to_string())?;
+    std::fs::read(module_path.trim())
+        .map_err(|err| format!("failed to read the shader module: {}", err))
+}
+
+/// Watch the `shader` and `shared` crates, rebuilding the shader on a background thread whenever
+/// they change.
+#[cfg(feature = "spirv-builder")]
+pub fn spawn() -> mpsc::Receiver<Event> {
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let paths = vec![manifest_dir.join("../shader"), manifest_dir.join("../shared")];
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut watcher = Watcher::new(paths);
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            if !watcher.changed() {
+                continue;
+            }
+            // Wait for editors to finish writing.
+            while watcher.changed() {
+                std::thread::sleep(POLL_INTERVAL);
+            }
+            if tx.send(Event::Compiling).is_err() {
+                return;
+            }
+            let event = match compile() {
+                Ok(spirv) => Event::Compiled(spirv),
+                Err(errors) => Event::Failed(errors),
+            };
+            if tx.send(event).is_err() {
+                return;
+            }
+        }
+    });
+    rx
+}
+
+/// Without `spirv-builder` the shader can't be rebuilt, so no events are ever sent.
+#[cfg(not(feature = "spirv-builder"))]
+pub fn spawn() -> mpsc::Receiver<Event> {
+    mpsc::channel().1
+}
+
+/// Describe a shader module of the given SPIR-V.
+pub fn module_desc(spirv: &[u8]) -> wgpu::ShaderModuleDescriptor<'static> {
+    let spirv = wgpu::util::make_spirv(spirv);
+    let spirv = match spirv {
+        wgpu::ShaderSource::Wgsl(cow) => wgpu::ShaderSource::Wgsl(Cow::Owned(cow.into_owned())),
+        wgpu::ShaderSource::SpirV(cow) => {
+            wgpu::ShaderSource::SpirV(Cow::Owned(cow.into_owned()))
+        }
+    };
+    wgpu::ShaderModuleDescriptor {
+        label: Some("nannou-raytracer-shader"),
+        source: spirv,
+        flags: wgpu::ShaderFlags::default(),
+    }
+}
+
+// The compiler output from the first error onwards, skipping cargo's progress messages.
+#[cfg(feature = "spirv-builder")]
+fn errors(stderr: &str) -> String {
+    let lines: Vec<_> = stderr
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("Compiling"))
+        .skip_while(|line| !line.starts_with("error"))
+        .take(MAX_ERROR_LINES)
+        .collect();
+    match lines.is_empty() {
+        true => stderr.trim().to_string(),
+        false => lines.join("\n"),
+    }
+}
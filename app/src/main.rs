@@ -1,20 +1,77 @@
 use fps_ticker::Fps;
 use nannou::prelude::*;
 use nannou::ui::prelude::*;
+use serde::{Deserialize, Serialize};
 use shared::ShaderConstants;
-use spirv_builder::{Capability, MetadataPrintout, SpirvBuilder};
-use std::borrow::Cow;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc;
 
+mod accumulate;
+mod benchmark;
+mod capture;
+mod compute;
+mod denoise;
+mod env_map;
+mod gbuffer;
 mod gui;
+mod hot_reload;
+mod offline;
+mod preset;
+mod record;
+mod scene;
+#[cfg(feature = "spirv-builder")]
+mod shader_sources;
+mod tiles;
+mod timeline;
+mod watch;
+mod wavefront;
 mod shaders {
     #[allow(non_upper_case_globals)]
     pub const main_fs: &str = "main_fs";
     #[allow(non_upper_case_globals)]
+    pub const main_cs: &str = "main_cs";
+    #[allow(non_upper_case_globals)]
     pub const main_vs: &str = "main_vs";
+    #[allow(non_upper_case_globals)]
+    pub const post_fs: &str = "post_fs";
+    #[allow(non_upper_case_globals)]
+    pub const accumulate_fs: &str = "accumulate_fs";
+    #[allow(non_upper_case_globals)]
+    pub const denoise_temporal_fs: &str = "denoise_temporal_fs";
+    #[allow(non_upper_case_globals)]
+    pub const denoise_atrous_fs: &str = "denoise_atrous_fs";
+    #[allow(non_upper_case_globals)]
+    pub const wavefront_clear_cs: &str = "wavefront_clear_cs";
+    #[allow(non_upper_case_globals)]
+    pub const wavefront_dispatch_cs: &str = "wavefront_dispatch_cs";
+    #[allow(non_upper_case_globals)]
+    pub const wavefront_generate_cs: &str = "wavefront_generate_cs";
+    #[allow(non_upper_case_globals)]
+    pub const wavefront_extend_cs: &str = "wavefront_extend_cs";
+    #[allow(non_upper_case_globals)]
+    pub const wavefront_shade_lambertian_cs: &str = "wavefront_shade_lambertian_cs";
+    #[allow(non_upper_case_globals)]
+    pub const wavefront_shade_metal_cs: &str = "wavefront_shade_metal_cs";
+    #[allow(non_upper_case_globals)]
+    pub const wavefront_shade_dielectric_cs: &str = "wavefront_shade_dielectric_cs";
+    #[allow(non_upper_case_globals)]
+    pub const wavefront_shade_volume_cs: &str = "wavefront_shade_volume_cs";
+    #[allow(non_upper_case_globals)]
+    pub const wavefront_shadow_cs: &str = "wavefront_shadow_cs";
+    #[allow(non_upper_case_globals)]
+    pub const wavefront_finish_cs: &str = "wavefront_finish_cs";
 }
 
+/// The SPIR-V module chosen by `build.rs`: the prebuilt one, or the shader built from source with
+/// `hot-reload`.
+const SHADER_SPV: &[u8] = include_bytes!(env!("NANNOU_RAYTRACER_SHADER_SPV"));
+
 fn main() {
+    #[cfg(feature = "spirv-builder")]
+    if hot_reload::compile_requested() {
+        hot_reload::compile_and_exit();
+    }
     nannou::app(model).update(update).run();
 }
 
@@ -23,37 +80,129 @@ struct Model {
     scene_window: window::Id,
     push_constants: ShaderConstants,
     shader_mod: wgpu::ShaderModule,
+    scene_bind_group_layout: wgpu::BindGroupLayout,
+    env_map: env_map::Bindings,
+    scene: scene::Scene,
+    // The file `scene` was loaded from.
+    scene_path: PathBuf,
+    // The time of the animation shown, played back or scrubbed in the GUI.
+    playback: timeline::Playback,
+    scene_bindings: scene::Bindings,
     graphics: Graphics,
     config: Config,
     scene_fps: Fps,
     ui: Ui,
     ids: gui::Ids,
+    // The render to disk in progress, if any.
+    offline: Option<offline::Render>,
+    // The recording of the animation in progress, if any.
+    recording: Option<record::Recording>,
+    // Whether a screenshot is waiting for the image being drawn to finish.
+    screenshot_pending: bool,
+    // Status messages from renders, recordings and screenshots being written in the background.
+    offline_status_tx: mpsc::Sender<String>,
+    offline_status_rx: mpsc::Receiver<String>,
+    offline_status: Option<String>,
+    // Rebuilds of the shader, swapped in as they complete.
+    shader_events: mpsc::Receiver<hot_reload::Event>,
+    // The progress or errors of the latest rebuild, if it hasn't succeeded.
+    shader_status: Option<String>,
+    // Reloads of the scene file, swapped in as they parse.
+    scene_watch: scene::Watch,
+    // The errors of the latest reload, if it failed.
+    scene_status: Option<String>,
+    // The presets listed in the GUI, refreshed after saving one.
+    presets: Vec<PathBuf>,
+    // The outcome of the last preset saved or loaded.
+    preset_status: Option<String>,
 }
 
 pub struct Config {
     pub render_scale: f32,
+    pub renderer: Renderer,
     pub seed_rng_with_time: bool,
+    /// Drives the position of the sun in the sky in hours, `0.0..24.0`.
+    pub time_of_day: f32,
+    /// Rays per pixel used when rendering to disk.
+    pub offline_rays_per_pixel: u32,
+    /// The multiple of the window's resolution rendered to disk, up to `offline::MAX_SCALE`.
+    pub offline_scale: u32,
+    pub tiles: tiles::Settings,
+    pub denoise: denoise::Settings,
+    pub recording: record::Settings,
+}
+
+/// The shaders that trace the scene. All write the same radiance and AOVs.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Renderer {
+    /// Draw `main_fs` over each tile.
+    Fragment,
+    /// Dispatch the `main_cs` compute shader over each tile.
+    Compute,
+    /// Dispatch the wavefront kernels over each tile, see `shared::wavefront`.
+    Wavefront,
 }
 
 struct Graphics {
-    pipeline_layout: wgpu::PipelineLayout,
-    pipeline: wgpu::RenderPipeline,
-    // The scaled texture to which the raytraced scene is rendered.
+    pipelines: Rc<Pipelines>,
+    compute_bind_group: wgpu::BindGroup,
+    post_bind_group: wgpu::BindGroup,
+    // The scaled texture to which the raytraced scene is rendered as raw HDR.
     scaled_texture: wgpu::Texture,
-    // Reshapes the scene texture to the swap chain image texture.
+    // Normals, depth and albedo of the scaled texture's primary hits.
+    gbuffer: gbuffer::GBuffer,
+    accumulator: accumulate::Accumulator,
+    // The tiles of the scaled texture to draw each frame.
+    tiler: tiles::Tiler,
+    denoiser: denoise::Denoiser,
+    // The tone mapped and sRGB encoded scaled texture.
+    display_texture: wgpu::Texture,
+    // Reshapes the display texture to the swap chain image texture.
     texture_reshaper: wgpu::TextureReshaper,
 }
 
+/// The pipelines of the shader module and the layouts of their bindings, which don't depend on the
+/// window's size. Renders to disk share them, so they keep drawing with the shader they started
+/// with while the window's `Graphics` are rebuilt.
+struct Pipelines {
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    // Trace the scene into the same targets as `pipeline` when selected in the `Config`.
+    compute: compute::ComputeRenderer,
+    wavefront: wavefront::Wavefront,
+    // Tone maps the raw HDR scene into a displayable texture.
+    post_bind_group_layout: wgpu::BindGroupLayout,
+    post_pipeline: wgpu::RenderPipeline,
+    post_sampler: wgpu::Sampler,
+    // The layout of `main_fs`'s bindings of the accumulation.
+    accum_bind_group_layout: wgpu::BindGroupLayout,
+}
+
+const HDR_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const DISPLAY_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
 const WIN_H: u32 = 640;
 const SCENE_WIN_W: u32 = WIN_H * 21 / 9;
 const WIN_Y: i32 = gui::PAD as i32;
 const SCENE_WIN_X: i32 = gui::WIN_X + gui::WIN_W as i32 + gui::PAD as i32;
 
+impl Renderer {
+    pub const ALL: [Renderer; 3] = [Renderer::Fragment, Renderer::Compute, Renderer::Wavefront];
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             render_scale: 0.5,
+            renderer: Renderer::Fragment,
             seed_rng_with_time: true,
+            time_of_day: 10.0,
+            offline_rays_per_pixel: 64,
+            offline_scale: 1,
+            tiles: Default::default(),
+            denoise: Default::default(),
+            recording: Default::default(),
         }
     }
 }
@@ -72,10 +221,13 @@ fn model(app: &App) -> Model {
         label: Some("nannou-raytracer-device"),
         features: wgpu::Features::PUSH_CONSTANTS,
         limits: wgpu::Limits {
-            max_push_constant_size: 256,
+            max_push_constant_size: shared::layout::MAX_PUSH_CONSTANT_SIZE as u32,
+            max_storage_buffers_per_shader_stage:
+                shared::layout::MAX_STORAGE_BUFFERS_PER_SHADER_STAGE,
             ..Default::default()
         },
     };
+    check_adapter_limits(&device_desc.limits);
 
     let scene_window = app
         .new_window()
@@ -83,6 +235,8 @@ fn model(app: &App) -> Model {
         .device_descriptor(device_desc)
         .size(SCENE_WIN_W, WIN_H)
         .view(view_scene)
+        .key_pressed(key_pressed)
+        .dropped_file(dropped_file)
         .build()
         .unwrap();
 
@@ -107,74 +261,268 @@ fn model(app: &App) -> Model {
         .expect("failed to build `Ui` for GUI window");
     let ids = gui::Ids::new(ui.widget_id_generator());
 
-    // Load the rust-gpu shader.
+    // Load the embedded rust-gpu shader, rebuilding it as its sources change.
     let scene_win = app.window(scene_window).unwrap();
     let device = scene_win.swap_chain_device();
-    let shader_mod_desc = load_shader_module_desc();
-    let shader_mod = device.create_shader_module(&shader_mod_desc);
+    let shader_mod = device.create_shader_module(&hot_reload::module_desc(SHADER_SPV));
+    let shader_events = hot_reload::spawn();
 
-    let scene_fps = Fps::default();
-    let config = Config::default();
-    let push_constants = ShaderConstants {
-        rays_per_pixel: 2,
-        ray_bounce_limit: 8,
-        vfov: core::f32::consts::PI * 0.5,
-        aperture: 0.0,
-        ..Default::default()
+    // Load the preset's settings over the defaults, if one was given.
+    let preset = path_arg("--preset").map(|path| {
+        preset::Preset::load(&path).unwrap_or_else(|err| {
+            eprintln!("{}: {}", path.display(), err);
+            std::process::exit(1);
+        })
+    });
+
+    // Load the environment map if one was given, otherwise bind a placeholder. A map given on the
+    // command line takes precedence over the preset's, which must load for the preset to render
+    // the same image.
+    let queue = scene_win.swap_chain_queue();
+    let env_map = match path_arg("--env-map") {
+        Some(path) => load_env_map(&path),
+        None => preset.as_ref().and_then(preset::Preset::env_map_path).map(|path| {
+            env_map::EnvMap::load(&path).unwrap_or_else(|err| {
+                eprintln!("{}: {}", path.display(), err);
+                std::process::exit(1);
+            })
+        }),
     };
+    let env_map = env_map.unwrap_or_else(env_map::EnvMap::placeholder);
+    let env_map = env_map::Bindings::new(device, queue, &env_map);
+
+    // Load the scene, watching its file for changes. Once running, errors are shown in the GUI.
+    // A scene given on the command line takes precedence over the preset's.
+    let scene_path = path_arg("--scene")
+        .or_else(|| preset.as_ref().and_then(preset::Preset::scene_path))
+        .unwrap_or_else(|| scene::library_path(scene::DEFAULT_SCENE));
+    let mut scene = scene::Scene::load(&scene_path).unwrap_or_else(|err| {
+        eprintln!("{}: {}", scene_path.display(), err);
+        std::process::exit(1);
+    });
+    let scene_watch = scene::spawn(scene_path.clone());
+    let scene_bind_group_layout = scene::bind_group_layout(device);
+    let mut scene_bindings = scene::Bindings::new(device, &scene_bind_group_layout, &env_map);
+
+    let scene_fps = Fps::default();
+    let mut config = Config::default();
+    let mut push_constants = default_push_constants();
+    let mut playback = timeline::Playback::default();
+    if env_map.name.is_some() {
+        push_constants.environment = shared::environment::MAP;
+    }
+    scene.camera.reset(&mut push_constants);
+    if let Some(preset) = &preset {
+        preset.apply(&mut config, &mut push_constants, &mut scene.camera, &mut playback);
+        push_constants.time = playback.time;
+        scene.camera.aim(&mut push_constants);
+    }
     let msaa_samples = scene_win.msaa_samples();
     let format = Frame::TEXTURE_FORMAT;
     let (w_px, h_px) = scene_win.inner_size_pixels();
     let scaled_texture_size = scaled_texture_size([w_px, h_px], config.render_scale);
-    let graphics = create_graphics(device, &shader_mod, format, msaa_samples, scaled_texture_size);
+    let graphics = create_graphics(
+        device,
+        &shader_mod,
+        &scene_bind_group_layout,
+        format,
+        msaa_samples,
+        scaled_texture_size,
+    );
+
+    if benchmark::requested() {
+        benchmark::run(
+            device,
+            queue,
+            &graphics,
+            &env_map,
+            &scene,
+            &mut scene_bindings,
+            push_constants,
+            &config,
+        );
+        std::process::exit(0);
+    }
+
+    let (offline_status_tx, offline_status_rx) = mpsc::channel();
 
     Model {
         gui_window,
         scene_window,
         shader_mod,
+        scene_bind_group_layout,
+        env_map,
+        scene,
+        scene_path,
+        playback,
+        scene_bindings,
         graphics,
         scene_fps,
         config,
         push_constants,
         ui,
         ids,
+        offline: None,
+        recording: None,
+        screenshot_pending: false,
+        offline_status_tx,
+        offline_status_rx,
+        offline_status: None,
+        shader_events,
+        shader_status: None,
+        scene_watch,
+        scene_status: None,
+        presets: preset::list(),
+        preset_status: None,
     }
 }
 
-fn update(app: &App, model: &mut Model, _: Update) {
-    {
+/// The push constants the app starts with, before the scene sets up the camera.
+fn default_push_constants() -> ShaderConstants {
+    ShaderConstants {
+        rays_per_pixel: 2,
+        ray_bounce_limit: 8,
+        roulette_depth: 3,
+        sampler: shared::sampler::SOBOL,
+        environment: shared::environment::SKY,
+        env_intensity: 1.0,
+        turbidity: 2.5,
+        tonemap: shared::tonemap::ACES_FILMIC,
+        ..Default::default()
+    }
+}
+
+fn update(app: &App, model: &mut Model, update: Update) {
+    let scene_duration = model.scene.duration();
+    let actions = {
         let ui = model.ui.set_widgets();
         gui::update(
             ui,
             &model.ids,
             &model.scene_fps,
+            model.env_map.name.as_deref(),
+            model.offline_status.as_deref(),
+            model.offline.as_ref().map(offline::Render::progress),
+            model.recording.as_ref().map(record::Recording::progress),
+            model.shader_status.as_deref(),
+            model.scene_status.as_deref(),
+            &model.scene_path,
+            &model.presets,
+            model.preset_status.as_deref(),
+            model.graphics.tiler.progress(),
+            scene_duration,
+            &mut model.playback,
             &mut model.config,
             &mut model.push_constants,
-        );
+        )
+    };
+
+    // Screenshots wait until the last frame drew the final tiles of the image, which happens
+    // before the graphics might be rebuilt and a new image started below.
+    if model.screenshot_pending && model.graphics.tiler.finished() {
+        model.screenshot_pending = false;
+        screenshot(app, model);
     }
 
-    // Recreate scaled texture and reshaper if scale changed.
+    // Swap in the latest successful rebuild of the shader, keeping the last good one on errors.
     let win = app.window(model.scene_window).unwrap();
+    let mut shader_changed = false;
+    for event in model.shader_events.try_iter() {
+        match event {
+            hot_reload::Event::Compiling => {
+                model.shader_status = Some("Compiling shader...".to_string());
+            }
+            hot_reload::Event::Compiled(spirv) => {
+                let desc = hot_reload::module_desc(&spirv);
+                model.shader_mod = win.swap_chain_device().create_shader_module(&desc);
+                model.shader_status = None;
+                shader_changed = true;
+            }
+            hot_reload::Event::Failed(errors) => {
+                model.shader_status = Some(format!("Shader error:\n{}", errors));
+            }
+        }
+    }
+
+    // Recreate scaled texture and reshaper if scale changed, and the pipelines if the shader did.
+    // The render to disk and recording keep the pipelines they started with.
     let (win_w_px, win_h_px) = win.inner_size_pixels();
     let scaled_texture_size = scaled_texture_size([win_w_px, win_h_px], model.config.render_scale);
-    if scaled_texture_size != model.graphics.scaled_texture.size() {
+    if shader_changed || scaled_texture_size != model.graphics.scaled_texture.size() {
         let device = win.swap_chain_device();
         let msaa_samples = win.msaa_samples();
         let format = Frame::TEXTURE_FORMAT;
-        model.graphics = create_graphics(device, &model.shader_mod, format, msaa_samples, scaled_texture_size);
+        model.graphics = create_graphics(
+            device,
+            &model.shader_mod,
+            &model.scene_bind_group_layout,
+            format,
+            msaa_samples,
+            scaled_texture_size,
+        );
+    }
+
+    // Swap in the latest scene that parsed, keeping the last good one on errors. It's uploaded
+    // along with the next image. Editing the camera resets the GUI's camera controls and any
+    // camera pinned by a preset.
+    for result in model.scene_watch.try_iter() {
+        match result {
+            Ok(mut scene) => {
+                scene.camera.pinned = model.scene.camera.pinned;
+                if scene.camera != model.scene.camera {
+                    scene.camera.pinned = None;
+                    scene.camera.reset(&mut model.push_constants);
+                }
+                model.scene = scene;
+                model.scene_status = None;
+            }
+            Err(err) => model.scene_status = Some(format!("Scene error:\n{}", err)),
+        }
     }
 
     let pc = &mut model.push_constants;
 
-    pc.time = app.time;
-    pc.rng_seed_offset = if model.config.seed_rng_with_time {
-        app.time
-    } else {
-        0.0
-    };
+    // Freeze the animation while accumulating, as moving the scene would discard the
+    // accumulation. Scrubbing still moves it.
+    if pc.accumulate == 0 {
+        let dt = update.since_last.as_secs_f32();
+        model.playback.advance(dt, scene_duration);
+    }
+
+    // Changes take effect once every tile of the image being drawn is done.
+    let new_image = model.graphics.tiler.finished();
+    if new_image {
+        pc.time = model.playback.time;
+        model.scene.camera.aim(pc);
+        if model.config.seed_rng_with_time {
+            pc.frame = pc.frame.wrapping_add(1);
+        }
+    }
 
     let [w_px, h_px] = model.graphics.scaled_texture.size();
     pc.view_size_pixels = [w_px, h_px];
+    pc.env_map_size = model.env_map.size;
+    let (sun_elevation, sun_azimuth) = shared::sky::sun_position(model.config.time_of_day);
+    pc.sun_elevation = sun_elevation;
+    pc.sun_azimuth = sun_azimuth;
+
+    if new_image {
+        // Write the scene buffers whose contents changed, e.g. after a reload, discarding the
+        // accumulation of the old scene.
+        pc.sphere_count = model.scene.spheres.len() as u32;
+        let queue = win.swap_chain_queue();
+        if model.scene_bindings.upload(queue, &model.scene, pc.time) {
+            model.graphics.accumulator.reset();
+        }
+        model.graphics.accumulator.update(pc);
+        if denoise_active(&model.config, pc) {
+            model.graphics.denoiser.update(&model.config.denoise, pc);
+        } else {
+            model.graphics.denoiser.reset();
+        }
+        model.graphics.tiler.start(&model.config.tiles, *pc);
+    }
+    model.graphics.tiler.advance(&model.config.tiles);
 
     let win = app.window(model.scene_window).unwrap();
     let win_rect = win.rect();
@@ -182,6 +530,201 @@ fn update(app: &App, model: &mut Model, _: Update) {
     let mouse_x = map_range(m.x, win_rect.left(), win_rect.right(), 0.0, w_px as f32);
     let mouse_y = map_range(m.y, win_rect.top(), win_rect.bottom(), 0.0, h_px as f32);
     pc.mouse_pixels = [mouse_x, mouse_y];
+
+    for action in actions {
+        match action {
+            gui::Action::RenderOffline if model.offline.is_some() => {
+                model.offline = None;
+                model.offline_status = Some("Render cancelled".to_string());
+            }
+            gui::Action::RenderOffline => {
+                let (w_px, h_px) = win.inner_size_pixels();
+                let preset = current_preset(model).to_toml().unwrap_or_default();
+                let render = offline::Render::new(
+                    win.swap_chain_device(),
+                    win.swap_chain_queue(),
+                    &model.shader_mod,
+                    &model.graphics,
+                    &model.scene_bind_group_layout,
+                    &model.env_map,
+                    &model.scene,
+                    model.push_constants,
+                    &model.config,
+                    [w_px, h_px],
+                    preset,
+                );
+                model.offline = Some(render);
+                model.offline_status = Some("Rendering...".to_string());
+            }
+            // The writer reports the frames saved once the recording is dropped.
+            gui::Action::Record if model.recording.is_some() => {
+                model.recording = None;
+                model.offline_status = Some("Recording cancelled".to_string());
+            }
+            gui::Action::Record => {
+                let (w_px, h_px) = win.inner_size_pixels();
+                let preset = current_preset(model).to_toml().unwrap_or_default();
+                let recording = record::Recording::new(
+                    win.swap_chain_device(),
+                    win.swap_chain_queue(),
+                    &model.shader_mod,
+                    &model.graphics,
+                    &model.scene_bind_group_layout,
+                    &model.env_map,
+                    &model.scene,
+                    model.push_constants,
+                    &model.config,
+                    [w_px, h_px],
+                    preset,
+                    model.offline_status_tx.clone(),
+                );
+                model.recording = Some(recording);
+                model.offline_status = Some("Recording...".to_string());
+            }
+            gui::Action::Screenshot => request_screenshot(model),
+            gui::Action::LoadScene(path) => {
+                load_scene(model, path);
+            }
+            gui::Action::SavePreset => {
+                model.preset_status = Some(match current_preset(model).save() {
+                    Ok(path) => format!("Saved {}", path.display()),
+                    Err(err) => format!("Preset error: {}", err),
+                });
+                model.presets = preset::list();
+            }
+            gui::Action::LoadPreset(path) => {
+                model.preset_status = Some(match load_preset(app, model, &path) {
+                    Ok(()) => format!("Loaded {}", path.display()),
+                    Err(err) => format!("Preset error: {}", err),
+                });
+            }
+        }
+    }
+
+    // Draw the next batches of the render to disk and recording alongside the window's frame.
+    if let Some(render) = &mut model.offline {
+        let device = win.swap_chain_device();
+        let queue = win.swap_chain_queue();
+        if render.advance(device, queue, &model.offline_status_tx) {
+            model.offline = None;
+            model.offline_status = Some("Saving...".to_string());
+        }
+    }
+    if let Some(recording) = &mut model.recording {
+        let device = win.swap_chain_device();
+        let queue = win.swap_chain_queue();
+        if recording.advance(device, queue) {
+            model.recording = None;
+            model.offline_status = Some("Saving...".to_string());
+        }
+    }
+
+    // Offline renders wait on buffer mapping, which requires polling the device.
+    win.swap_chain_device().poll(wgpu::Maintain::Poll);
+    for status in model.offline_status_rx.try_iter() {
+        model.offline_status = Some(status);
+    }
+}
+
+// Take screenshots with F12.
+fn key_pressed(_app: &App, model: &mut Model, key: Key) {
+    if key == Key::F12 {
+        request_screenshot(model);
+    }
+}
+
+// Take a screenshot once every tile of the image being drawn is done, rather than saving a
+// partly drawn image.
+fn request_screenshot(model: &mut Model) {
+    model.screenshot_pending = true;
+    model.offline_status = Some("Saving...".to_string());
+}
+
+// Write what the scene window shows to disk.
+fn screenshot(app: &App, model: &mut Model) {
+    let win = app.window(model.scene_window).unwrap();
+    let preset = current_preset(model).to_toml().unwrap_or_default();
+    offline::screenshot(
+        win.swap_chain_device(),
+        win.swap_chain_queue(),
+        &model.graphics,
+        &model.push_constants,
+        preset,
+        model.offline_status_tx.clone(),
+    );
+    model.offline_status = Some("Saving...".to_string());
+}
+
+// The preset of the current settings, scene and environment map.
+fn current_preset(model: &Model) -> preset::Preset {
+    let scene_path = Some(model.scene_path.as_path());
+    let env_map_path = model.env_map.path.as_deref();
+    preset::Preset::capture(&model.config, &model.push_constants, scene_path, env_map_path)
+}
+
+// Switch scenes, watching the new file in place of the last. The scene is uploaded along with the
+// next image. If the new scene fails to load, the last stays loaded and watched, and this returns
+// `false`.
+fn load_scene(model: &mut Model, path: PathBuf) -> bool {
+    let scene = match scene::Scene::load(&path) {
+        Ok(scene) => scene,
+        Err(err) => {
+            model.scene_status = Some(format!("Scene error:\n{}: {}", path.display(), err));
+            return false;
+        }
+    };
+    scene.camera.reset(&mut model.push_constants);
+    model.scene = scene;
+    model.scene_status = None;
+    model.graphics.accumulator.reset();
+    model.graphics.denoiser.reset();
+    model.scene_watch = scene::spawn(path.clone());
+    model.scene_path = path;
+    true
+}
+
+// Switch to the preset's scene and environment map, then apply its settings. The scene is loaded
+// first, as loading a scene resets the camera. If either file fails to load, the settings are
+// left alone rather than applied to a different image.
+fn load_preset(app: &App, model: &mut Model, path: &Path) -> Result<(), String> {
+    let preset = preset::Preset::load(path).map_err(|err| err.to_string())?;
+    let env_map = match preset.env_map_path() {
+        Some(env_map_path) => match env_map::EnvMap::load(&env_map_path) {
+            Ok(env_map) => Some(env_map),
+            Err(err) => return Err(format!("{}: {}", env_map_path.display(), err)),
+        },
+        None => None,
+    };
+    if let Some(scene_path) = preset.scene_path() {
+        if !load_scene(model, scene_path) {
+            return Err(format!("failed to load the scene of {}", path.display()));
+        }
+    }
+    if let Some(env_map) = &env_map {
+        set_env_map(app, model, env_map);
+    }
+    let (config, pc) = (&mut model.config, &mut model.push_constants);
+    preset.apply(config, pc, &mut model.scene.camera, &mut model.playback);
+    Ok(())
+}
+
+// Load environment maps dropped onto the scene window.
+fn dropped_file(app: &App, model: &mut Model, path: PathBuf) {
+    if let Some(env_map) = load_env_map(&path) {
+        set_env_map(app, model, &env_map);
+    }
+}
+
+// Light the scene with the given environment map.
+fn set_env_map(app: &App, model: &mut Model, env_map: &env_map::EnvMap) {
+    let win = app.window(model.scene_window).unwrap();
+    let device = win.swap_chain_device();
+    let queue = win.swap_chain_queue();
+    model.env_map = env_map::Bindings::new(device, queue, env_map);
+    let layout = &model.scene_bind_group_layout;
+    model.scene_bindings.set_env_map(device, layout, &model.env_map);
+    model.push_constants.environment = shared::environment::MAP;
+    model.graphics.accumulator.reset();
 }
 
 fn view_ui(app: &App, model: &Model, frame: Frame) {
@@ -195,22 +738,44 @@ fn view_ui(app: &App, model: &Model, frame: Frame) {
 fn view_scene(_app: &App, model: &Model, frame: Frame) {
     frame.clear(BLACK);
 
-    // Encode the commands for rendering to the scaled texture.
+    // Encode the commands for rendering this frame's tiles to the scaled texture, then tone
+    // mapping it.
     let mut encoder = frame.command_encoder();
-    {
-        let texture_view = model.graphics.scaled_texture.view().build();
-        let mut render_pass = wgpu::RenderPassBuilder::new()
-            .color_attachment(&texture_view, |color| color.load_op(wgpu::LoadOp::Load))
-            .begin(&mut encoder);
-        render_pass.set_pipeline(&model.graphics.pipeline);
-        let pc_bytes = unsafe { any_as_u8_slice(&model.push_constants) };
-        render_pass.set_push_constants(wgpu::ShaderStage::all(), 0, pc_bytes);
-        let vertex_range = 0..3;
-        let instance_range = 0..1;
-        render_pass.draw(vertex_range, instance_range);
+    let graphics = &model.graphics;
+    let pc = graphics.tiler.constants();
+    let scaled_view = graphics.scaled_texture.view().build();
+    let gbuffer_views = graphics.gbuffer.views();
+    let scene_bind_group = &model.scene_bindings.bind_group;
+    let accum_bind_group = graphics.accumulator.scene_bind_group();
+    let tiles = graphics.tiler.frame_tiles();
+    encode_scene(
+        &mut encoder,
+        &graphics.pipelines,
+        model.config.renderer,
+        scene_bind_group,
+        accum_bind_group,
+        &graphics.compute_bind_group,
+        pc,
+        &tiles,
+        &scaled_view,
+        &gbuffer_views,
+    );
+    // The image is only accumulated and denoised once all of its tiles are drawn.
+    if graphics.tiler.finished() {
+        if pc.accumulate != 0 {
+            graphics.accumulator.encode(&mut encoder, pc);
+        }
+        if denoise_active(&model.config, pc) {
+            let denoise = &model.config.denoise;
+            graphics.denoiser.encode(&mut encoder, denoise, &scaled_view, &graphics.gbuffer);
+        }
     }
+    let display_view = graphics.display_texture.view().build();
+    let pc = &model.push_constants;
+    let post_bind_group = &graphics.post_bind_group;
+    encode_post_pass(&mut encoder, &graphics.pipelines, post_bind_group, pc, &display_view);
 
-    // Draw the scaled texture to the frame.
+    // Draw the display texture to the frame.
     model
         .graphics
         .texture_reshaper
@@ -219,6 +784,164 @@ fn view_scene(_app: &App, model: &Model, frame: Frame) {
     model.scene_fps.tick();
 }
 
+// Debug modes are displayed as they are written by `main_fs`, and the accumulation converges
+// without the denoiser's help.
+fn denoise_active(config: &Config, push_constants: &ShaderConstants) -> bool {
+    config.denoise.enabled
+        && push_constants.debug_mode == shared::debug::OFF
+        && push_constants.accumulate == 0
+}
+
+/// Ray trace the given `tiles` of the scene with the `renderer`, leaving the rest of the textures
+/// as they are.
+///
+/// The compute renderers write the textures bound by `compute_bind_group`, while `main_fs` draws
+/// to the `target` and `gbuffer` views of the same textures. `accum_bind_group` binds the
+/// accumulation, whose converged pixels are skipped while accumulating.
+#[allow(clippy::too_many_arguments)]
+fn encode_scene(
+    encoder: &mut wgpu::CommandEncoder,
+    pipelines: &Pipelines,
+    renderer: Renderer,
+    scene_bind_group: &wgpu::BindGroup,
+    accum_bind_group: &wgpu::BindGroup,
+    compute_bind_group: &wgpu::BindGroup,
+    push_constants: &ShaderConstants,
+    tiles: &[tiles::Tile],
+    target: &wgpu::TextureView,
+    gbuffer: &gbuffer::Views,
+) {
+    match renderer {
+        Renderer::Fragment => encode_scene_pass(
+            encoder,
+            pipelines,
+            scene_bind_group,
+            accum_bind_group,
+            push_constants,
+            tiles,
+            target,
+            gbuffer,
+        ),
+        // The wavefront kernels don't gather the statistics shown by the debug modes.
+        Renderer::Wavefront if push_constants.debug_mode == shared::debug::OFF => pipelines.wavefront.encode(
+            encoder,
+            scene_bind_group,
+            accum_bind_group,
+            compute_bind_group,
+            push_constants,
+            tiles,
+        ),
+        Renderer::Compute | Renderer::Wavefront => pipelines.compute.encode(
+            encoder,
+            scene_bind_group,
+            accum_bind_group,
+            compute_bind_group,
+            push_constants,
+            tiles,
+        ),
+    }
+}
+
+// Draw `main_fs` over the given `tiles` of the HDR `target` and its guide buffers.
+#[allow(clippy::too_many_arguments)]
+fn encode_scene_pass(
+    encoder: &mut wgpu::CommandEncoder,
+    pipelines: &Pipelines,
+    scene_bind_group: &wgpu::BindGroup,
+    accum_bind_group: &wgpu::BindGroup,
+    push_constants: &ShaderConstants,
+    tiles: &[tiles::Tile],
+    target: &wgpu::TextureView,
+    gbuffer: &gbuffer::Views,
+) {
+    let mut render_pass = wgpu::RenderPassBuilder::new()
+        .color_attachment(target, |color| color.load_op(wgpu::LoadOp::Load))
+        .color_attachment(&gbuffer.normal_depth, |color| color.load_op(wgpu::LoadOp::Load))
+        .color_attachment(&gbuffer.albedo, |color| color.load_op(wgpu::LoadOp::Load))
+        .color_attachment(&gbuffer.material, |color| color.load_op(wgpu::LoadOp::Load))
+        .begin(encoder);
+    render_pass.set_pipeline(&pipelines.pipeline);
+    render_pass.set_bind_group(0, scene_bind_group, &[]);
+    render_pass.set_bind_group(1, accum_bind_group, &[]);
+    let pc_bytes = bytemuck::bytes_of(push_constants);
+    render_pass.set_push_constants(wgpu::ShaderStage::all(), 0, pc_bytes);
+    for tile in tiles {
+        render_pass.set_scissor_rect(tile.x, tile.y, tile.w, tile.h);
+        let vertex_range = 0..3;
+        let instance_range = 0..1;
+        render_pass.draw(vertex_range, instance_range);
+    }
+}
+
+/// Apply exposure and tone mapping to the HDR texture bound by `post_bind_group`, writing the
+/// result to the `DISPLAY_TEXTURE_FORMAT` `target`.
+fn encode_post_pass(
+    encoder: &mut wgpu::CommandEncoder,
+    pipelines: &Pipelines,
+    post_bind_group: &wgpu::BindGroup,
+    push_constants: &ShaderConstants,
+    target: &wgpu::TextureView,
+) {
+    let mut render_pass = wgpu::RenderPassBuilder::new()
+        .color_attachment(target, |color| color.load_op(wgpu::LoadOp::Load))
+        .begin(encoder);
+    render_pass.set_pipeline(&pipelines.post_pipeline);
+    render_pass.set_bind_group(0, post_bind_group, &[]);
+    let pc_bytes = bytemuck::bytes_of(push_constants);
+    render_pass.set_push_constants(wgpu::ShaderStage::all(), 0, pc_bytes);
+    render_pass.draw(0..3, 0..1);
+}
+
+/// Bind an HDR texture, its AOVs and the accumulation as the input to the post-process pass.
+///
+/// `sampler` must use nearest filtering as the AOV textures are not filterable.
+fn post_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    hdr_texture: &wgpu::Texture,
+    gbuffer: &gbuffer::GBuffer,
+    accumulation: &wgpu::Texture,
+) -> wgpu::BindGroup {
+    let texture_view = hdr_texture.view().build();
+    let gbuffer_views = gbuffer.views();
+    let accumulation_view = accumulation.view().build();
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("nannou-raytracer-post-bind-group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&gbuffer_views.normal_depth),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&gbuffer_views.albedo),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&gbuffer_views.material),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::TextureView(&accumulation_view),
+            },
+        ],
+    })
+}
+
 fn scaled_texture_size(win_size_px: [u32; 2], scale: f32) -> [u32; 2] {
     let [w, h] = win_size_px;
     [(w as f32 * scale) as u32, (h as f32 * scale) as u32]
@@ -227,19 +950,26 @@ fn scaled_texture_size(win_size_px: [u32; 2], scale: f32) -> [u32; 2] {
 fn create_graphics(
     device: &wgpu::Device,
     shader_mod: &wgpu::ShaderModule,
+    scene_bind_group_layout: &wgpu::BindGroupLayout,
     dst_format: wgpu::TextureFormat,
     sample_count: u32,
     scaled_texture_size: [u32; 2],
 ) -> Graphics {
     let scaled_texture_sample_count = 1;
-    let scaled_texture_format = wgpu::TextureFormat::Rgba16Float;
+    let scaled_texture_format = HDR_TEXTURE_FORMAT;
 
     // Create our custom texture.
     let scaled_texture = wgpu::TextureBuilder::new()
         .size(scaled_texture_size)
-        // Our texture will be used as the RENDER_ATTACHMENT for our `Draw` render pass.
-        // It will also be SAMPLED by the `TextureCapturer` and `TextureResizer`.
-        .usage(wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED)
+        // Our texture will be used as the RENDER_ATTACHMENT for our `Draw` render pass, or as
+        // STORAGE by the compute path. It will also be SAMPLED by the post-process pass, and
+        // copied from by screenshots.
+        .usage(
+            wgpu::TextureUsage::RENDER_ATTACHMENT
+                | wgpu::TextureUsage::STORAGE
+                | wgpu::TextureUsage::SAMPLED
+                | wgpu::TextureUsage::COPY_SRC,
+        )
         // Use nannou's default multisampling sample count.
         .sample_count(scaled_texture_sample_count)
         // Use a spacious 16-bit linear sRGBA format suitable for high quality drawing.
@@ -247,9 +977,21 @@ fn create_graphics(
         // Build it!
         .build(device);
 
+    // The tone mapped texture, SAMPLED by the `TextureReshaper` and copied from by screenshots.
+    let display_texture = wgpu::TextureBuilder::new()
+        .size(scaled_texture_size)
+        .usage(
+            wgpu::TextureUsage::RENDER_ATTACHMENT
+                | wgpu::TextureUsage::SAMPLED
+                | wgpu::TextureUsage::COPY_SRC,
+        )
+        .sample_count(scaled_texture_sample_count)
+        .format(DISPLAY_TEXTURE_FORMAT)
+        .build(device);
+
     // Create the texture reshaper.
-    let texture_view = scaled_texture.view().build();
-    let texture_sample_type = scaled_texture.sample_type();
+    let texture_view = display_texture.view().build();
+    let texture_sample_type = display_texture.sample_type();
     let texture_reshaper = wgpu::TextureReshaper::new(
         device,
         &texture_view,
@@ -259,63 +1001,299 @@ fn create_graphics(
         dst_format,
     );
 
+    let accum_bind_group_layout = texture_bind_group_layout(device, 2);
     let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
         label: Some("nannou-raytracer-pipeline-layout"),
-        bind_group_layouts: &[],
+        bind_group_layouts: &[scene_bind_group_layout, &accum_bind_group_layout],
         push_constant_ranges: &[wgpu::PushConstantRange {
             stages: wgpu::ShaderStage::all(),
             range: 0..std::mem::size_of::<ShaderConstants>() as u32,
         }],
     });
 
-    let pipeline = wgpu::RenderPipelineBuilder::from_layout(&pipeline_layout, &shader_mod)
+    let color_target = wgpu::ColorTargetState {
+        format: scaled_texture_format,
+        blend: Some(wgpu::BlendState {
+            color: wgpu::BlendComponent::OVER,
+            alpha: wgpu::BlendComponent::REPLACE,
+        }),
+        write_mask: wgpu::ColorWrite::ALL,
+    };
+    let [normal_depth_target, albedo_target, material_target] = gbuffer::GBuffer::color_targets();
+    let pipeline = fullscreen_pipeline(
+        device,
+        &pipeline_layout,
+        shader_mod,
+        shaders::main_fs,
+        &[color_target, normal_depth_target, albedo_target, material_target],
+    );
+
+    let gbuffer_usage = wgpu::TextureUsage::COPY_SRC | wgpu::TextureUsage::STORAGE;
+    let gbuffer = gbuffer::GBuffer::new(device, scaled_texture_size, gbuffer_usage);
+    let compute = compute::ComputeRenderer::new(
+        device,
+        shader_mod,
+        scene_bind_group_layout,
+        &accum_bind_group_layout,
+    );
+    let compute_bind_group = compute.bind_group(device, &scaled_texture, &gbuffer);
+    let wavefront = wavefront::Wavefront::new(
+        device,
+        shader_mod,
+        scene_bind_group_layout,
+        &accum_bind_group_layout,
+        &compute,
+    );
+    let accumulator = accumulate::Accumulator::new(
+        device,
+        shader_mod,
+        &accum_bind_group_layout,
+        scaled_texture_size,
+        &scaled_texture,
+    );
+    let denoiser = denoise::Denoiser::new(
+        device,
+        shader_mod,
+        scaled_texture_size,
+        &scaled_texture,
+        &gbuffer,
+    );
+
+    // The post-process pass reads the HDR texture or an AOV and writes the display texture.
+    let texture_entry = |binding, filterable| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStage::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    };
+    let sampler_entry = |binding, filtering| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStage::FRAGMENT,
+        ty: wgpu::BindingType::Sampler {
+            filtering,
+            comparison: false,
+        },
+        count: None,
+    };
+    let post_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("nannou-raytracer-post-bind-group-layout"),
+        entries: &[
+            texture_entry(0, true),
+            sampler_entry(1, true),
+            texture_entry(2, false),
+            texture_entry(3, false),
+            texture_entry(4, false),
+            sampler_entry(5, false),
+            texture_entry(6, false),
+        ],
+    });
+    // Defaults to nearest filtering, as required for the AOVs.
+    let post_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("nannou-raytracer-post-sampler"),
+        ..Default::default()
+    });
+    let post_bind_group = post_bind_group(
+        device,
+        &post_bind_group_layout,
+        &post_sampler,
+        &scaled_texture,
+        &gbuffer,
+        &accumulator.color,
+    );
+    let post_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("nannou-raytracer-post-pipeline-layout"),
+        bind_group_layouts: &[&post_bind_group_layout],
+        push_constant_ranges: &[wgpu::PushConstantRange {
+            stages: wgpu::ShaderStage::all(),
+            range: 0..std::mem::size_of::<ShaderConstants>() as u32,
+        }],
+    });
+    let post_pipeline = wgpu::RenderPipelineBuilder::from_layout(&post_pipeline_layout, &shader_mod)
         .fragment_shader(&shader_mod)
         .vertex_entry_point(shaders::main_vs)
-        .fragment_entry_point(shaders::main_fs)
-        .color_format(scaled_texture_format)
-        .color_blend(wgpu::BlendComponent::OVER)
-        .alpha_blend(wgpu::BlendComponent::REPLACE)
+        .fragment_entry_point(shaders::post_fs)
+        .color_format(DISPLAY_TEXTURE_FORMAT)
         .sample_count(scaled_texture_sample_count)
         .build(device);
 
-    Graphics {
+    let pipelines = Pipelines {
         pipeline_layout,
         pipeline,
+        compute,
+        wavefront,
+        post_bind_group_layout,
+        post_pipeline,
+        post_sampler,
+        accum_bind_group_layout,
+    };
+    Graphics {
+        pipelines: Rc::new(pipelines),
+        compute_bind_group,
+        post_bind_group,
         scaled_texture,
+        gbuffer,
+        accumulator,
+        tiler: Default::default(),
+        denoiser,
+        display_texture,
         texture_reshaper,
     }
 }
 
-fn load_shader_module_desc() -> wgpu::ShaderModuleDescriptor<'static> {
-    let manifest_dir = env!("CARGO_MANIFEST_DIR");
-    let crate_path = [manifest_dir, "..", "shader"]
-        .iter()
-        .copied()
-        .collect::<PathBuf>();
-    let compile_result = SpirvBuilder::new(crate_path, "spirv-unknown-vulkan1.1")
-        .print_metadata(MetadataPrintout::None)
-        // Seems to be needed to handle conditions within functions?
-        // Error was confusing but adding this worked.
-        .capability(Capability::Int8)
-        .build()
-        .unwrap();
-    let module_path = compile_result.module.unwrap_single();
-    let data = std::fs::read(module_path).unwrap();
-    let spirv = wgpu::util::make_spirv(&data);
-    let spirv = match spirv {
-        wgpu::ShaderSource::Wgsl(cow) => wgpu::ShaderSource::Wgsl(Cow::Owned(cow.into_owned())),
-        wgpu::ShaderSource::SpirV(cow) => {
-            wgpu::ShaderSource::SpirV(Cow::Owned(cow.into_owned()))
+/// A pipeline drawing `main_vs`'s full screen triangle with the given fragment entry point.
+fn fullscreen_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader_mod: &wgpu::ShaderModule,
+    fs_entry_point: &str,
+    targets: &[wgpu::ColorTargetState],
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("nannou-raytracer-pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader_mod,
+            entry_point: shaders::main_vs,
+            buffers: &[],
+        },
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        fragment: Some(wgpu::FragmentState {
+            module: shader_mod,
+            entry_point: fs_entry_point,
+            targets,
+        }),
+    })
+}
+
+fn copy_texture(encoder: &mut wgpu::CommandEncoder, src: &wgpu::Texture, dst: &wgpu::Texture) {
+    let [width, height] = src.size();
+    encoder.copy_texture_to_texture(
+        wgpu::ImageCopyTexture {
+            texture: src,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        wgpu::ImageCopyTexture {
+            texture: dst,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+}
+
+// A nearest sampler at binding 0 followed by `texture_count` unfilterable float textures, visible
+// to the fragment and compute stages.
+fn texture_bind_group_layout(device: &wgpu::Device, texture_count: u32) -> wgpu::BindGroupLayout {
+    let visibility = wgpu::ShaderStage::FRAGMENT | wgpu::ShaderStage::COMPUTE;
+    let sampler = wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility,
+        ty: wgpu::BindingType::Sampler {
+            filtering: false,
+            comparison: false,
+        },
+        count: None,
+    };
+    let texture = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    };
+    let entries: Vec<_> = Some(sampler)
+        .into_iter()
+        .chain((1..=texture_count).map(texture))
+        .collect();
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("nannou-raytracer-texture-bind-group-layout"),
+        entries: &entries,
+    })
+}
+
+fn texture_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    textures: &[&wgpu::Texture],
+) -> wgpu::BindGroup {
+    let views: Vec<_> = textures.iter().map(|texture| texture.view().build()).collect();
+    let entries: Vec<_> = Some(wgpu::BindingResource::Sampler(sampler))
+        .into_iter()
+        .chain(views.iter().map(wgpu::BindingResource::TextureView))
+        .enumerate()
+        .map(|(binding, resource)| wgpu::BindGroupEntry {
+            binding: binding as u32,
+            resource,
+        })
+        .collect();
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("nannou-raytracer-texture-bind-group"),
+        layout,
+        entries: &entries,
+    })
+}
+
+/// The path given via the `<flag> <path>` argument, e.g. `--env-map`, if any.
+fn path_arg(flag: &str) -> Option<PathBuf> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args.next().map(PathBuf::from);
         }
+    }
+    None
+}
+
+// Exit with an explanation if the adapter can't provide the limits requested, rather than failing
+// wgpu's validation when the device or pipelines are created.
+fn check_adapter_limits(limits: &wgpu::Limits) {
+    let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+    let options = wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::default(),
+        compatible_surface: None,
+    };
+    let adapter = match futures::executor::block_on(instance.request_adapter(&options)) {
+        Some(adapter) => adapter,
+        None => return,
     };
-    wgpu::ShaderModuleDescriptor {
-        label: Some("nannou-raytracer-shader"),
-        source: spirv,
-        flags: wgpu::ShaderFlags::default(),
+    let supported = adapter.limits();
+    if supported.max_storage_buffers_per_shader_stage < limits.max_storage_buffers_per_shader_stage
+        || supported.max_push_constant_size < limits.max_push_constant_size
+    {
+        eprintln!(
+            "{} supports {} storage buffers per shader stage and {} bytes of push constants, \
+             but {} and {} are needed",
+            adapter.get_info().name,
+            supported.max_storage_buffers_per_shader_stage,
+            supported.max_push_constant_size,
+            limits.max_storage_buffers_per_shader_stage,
+            limits.max_push_constant_size,
+        );
+        std::process::exit(1);
     }
 }
 
-// NOTE: Super unsafe for general use, OK for this case.
-unsafe fn any_as_u8_slice<T: Sized>(p: &T) -> &[u8] {
-    std::slice::from_raw_parts((p as *const T) as *const u8, std::mem::size_of::<T>())
+fn load_env_map(path: &Path) -> Option<env_map::EnvMap> {
+    match env_map::EnvMap::load(path) {
+        Ok(env_map) => Some(env_map),
+        Err(err) => {
+            eprintln!("{}: {}", path.display(), err);
+            None
+        }
+    }
 }
@@ -0,0 +1,390 @@
+//! Writing the scene to disk: screenshots of the window and renders at a multiple of its
+//! resolution.
+
+use crate::accumulate::Accumulator;
+use crate::capture::{self, ExrLayer, Readback};
+use crate::gbuffer::GBuffer;
+use crate::{env_map, scene, tiles};
+use crate::{Config, Graphics, Pipelines, Renderer, DISPLAY_TEXTURE_FORMAT, HDR_TEXTURE_FORMAT};
+use nannou::wgpu;
+use shared::ShaderConstants;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The directory to which renders and screenshots are written.
+pub const RENDERS_DIR: &str = "renders";
+
+/// The metadata key of the preset of the settings embedded in each PNG and EXR, see `preset`.
+pub const PRESET_KEY: &str = "nannou-raytracer.preset";
+
+/// The largest multiple of the window's resolution that can be rendered to disk.
+pub const MAX_SCALE: u32 = 4;
+
+// The rays traced per pixel in each accumulated frame. Small frames let converged pixels stop
+// early and keep each submission short.
+const RAYS_PER_FRAME: u32 = 4;
+
+// The EXR channels of the accumulation's mean radiance and sample count, and of a single frame.
+const ACCUMULATION_CHANNELS: &[&str] = &["R", "G", "B", "samples.Y"];
+const HDR_CHANNELS: &[&str] = &["R", "G", "B"];
+
+// Decodes the texels of a readback.
+type Decode = fn(&[u8]) -> Vec<[f32; 4]>;
+
+/// A render to disk in progress, drawn through the same scene and post-process passes as the
+/// window.
+///
+/// Frames are accumulated until each pixel has taken the config's offline rays per pixel, rounded
+/// up to whole frames, or until its error has converged below the push constants' adaptive
+/// threshold. Each frame is traced by the renderer selected in the config, a batch of tiles per
+/// call to `advance`, so the window keeps drawing in between. The render keeps the window's
+/// pipelines as they were when it started, so rebuilding the window's graphics doesn't affect it.
+pub struct Render {
+    push_constants: ShaderConstants,
+    renderer: Renderer,
+    pipelines: Rc<Pipelines>,
+    // The scene as it was when the render started, unaffected by reloads and animation.
+    scene_bindings: scene::Bindings,
+    hdr_texture: wgpu::Texture,
+    ldr_texture: wgpu::Texture,
+    gbuffer: GBuffer,
+    compute_bind_group: wgpu::BindGroup,
+    accumulator: Accumulator,
+    post_bind_group: wgpu::BindGroup,
+    // The tiles of each frame and the number drawn per batch.
+    tiles: Vec<tiles::Tile>,
+    batch_size: usize,
+    frames: u32,
+    // Whether the noise was animated, and the number of the first frame's samples.
+    animate_noise: bool,
+    first_frame: u32,
+    // The number of batches submitted so far, across all frames.
+    batches: usize,
+    // The TOML of the settings the render was started with, embedded in the files.
+    preset: String,
+}
+
+impl Render {
+    /// Start rendering the scene with the current settings at the config's offline scale times
+    /// the window's size in pixels.
+    ///
+    /// `scene_layout` and `env_map` are those of the window's scene bindings, and `preset` is
+    /// embedded in the files written.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        shader_mod: &wgpu::ShaderModule,
+        graphics: &Graphics,
+        scene_layout: &wgpu::BindGroupLayout,
+        env_map: &env_map::Bindings,
+        scene: &scene::Scene,
+        mut push_constants: ShaderConstants,
+        config: &Config,
+        [w, h]: [u32; 2],
+        preset: String,
+    ) -> Self {
+        let scale = config.offline_scale.max(1).min(MAX_SCALE);
+        let size = [w * scale, h * scale];
+        push_constants.view_size_pixels = size;
+        push_constants.aov = shared::aov::BEAUTY;
+        push_constants.accumulate = 1;
+        let rays_per_pixel = config.offline_rays_per_pixel.max(1);
+        let rays_per_frame = rays_per_pixel.min(RAYS_PER_FRAME);
+        let frames = (rays_per_pixel + rays_per_frame - 1) / rays_per_frame;
+        push_constants.rays_per_pixel = rays_per_frame;
+
+        let mut scene_bindings = scene::Bindings::new(device, scene_layout, env_map);
+        scene_bindings.upload(queue, scene, push_constants.time);
+
+        let texture = |format| {
+            wgpu::TextureBuilder::new()
+                .size(size)
+                .usage(
+                    wgpu::TextureUsage::RENDER_ATTACHMENT
+                        | wgpu::TextureUsage::STORAGE
+                        | wgpu::TextureUsage::SAMPLED
+                        | wgpu::TextureUsage::COPY_SRC,
+                )
+                .format(format)
+                .build(device)
+        };
+        let hdr_texture = texture(HDR_TEXTURE_FORMAT);
+        let ldr_texture = texture(DISPLAY_TEXTURE_FORMAT);
+        let gbuffer_usage = wgpu::TextureUsage::COPY_SRC | wgpu::TextureUsage::STORAGE;
+        let gbuffer = GBuffer::new(device, size, gbuffer_usage);
+        let pipelines = graphics.pipelines.clone();
+        let compute_bind_group = pipelines.compute.bind_group(device, &hdr_texture, &gbuffer);
+        let accumulator = Accumulator::new(
+            device,
+            shader_mod,
+            &pipelines.accum_bind_group_layout,
+            size,
+            &hdr_texture,
+        );
+        let post_bind_group = crate::post_bind_group(
+            device,
+            &pipelines.post_bind_group_layout,
+            &pipelines.post_sampler,
+            &hdr_texture,
+            &gbuffer,
+            &accumulator.color,
+        );
+
+        let tile_settings = &config.tiles;
+        let tile_size = tile_settings.tile_size(size);
+        let tile_count = tiles::tile_count(size, tile_size);
+        let tiles = (0..tile_count).map(|i| tiles::tile(size, tile_size, i)).collect();
+        let batch_size = tile_settings.tiles_per_frame(size).max(1) as usize;
+
+        Render {
+            push_constants,
+            renderer: config.renderer,
+            pipelines,
+            scene_bindings,
+            hdr_texture,
+            ldr_texture,
+            gbuffer,
+            compute_bind_group,
+            accumulator,
+            post_bind_group,
+            tiles,
+            batch_size,
+            frames,
+            animate_noise: config.seed_rng_with_time,
+            first_frame: 0,
+            batches: 0,
+            preset,
+        }
+    }
+
+    /// The fraction of the render's batches submitted.
+    pub fn progress(&self) -> f32 {
+        self.batches as f32 / self.total_batches() as f32
+    }
+
+    /// Start the render over at another time of the scene's animation, keeping its settings and
+    /// resolution. The camera is aimed and the spheres moved as they are at `time`.
+    ///
+    /// `sequence` numbers the image within an animation. If the config animated the noise, each
+    /// image takes the samples following those of the last rather than repeating them.
+    pub fn restart(&mut self, queue: &wgpu::Queue, scene: &scene::Scene, time: f32, sequence: u32) {
+        self.push_constants.time = time;
+        self.first_frame = match self.animate_noise {
+            true => sequence.wrapping_mul(self.frames),
+            false => 0,
+        };
+        self.push_constants.sphere_count = scene.spheres.len() as u32;
+        scene.camera.aim(&mut self.push_constants);
+        self.scene_bindings.upload(queue, scene, time);
+        self.batches = 0;
+    }
+
+    /// Submit the next batch of tiles. Once every frame is drawn, read back the render and write
+    /// the tone mapped PNG and an EXR of the raw HDR render and its AOVs to `RENDERS_DIR`,
+    /// returning `true`.
+    ///
+    /// Encoding the files happens on a background thread that reports its outcome via `status`,
+    /// and the device must be polled for the readback to complete.
+    pub fn advance(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        status: &mpsc::Sender<String>,
+    ) -> bool {
+        match self.step(device, queue) {
+            Some(readbacks) => {
+                spawn_write("render", readbacks, self.preset.clone(), status.clone());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Submit the next batch of tiles, or once every frame is drawn, the post-process pass and
+    /// the copies of the render returned to be written.
+    pub fn step(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Readbacks> {
+        let desc = wgpu::CommandEncoderDescriptor {
+            label: Some("nannou-raytracer-offline"),
+        };
+        let mut encoder = device.create_command_encoder(&desc);
+        if self.batches < self.total_batches() {
+            let batches_per_frame = self.batches_per_frame();
+            let frame = (self.batches / batches_per_frame) as u32;
+            let batch = self.batches % batches_per_frame;
+            self.push_constants.accumulated_frames = frame;
+            self.push_constants.frame = self.first_frame.wrapping_add(frame);
+            let start = batch * self.batch_size;
+            let end = (start + self.batch_size).min(self.tiles.len());
+            crate::encode_scene(
+                &mut encoder,
+                &self.pipelines,
+                self.renderer,
+                &self.scene_bindings.bind_group,
+                self.accumulator.scene_bind_group(),
+                &self.compute_bind_group,
+                &self.push_constants,
+                &self.tiles[start..end],
+                &self.hdr_texture.view().build(),
+                &self.gbuffer.views(),
+            );
+            if batch + 1 == batches_per_frame {
+                self.accumulator.encode(&mut encoder, &self.push_constants);
+            }
+            queue.submit(Some(encoder.finish()));
+            self.batches += 1;
+            return None;
+        }
+
+        let ldr_view = self.ldr_texture.view().build();
+        let pc = &self.push_constants;
+        let pipelines = &self.pipelines;
+        crate::encode_post_pass(&mut encoder, pipelines, &self.post_bind_group, pc, &ldr_view);
+        let readbacks = Readbacks {
+            ldr: Readback::new(device, &mut encoder, &self.ldr_texture, 4),
+            color: Readback::new(device, &mut encoder, &self.accumulator.color, 16),
+            color_channels: ACCUMULATION_CHANNELS,
+            decode_color: capture::rgba32f_texels,
+            normal_depth: Readback::new(device, &mut encoder, &self.gbuffer.normal_depth, 16),
+            albedo: Readback::new(device, &mut encoder, &self.gbuffer.albedo, 8),
+            material: Readback::new(device, &mut encoder, &self.gbuffer.material, 16),
+        };
+        queue.submit(Some(encoder.finish()));
+        Some(readbacks)
+    }
+
+    fn batches_per_frame(&self) -> usize {
+        (self.tiles.len() + self.batch_size - 1) / self.batch_size
+    }
+
+    fn total_batches(&self) -> usize {
+        self.batches_per_frame() * self.frames as usize
+    }
+}
+
+/// Write what the window currently shows: the tone mapped display texture to a PNG, and the
+/// HDR render or accumulation it was mapped from to an EXR along with the AOVs.
+///
+/// Only the copies are submitted here. Encoding the files happens on a background thread that
+/// reports its outcome via `status`, and the device must be polled for the readback to complete.
+pub fn screenshot(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    graphics: &Graphics,
+    push_constants: &ShaderConstants,
+    preset: String,
+    status: mpsc::Sender<String>,
+) {
+    let desc = wgpu::CommandEncoderDescriptor {
+        label: Some("nannou-raytracer-screenshot"),
+    };
+    let mut encoder = device.create_command_encoder(&desc);
+    let gbuffer = &graphics.gbuffer;
+    let ldr = Readback::new(device, &mut encoder, &graphics.display_texture, 4);
+    // The post-process pass displays the accumulation while accumulating, otherwise the frame.
+    let (color, color_channels, decode_color) = match push_constants.accumulate != 0 {
+        true => {
+            let color = Readback::new(device, &mut encoder, &graphics.accumulator.color, 16);
+            (color, ACCUMULATION_CHANNELS, capture::rgba32f_texels as Decode)
+        }
+        false => {
+            let color = Readback::new(device, &mut encoder, &graphics.scaled_texture, 8);
+            (color, HDR_CHANNELS, capture::rgba16f_texels as Decode)
+        }
+    };
+    let readbacks = Readbacks {
+        ldr,
+        color,
+        color_channels,
+        decode_color,
+        normal_depth: Readback::new(device, &mut encoder, &gbuffer.normal_depth, 16),
+        albedo: Readback::new(device, &mut encoder, &gbuffer.albedo, 8),
+        material: Readback::new(device, &mut encoder, &gbuffer.material, 16),
+    };
+    queue.submit(Some(encoder.finish()));
+    spawn_write("screenshot", readbacks, preset, status);
+}
+
+/// The copies of a render or screenshot being read back, to be written once mapped.
+pub struct Readbacks {
+    ldr: Readback,
+    color: Readback,
+    // The EXR channels of the color's components and how to decode its texels.
+    color_channels: &'static [&'static str],
+    decode_color: Decode,
+    normal_depth: Readback,
+    albedo: Readback,
+    material: Readback,
+}
+
+// Write the readbacks to `<prefix>-<timestamp>.png` and `.exr` in `RENDERS_DIR` on a background
+// thread, sending the outcome to `status`.
+fn spawn_write(prefix: &str, readbacks: Readbacks, preset: String, status: mpsc::Sender<String>) {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let stem = PathBuf::from(RENDERS_DIR).join(format!("{}-{}", prefix, stamp));
+    let prefix = prefix.to_string();
+    std::thread::spawn(move || {
+        let msg = match readbacks.write(&stem, &preset) {
+            Ok(_) => format!("Saved {}", stem.with_extension("png").display()),
+            Err(err) => format!("Failed to save {}: {}", prefix, err),
+        };
+        status.send(msg).ok();
+    });
+}
+
+impl Readbacks {
+    /// The size of the images in pixels.
+    pub fn size(&self) -> [u32; 2] {
+        self.ldr.size()
+    }
+
+    /// Wait for the textures to be read back and write `<stem>.png` and `<stem>.exr`, creating
+    /// the directory if need be, with `preset` embedded under `PRESET_KEY`. Returns the tone
+    /// mapped `Rgba8UnormSrgb` texels written to the PNG.
+    pub fn write(self, stem: &Path, preset: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        if let Some(dir) = stem.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let size = self.size();
+        let metadata = [(PRESET_KEY, preset)];
+        let read = |readback: Readback| {
+            readback
+                .read()
+                .map_err(|_| "failed to read back the render")
+        };
+        let ldr = read(self.ldr)?;
+        capture::save_png(&stem.with_extension("png"), size, ldr.clone(), &metadata)?;
+
+        let normal_depth = capture::rgba32f_texels(&read(self.normal_depth)?);
+        let depth = normal_depth.iter().map(|&[_, _, _, d]| [d, 0.0, 0.0, 0.0]).collect();
+        let layers = vec![
+            ExrLayer {
+                channels: self.color_channels,
+                texels: (self.decode_color)(&read(self.color)?),
+            },
+            ExrLayer {
+                channels: &["Z"],
+                texels: depth,
+            },
+            ExrLayer {
+                channels: &["normal.X", "normal.Y", "normal.Z"],
+                texels: normal_depth,
+            },
+            ExrLayer {
+                channels: &["albedo.R", "albedo.G", "albedo.B"],
+                texels: capture::rgba16f_texels(&read(self.albedo)?),
+            },
+            ExrLayer {
+                channels: &["material.kind", "material.index", "bounces.Y"],
+                texels: capture::rgba32f_texels(&read(self.material)?),
+            },
+        ];
+        capture::save_exr_layers(&stem.with_extension("exr"), size, layers, &metadata)?;
+        Ok(ldr)
+    }
+}
@@ -0,0 +1,497 @@
+//! Presets of the settings edited in the GUI, saved to and loaded from TOML files so that a render
+//! can be reproduced later or on another machine.
+//!
+//! ```toml
+//! scene = "scenes/cornell_box.toml"
+//! time = 1.5
+//!
+//! [render]
+//! render_scale = 0.5
+//! renderer = "wavefront"
+//! rays_per_pixel = 4
+//! ray_bounce_limit = 8
+//! animate_noise = false
+//! frame = 0
+//!
+//! [camera]
+//! from = [0.0, 1.0, 3.4]
+//! to = [0.0, 1.0, 0.0]
+//! vfov = 40.0
+//! aperture = 0.05
+//!
+//! [environment]
+//! kind = "map"
+//! map = "hdri/studio.exr"
+//! ```
+//!
+//! Every field is optional. Missing settings take the app's defaults, while a missing camera
+//! parameter keeps the one given by the scene file. A camera `from` or `to` holds the camera still
+//! there, in place of the scene's keyframes and motion.
+
+use crate::{denoise, record, scene, tiles, timeline, Config, Renderer};
+use serde::{Deserialize, Serialize};
+use shared::ShaderConstants;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The directory to which presets are saved and from which the GUI lists them.
+pub const PRESETS_DIR: &str = "presets";
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Preset {
+    /// The scene file. Relative paths are relative to the repository root, as in `scene::LIBRARY`,
+    /// or failing that the working directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scene: Option<PathBuf>,
+    /// The time of the animation in seconds, at which playback is paused.
+    pub time: f32,
+    pub render: Render,
+    pub accumulation: Accumulation,
+    pub camera: Camera,
+    pub environment: Environment,
+    pub display: Display,
+    pub tiles: tiles::Settings,
+    pub denoise: denoise::Settings,
+    pub recording: record::Settings,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Render {
+    pub render_scale: f32,
+    pub renderer: Renderer,
+    pub rays_per_pixel: u32,
+    pub ray_bounce_limit: u32,
+    pub roulette_depth: u32,
+    pub radiance_clamp: f32,
+    pub sampler: Sampler,
+    pub spectral: bool,
+    /// Whether each frame takes new samples rather than repeating the last.
+    pub animate_noise: bool,
+    pub offline_rays_per_pixel: u32,
+    pub offline_scale: u32,
+    /// The number of the frame seeding the samplers, which only advances with `animate_noise`.
+    pub frame: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Accumulation {
+    pub enabled: bool,
+    pub adaptive_threshold: f32,
+}
+
+/// The scene's camera.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Camera {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<[f32; 3]>,
+    /// The vertical field of view in degrees.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vfov: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aperture: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub focus_dist: Option<f32>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Environment {
+    pub kind: EnvironmentKind,
+    /// The environment map file, loaded in place of the current one. Relative paths are relative to
+    /// the repository root or failing that the working directory, as for `scene`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub map: Option<PathBuf>,
+    pub rotation: f32,
+    pub intensity: f32,
+    pub turbidity: f32,
+    /// Drives the position of the sun in the sky in hours, `0.0..24.0`.
+    pub time_of_day: f32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Display {
+    /// Exposure applied before tone mapping in EV.
+    pub exposure: f32,
+    pub tonemap: Tonemap,
+}
+
+/// The kinds in the `shared::sampler` module.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sampler {
+    Pcg,
+    Sobol,
+    Halton,
+    BlueNoise,
+}
+
+/// The kinds in the `shared::environment` module.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnvironmentKind {
+    Sky,
+    Map,
+}
+
+/// The operators in the `shared::tonemap` module.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tonemap {
+    Clamp,
+    Reinhard,
+    AcesFilmic,
+    Agx,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Serialize(toml::ser::Error),
+}
+
+impl Preset {
+    /// The current settings, along with the scene and environment map files they were used with.
+    pub fn capture(
+        config: &Config,
+        push_constants: &ShaderConstants,
+        scene_path: Option<&Path>,
+        env_map_path: Option<&Path>,
+    ) -> Self {
+        let pc = push_constants;
+        Preset {
+            scene: scene_path.map(portable_scene_path),
+            time: pc.time,
+            render: Render {
+                render_scale: config.render_scale,
+                renderer: config.renderer,
+                rays_per_pixel: pc.rays_per_pixel,
+                ray_bounce_limit: pc.ray_bounce_limit,
+                roulette_depth: pc.roulette_depth,
+                radiance_clamp: pc.radiance_clamp,
+                sampler: Sampler::from_kind(pc.sampler),
+                spectral: pc.spectral != 0,
+                animate_noise: config.seed_rng_with_time,
+                offline_rays_per_pixel: config.offline_rays_per_pixel,
+                offline_scale: config.offline_scale,
+                frame: pc.frame,
+            },
+            accumulation: Accumulation {
+                enabled: pc.accumulate != 0,
+                adaptive_threshold: pc.adaptive_threshold,
+            },
+            camera: Camera {
+                from: Some(pc.camera_from),
+                to: Some(pc.camera_to),
+                vfov: Some(pc.vfov.to_degrees()),
+                aperture: Some(pc.aperture),
+                focus_dist: Some(pc.focus_dist),
+            },
+            environment: Environment {
+                kind: EnvironmentKind::from_kind(pc.environment),
+                map: env_map_path.map(portable_path),
+                rotation: pc.env_rotation,
+                intensity: pc.env_intensity,
+                turbidity: pc.turbidity,
+                time_of_day: config.time_of_day,
+            },
+            display: Display {
+                exposure: pc.exposure,
+                tonemap: Tonemap::from_kind(pc.tonemap),
+            },
+            tiles: config.tiles.clone(),
+            denoise: config.denoise.clone(),
+            recording: config.recording.clone(),
+        }
+    }
+
+    /// Overwrite the settings with those of the preset, pinning the scene's `camera` if the
+    /// preset places it and pausing `playback` at the preset's time.
+    ///
+    /// Neither the scene nor the environment map are loaded here, see `scene_path` and
+    /// `env_map_path`. Load the scene first, as loading a scene resets the camera.
+    pub fn apply(
+        &self,
+        config: &mut Config,
+        push_constants: &mut ShaderConstants,
+        camera: &mut scene::SceneCamera,
+        playback: &mut timeline::Playback,
+    ) {
+        let pc = push_constants;
+        playback.time = self.time;
+        playback.playing = false;
+
+        let render = &self.render;
+        config.render_scale = render.render_scale;
+        config.renderer = render.renderer;
+        pc.rays_per_pixel = render.rays_per_pixel;
+        pc.ray_bounce_limit = render.ray_bounce_limit;
+        pc.roulette_depth = render.roulette_depth;
+        pc.radiance_clamp = render.radiance_clamp;
+        pc.sampler = render.sampler.kind();
+        pc.spectral = render.spectral as u32;
+        config.seed_rng_with_time = render.animate_noise;
+        config.offline_rays_per_pixel = render.offline_rays_per_pixel;
+        config.offline_scale = render.offline_scale;
+        pc.frame = render.frame;
+
+        pc.accumulate = self.accumulation.enabled as u32;
+        pc.adaptive_threshold = self.accumulation.adaptive_threshold;
+
+        if self.camera.from.is_some() || self.camera.to.is_some() {
+            let from = self.camera.from.unwrap_or(camera.from);
+            let to = self.camera.to.unwrap_or(camera.to);
+            camera.pinned = Some((from, to));
+        }
+        if let Some(vfov) = self.camera.vfov {
+            pc.vfov = vfov.to_radians();
+        }
+        if let Some(aperture) = self.camera.aperture {
+            pc.aperture = aperture;
+        }
+        if let Some(focus_dist) = self.camera.focus_dist {
+            pc.focus_dist = focus_dist;
+        }
+
+        let env = &self.environment;
+        pc.environment = env.kind.kind();
+        pc.env_rotation = env.rotation;
+        pc.env_intensity = env.intensity;
+        pc.turbidity = env.turbidity;
+        config.time_of_day = env.time_of_day;
+
+        pc.exposure = self.display.exposure;
+        pc.tonemap = self.display.tonemap.kind();
+
+        config.tiles = self.tiles.clone();
+        config.denoise = self.denoise.clone();
+        config.recording = self.recording.clone();
+    }
+
+    /// The scene file to load with the preset, if it names one.
+    pub fn scene_path(&self) -> Option<PathBuf> {
+        self.scene.as_deref().map(resolve_path)
+    }
+
+    /// The environment map to load with the preset, if it names one.
+    pub fn env_map_path(&self) -> Option<PathBuf> {
+        self.environment.map.as_deref().map(resolve_path)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&text)?)
+    }
+
+    pub fn to_toml(&self) -> Result<String, Error> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    /// Write the preset to a new file in `PRESETS_DIR`, returning its path. Existing presets are
+    /// never overwritten: a file saved within the same millisecond as another gets a numbered
+    /// suffix.
+    pub fn save(&self) -> Result<PathBuf, Error> {
+        let text = self.to_toml()?;
+        std::fs::create_dir_all(PRESETS_DIR)?;
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let mut suffix = 0;
+        loop {
+            let name = match suffix {
+                0 => format!("preset-{}.toml", stamp),
+                n => format!("preset-{}-{}.toml", stamp, n),
+            };
+            let path = Path::new(PRESETS_DIR).join(name);
+            // Creating the file only if it's new checks for collisions without a race.
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    file.write_all(text.as_bytes())?;
+                    return Ok(path);
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => suffix += 1,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    // The settings the app starts with, leaving the camera to the scene.
+    fn app_default() -> Self {
+        let push_constants = crate::default_push_constants();
+        let mut preset = Preset::capture(&Config::default(), &push_constants, None, None);
+        preset.camera = Camera::default();
+        preset
+    }
+}
+
+impl Default for Render {
+    fn default() -> Self {
+        Preset::app_default().render
+    }
+}
+
+impl Default for Accumulation {
+    fn default() -> Self {
+        Preset::app_default().accumulation
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Preset::app_default().environment
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Preset::app_default().display
+    }
+}
+
+impl Sampler {
+    fn from_kind(kind: u32) -> Self {
+        match kind {
+            shared::sampler::PCG => Sampler::Pcg,
+            shared::sampler::HALTON => Sampler::Halton,
+            shared::sampler::BLUE_NOISE => Sampler::BlueNoise,
+            _ => Sampler::Sobol,
+        }
+    }
+
+    fn kind(self) -> u32 {
+        match self {
+            Sampler::Pcg => shared::sampler::PCG,
+            Sampler::Sobol => shared::sampler::SOBOL,
+            Sampler::Halton => shared::sampler::HALTON,
+            Sampler::BlueNoise => shared::sampler::BLUE_NOISE,
+        }
+    }
+}
+
+impl EnvironmentKind {
+    fn from_kind(kind: u32) -> Self {
+        match kind {
+            shared::environment::MAP => EnvironmentKind::Map,
+            _ => EnvironmentKind::Sky,
+        }
+    }
+
+    fn kind(self) -> u32 {
+        match self {
+            EnvironmentKind::Sky => shared::environment::SKY,
+            EnvironmentKind::Map => shared::environment::MAP,
+        }
+    }
+}
+
+impl Tonemap {
+    fn from_kind(kind: u32) -> Self {
+        match kind {
+            shared::tonemap::REINHARD => Tonemap::Reinhard,
+            shared::tonemap::ACES_FILMIC => Tonemap::AcesFilmic,
+            shared::tonemap::AGX => Tonemap::Agx,
+            _ => Tonemap::Clamp,
+        }
+    }
+
+    fn kind(self) -> u32 {
+        match self {
+            Tonemap::Clamp => shared::tonemap::CLAMP,
+            Tonemap::Reinhard => shared::tonemap::REINHARD,
+            Tonemap::AcesFilmic => shared::tonemap::ACES_FILMIC,
+            Tonemap::Agx => shared::tonemap::AGX,
+        }
+    }
+}
+
+/// The presets in `PRESETS_DIR`, sorted by file name.
+pub fn list() -> Vec<PathBuf> {
+    let entries = match std::fs::read_dir(PRESETS_DIR) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().map_or(false, |ext| ext == "toml"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+// Library scenes are named relative to the repository root so the preset works in any checkout,
+// even one the library wasn't found in, and other scenes by `portable_path`.
+fn portable_scene_path(path: &Path) -> PathBuf {
+    let library_file = scene::LIBRARY
+        .iter()
+        .map(|&(_, file)| file)
+        .find(|file| scene::library_path(file) == path);
+    match library_file {
+        Some(file) => PathBuf::from(file),
+        None => portable_path(path),
+    }
+}
+
+// Files within the repository are named relative to its root, and others within the working
+// directory relative to that, so the preset works on other machines. Files elsewhere keep their
+// absolute path.
+fn portable_path(path: &Path) -> PathBuf {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let roots = [scene::library_path(""), std::env::current_dir().unwrap_or_default()];
+    roots
+        .iter()
+        .filter_map(|root| std::fs::canonicalize(root).ok())
+        .find_map(|root| path.strip_prefix(root).ok().map(Path::to_path_buf))
+        .unwrap_or(path)
+}
+
+// The file a preset names, looking for relative paths in the repository before the working
+// directory.
+fn resolve_path(path: &Path) -> PathBuf {
+    let in_repository = scene::library_path(path);
+    if path.is_relative() && !in_repository.exists() {
+        path.to_path_buf()
+    } else {
+        in_repository
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "failed to access preset: {}", err),
+            Error::Parse(err) => write!(f, "failed to parse preset: {}", err),
+            Error::Serialize(err) => write!(f, "failed to serialize preset: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Self {
+        Error::Parse(err)
+    }
+}
+
+impl From<toml::ser::Error> for Error {
+    fn from(err: toml::ser::Error) -> Self {
+        Error::Serialize(err)
+    }
+}
@@ -0,0 +1,227 @@
+//! Recording the scene's animation to disk as a numbered sequence of PNGs and EXRs, optionally
+//! encoded to a video by a local `ffmpeg`.
+//!
+//! Time steps at the recording's frame rate rather than following the clock, so the camera and
+//! spheres of each frame depend only on its number, however long it takes to render.
+
+use crate::offline::{self, Readbacks, RENDERS_DIR};
+use crate::{env_map, scene, Config, Graphics};
+use nannou::wgpu;
+use serde::{Deserialize, Serialize};
+use shared::ShaderConstants;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The highest frame rate offered by the GUI.
+pub const MAX_FPS: u32 = 60;
+
+/// The longest recording offered by the GUI in seconds.
+pub const MAX_DURATION: f32 = 60.0;
+
+/// The file written in the recording's directory when piping to `ffmpeg`.
+pub const VIDEO_FILE: &str = "video.mp4";
+
+/// User controls for recordings.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Frames per second of animation time.
+    pub fps: u32,
+    /// The length of the recording in seconds of animation time, which starts from zero.
+    pub duration: f32,
+    /// Whether to pipe the frames to `ffmpeg`, encoding `VIDEO_FILE` along with the images.
+    pub ffmpeg: bool,
+}
+
+/// A recording in progress.
+///
+/// Each frame is an `offline::Render` at the config's offline rays per pixel and scale, restarted
+/// at the frame's time. Finished frames are handed to a background thread that writes them in
+/// order.
+pub struct Recording {
+    render: offline::Render,
+    // The scene as it was when the recording started, unaffected by reloads.
+    scene: scene::Scene,
+    settings: Settings,
+    // The frame being rendered.
+    frame: u32,
+    frames: mpsc::Sender<Readbacks>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            fps: 30,
+            duration: 5.0,
+            ffmpeg: false,
+        }
+    }
+}
+
+impl Settings {
+    /// The number of frames recorded, at least one.
+    pub fn frame_count(&self) -> u32 {
+        ((self.duration * self.fps.max(1) as f32).ceil() as u32).max(1)
+    }
+
+    /// The animation time of a frame in seconds.
+    pub fn frame_time(&self, frame: u32) -> f32 {
+        frame as f32 / self.fps.max(1) as f32
+    }
+}
+
+impl Recording {
+    /// Start recording the scene with the current settings to a new directory in `RENDERS_DIR`.
+    ///
+    /// The arguments are those of `offline::Render::new`, along with where the writer reports
+    /// its outcome once the recording finishes or is dropped.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        shader_mod: &wgpu::ShaderModule,
+        graphics: &Graphics,
+        scene_layout: &wgpu::BindGroupLayout,
+        env_map: &env_map::Bindings,
+        scene: &scene::Scene,
+        push_constants: ShaderConstants,
+        config: &Config,
+        size: [u32; 2],
+        preset: String,
+        status: mpsc::Sender<String>,
+    ) -> Self {
+        let settings = config.recording.clone();
+        let mut render = offline::Render::new(
+            device,
+            queue,
+            shader_mod,
+            graphics,
+            scene_layout,
+            env_map,
+            scene,
+            push_constants,
+            config,
+            size,
+            preset.clone(),
+        );
+        render.restart(queue, scene, settings.frame_time(0), 0);
+
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let dir = PathBuf::from(RENDERS_DIR).join(format!("recording-{}", stamp));
+        let (frames, frames_rx) = mpsc::channel();
+        spawn_writer(dir, &settings, preset, frames_rx, status);
+
+        Recording {
+            render,
+            scene: scene.clone(),
+            settings,
+            frame: 0,
+            frames,
+        }
+    }
+
+    /// The number of the frame being rendered and the number of frames in total.
+    pub fn progress(&self) -> (u32, u32) {
+        (self.frame, self.settings.frame_count())
+    }
+
+    /// Submit the next batch of tiles of the current frame. Once it's drawn, hand it to the
+    /// writer and move on to the next, returning `true` after the last.
+    ///
+    /// The device must be polled for the frames' readbacks to complete.
+    pub fn advance(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        let readbacks = match self.render.step(device, queue) {
+            Some(readbacks) => readbacks,
+            None => return false,
+        };
+        // The writer only stops early on errors, which it reports itself.
+        if self.frames.send(readbacks).is_err() {
+            return true;
+        }
+        self.frame += 1;
+        if self.frame == self.settings.frame_count() {
+            return true;
+        }
+        let time = self.settings.frame_time(self.frame);
+        self.render.restart(queue, &self.scene, time, self.frame);
+        false
+    }
+}
+
+// Write the frames received to `frame-<number>.png` and `.exr` in `dir` on a background thread,
+// piping them to `ffmpeg` if enabled, until the recording finishes or is dropped. The outcome is
+// sent to `status`.
+fn spawn_writer(
+    dir: PathBuf,
+    settings: &Settings,
+    preset: String,
+    frames: mpsc::Receiver<Readbacks>,
+    status: mpsc::Sender<String>,
+) {
+    let fps = settings.ffmpeg.then(|| settings.fps.max(1));
+    std::thread::spawn(move || {
+        let msg = match write_frames(&dir, fps, &preset, frames) {
+            Ok(count) => format!("Saved {} frames to {}", count, dir.display()),
+            Err(err) => format!("Failed to save recording: {}", err),
+        };
+        status.send(msg).ok();
+    });
+}
+
+// Write each frame as it's read back, returning the number written. `ffmpeg_fps` is the frame rate
+// of the video, if one is encoded.
+fn write_frames(
+    dir: &Path,
+    ffmpeg_fps: Option<u32>,
+    preset: &str,
+    frames: mpsc::Receiver<Readbacks>,
+) -> Result<u32, Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(dir)?;
+    let mut ffmpeg = None;
+    let mut count = 0;
+    for readbacks in frames {
+        // The size is only known once the first frame arrives.
+        if let (0, Some(fps)) = (count, ffmpeg_fps) {
+            ffmpeg = Some(spawn_ffmpeg(dir, readbacks.size(), fps)?);
+        }
+        let stem = dir.join(format!("frame-{:05}", count));
+        let rgba8 = readbacks.write(&stem, preset)?;
+        if let Some(stdin) = ffmpeg.as_mut().and_then(|child| child.stdin.as_mut()) {
+            stdin.write_all(&rgba8)?;
+        }
+        count += 1;
+    }
+    if let Some(mut child) = ffmpeg {
+        // Closing its input ends the video.
+        drop(child.stdin.take());
+        let exit = child.wait()?;
+        if !exit.success() {
+            return Err(format!("ffmpeg {}", exit).into());
+        }
+    }
+    Ok(count)
+}
+
+// Start `ffmpeg` encoding raw `Rgba8UnormSrgb` frames from its stdin to an H.264 `VIDEO_FILE` in
+// `dir`. Odd sizes are padded, as the chroma subsampling needs even ones.
+fn spawn_ffmpeg(dir: &Path, [w, h]: [u32; 2], fps: u32) -> Result<Child, String> {
+    Command::new("ffmpeg")
+        .args(&["-y", "-loglevel", "error"])
+        .args(&["-f", "rawvideo", "-pix_fmt", "rgba"])
+        .args(&["-video_size", &format!("{}x{}", w, h)])
+        .args(&["-framerate", &fps.to_string()])
+        .args(&["-i", "-"])
+        .args(&["-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2"])
+        .args(&["-c:v", "libx264", "-pix_fmt", "yuv420p"])
+        .arg(dir.join(VIDEO_FILE))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|err| format!("failed to start ffmpeg: {}", err))
+}
@@ -0,0 +1,809 @@
+//! Scenes described by TOML files, and the storage buffers they are uploaded to.
+//!
+//! A scene file describes its camera, names its materials and lists the spheres using them:
+//!
+//! ```toml
+//! [camera]
+//! from = [0.0, 1.0, 0.0]
+//! to = [0.0, 1.0, -3.0]
+//! vfov = 60.0
+//!
+//! [materials.glass]
+//! kind = "dielectric"
+//! ior = 1.5
+//!
+//! [[spheres]]
+//! center = [0.0, 1.0, -3.0]
+//! radius = 0.5
+//! material = "glass"
+//! ```
+//!
+//! The camera and spheres may be animated by keyframes, each setting some of their parameters at a
+//! time in seconds, see `timeline`:
+//!
+//! ```toml
+//! [[camera.keyframes]]
+//! time = 0.0
+//! from = [0.0, 1.0, 0.0]
+//! interpolation = "catmull_rom"
+//!
+//! [[camera.keyframes]]
+//! time = 4.0
+//! from = [2.0, 1.5, 0.0]
+//! vfov = 40.0
+//!
+//! [[spheres.keyframes]]
+//! time = 2.0
+//! radius = 0.75
+//! interpolation = "bezier"
+//! ```
+//!
+//! The file is watched while the app runs and reloaded whenever it's saved. The scenes of
+//! `LIBRARY` ship in the `scenes` directory and can be switched between in the GUI. They're also
+//! built into the executable, which falls back to its own copies when run away from the
+//! repository.
+
+use crate::env_map;
+use crate::timeline::{self, Interpolation, Key, Track};
+use crate::watch::Watcher;
+use bytemuck::Pod;
+use glam::{vec3, Vec3};
+use nannou::wgpu;
+use serde::Deserialize;
+use shared::{Dielectric, Lambertian, MaterialInfo, MaterialKind, Metal, ShaderConstants, Sphere, Volume};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+/// The file loaded when no `--scene` is given.
+pub const DEFAULT_SCENE: &str = "scenes/default.toml";
+
+/// The scenes offered by the GUI, by name and file relative to the repository root.
+pub const LIBRARY: &[(&str, &str)] = &[
+    ("Default", DEFAULT_SCENE),
+    ("Ray Tracing in One Weekend", "scenes/one_weekend.toml"),
+    ("Cornell box", "scenes/cornell_box.toml"),
+    ("Materials", "scenes/materials.toml"),
+    ("Depth of field", "scenes/depth_of_field.toml"),
+    ("Volumes", "scenes/volumes.toml"),
+    ("Keyframes", "scenes/keyframes.toml"),
+];
+
+// The files of `LIBRARY` as they were when the executable was built, loaded in their place when
+// they can't be found.
+const EMBEDDED: &[(&str, &str)] = &[
+    (DEFAULT_SCENE, include_str!("../../scenes/default.toml")),
+    ("scenes/one_weekend.toml", include_str!("../../scenes/one_weekend.toml")),
+    ("scenes/cornell_box.toml", include_str!("../../scenes/cornell_box.toml")),
+    ("scenes/materials.toml", include_str!("../../scenes/materials.toml")),
+    ("scenes/depth_of_field.toml", include_str!("../../scenes/depth_of_field.toml")),
+    ("scenes/volumes.toml", include_str!("../../scenes/volumes.toml")),
+    ("scenes/keyframes.toml", include_str!("../../scenes/keyframes.toml")),
+];
+
+/// The most spheres a scene may contain.
+pub const MAX_SPHERES: usize = 512;
+
+/// The most materials of each kind a scene may contain.
+pub const MAX_MATERIALS: usize = 64;
+
+// How often the scene file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A scene ready to upload, with its materials grouped by kind.
+#[derive(Clone)]
+pub struct Scene {
+    pub camera: SceneCamera,
+    pub spheres: Vec<SceneSphere>,
+    pub lambertian: Vec<Lambertian>,
+    pub metal: Vec<Metal>,
+    pub dielectric: Vec<Dielectric>,
+    pub volume: Vec<Volume>,
+}
+
+/// Where a scene is seen from. The GUI's camera controls start from these settings whenever the
+/// scene is loaded.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneCamera {
+    pub from: [f32; 3],
+    pub to: [f32; 3],
+    /// The vertical field of view in degrees.
+    pub vfov: f32,
+    pub aperture: f32,
+    /// The distance to the plane in focus, by default the distance from `from` to `to`.
+    pub focus_dist: Option<f32>,
+    /// Moves `from` as a sphere's `Motion` moves its center.
+    pub motion: Option<Motion>,
+    /// Animates the parameters above, with `motion` moving the keyed `from`.
+    pub keyframes: CameraTimeline,
+    /// The `(from, to)` a preset placed the camera at, overriding `from`, `to`, their keyframes
+    /// and `motion`. Never read from the scene file.
+    #[serde(skip)]
+    pub pinned: Option<([f32; 3], [f32; 3])>,
+}
+
+/// A sphere of a scene, which may be animated.
+#[derive(Clone)]
+pub struct SceneSphere {
+    pub sphere: Sphere,
+    pub motion: Option<Motion>,
+    pub keyframes: SphereTimeline,
+}
+
+/// Moves a sphere around its center by `amplitude * sin(frequency * time + phase)` on each axis.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Motion {
+    pub amplitude: [f32; 3],
+    pub frequency: [f32; 3],
+    pub phase: [f32; 3],
+}
+
+/// A keyframe of the camera. Parameters that no keyframe sets keep those of the `SceneCamera`, or
+/// for the lens, those of the GUI.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraKeyframe {
+    pub time: f32,
+    pub from: Option<[f32; 3]>,
+    pub to: Option<[f32; 3]>,
+    /// The vertical field of view in degrees.
+    pub vfov: Option<f32>,
+    pub aperture: Option<f32>,
+    pub focus_dist: Option<f32>,
+    /// How the parameters set change until the next keyframe setting them.
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    /// The timing of `Interpolation::Bezier`, see `Key::ease`.
+    #[serde(default = "ease_in_out")]
+    pub ease: [f32; 4],
+}
+
+/// A keyframe of a sphere's transform. Parameters that no keyframe sets keep the sphere's.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SphereKeyframe {
+    pub time: f32,
+    pub center: Option<[f32; 3]>,
+    pub radius: Option<f32>,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    #[serde(default = "ease_in_out")]
+    pub ease: [f32; 4],
+}
+
+/// The camera's keyframes as a track of each parameter.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(from = "Vec<CameraKeyframe>")]
+pub struct CameraTimeline {
+    pub from: Track<3>,
+    pub to: Track<3>,
+    pub vfov: Track<1>,
+    pub aperture: Track<1>,
+    pub focus_dist: Track<1>,
+}
+
+/// A sphere's keyframes as a track of each parameter.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(from = "Vec<SphereKeyframe>")]
+pub struct SphereTimeline {
+    pub center: Track<3>,
+    pub radius: Track<1>,
+}
+
+/// The storage buffers holding the scene, bound along with the environment map.
+pub struct Bindings {
+    pub bind_group: wgpu::BindGroup,
+    spheres: Upload,
+    lambertian: Upload,
+    metal: Upload,
+    dielectric: Upload,
+    volume: Upload,
+}
+
+/// A scene file being watched by `spawn`, which stops watching once this is dropped.
+pub struct Watch {
+    events: mpsc::Receiver<Result<Scene, LoadError>>,
+    stop: Arc<AtomicBool>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    UnknownMaterial(String),
+    TooManySpheres(usize),
+    TooManyMaterials(&'static str, usize),
+}
+
+// The layout of a scene file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default)]
+    camera: SceneCamera,
+    #[serde(default)]
+    materials: BTreeMap<String, MaterialDesc>,
+    #[serde(default)]
+    spheres: Vec<SphereDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum MaterialDesc {
+    Lambertian {
+        albedo: [f32; 3],
+        #[serde(default)]
+        emission: [f32; 3],
+    },
+    Metal {
+        albedo: [f32; 3],
+        #[serde(default)]
+        fuzz: f32,
+    },
+    Dielectric {
+        ior: f32,
+        #[serde(default)]
+        cauchy_b: f32,
+    },
+    Volume {
+        albedo: [f32; 3],
+        density: f32,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SphereDesc {
+    center: [f32; 3],
+    radius: f32,
+    material: String,
+    motion: Option<Motion>,
+    #[serde(default)]
+    keyframes: SphereTimeline,
+}
+
+// A buffer along with the bytes last written to it.
+struct Upload {
+    buffer: wgpu::Buffer,
+    bytes: Vec<u8>,
+}
+
+impl Scene {
+    /// Load and validate a scene file, or the built-in copy of a library scene that's missing.
+    pub fn load(path: &Path) -> Result<Self, LoadError> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) => match embedded(path) {
+                Some(text) => text.to_string(),
+                None => return Err(err.into()),
+            },
+        };
+        let file: SceneFile = toml::from_str(&text)?;
+
+        let mut scene = Scene {
+            camera: file.camera,
+            spheres: vec![],
+            lambertian: vec![],
+            metal: vec![],
+            dielectric: vec![],
+            volume: vec![],
+        };
+        let mut materials = HashMap::new();
+        for (name, desc) in file.materials {
+            let (kind, index) = match desc {
+                MaterialDesc::Lambertian { albedo, emission } => {
+                    scene.lambertian.push(Lambertian::with_emission(albedo.into(), emission.into()));
+                    (MaterialKind::Lambertian, scene.lambertian.len())
+                }
+                MaterialDesc::Metal { albedo, fuzz } => {
+                    scene.metal.push(Metal::new(albedo.into(), fuzz));
+                    (MaterialKind::Metal, scene.metal.len())
+                }
+                MaterialDesc::Dielectric { ior, cauchy_b } => {
+                    scene.dielectric.push(Dielectric::with_dispersion(ior, cauchy_b));
+                    (MaterialKind::Dielectric, scene.dielectric.len())
+                }
+                MaterialDesc::Volume { albedo, density } => {
+                    scene.volume.push(Volume::new(albedo.into(), density));
+                    (MaterialKind::Volume, scene.volume.len())
+                }
+            };
+            let index = index as u32 - 1;
+            materials.insert(name, MaterialInfo::new(kind, index));
+        }
+        for (kind, count) in &[
+            ("lambertian", scene.lambertian.len()),
+            ("metal", scene.metal.len()),
+            ("dielectric", scene.dielectric.len()),
+            ("volume", scene.volume.len()),
+        ] {
+            if *count > MAX_MATERIALS {
+                return Err(LoadError::TooManyMaterials(*kind, *count));
+            }
+        }
+
+        if file.spheres.len() > MAX_SPHERES {
+            return Err(LoadError::TooManySpheres(file.spheres.len()));
+        }
+        for desc in file.spheres {
+            let material = *materials
+                .get(&desc.material)
+                .ok_or(LoadError::UnknownMaterial(desc.material))?;
+            let sphere = Sphere {
+                center: desc.center,
+                radius: desc.radius,
+                material,
+            };
+            scene.spheres.push(SceneSphere {
+                sphere,
+                motion: desc.motion,
+                keyframes: desc.keyframes,
+            });
+        }
+        Ok(scene)
+    }
+
+    /// The spheres at the given time.
+    pub fn spheres(&self, time: f32) -> Vec<Sphere> {
+        self.spheres
+            .iter()
+            .map(|s| {
+                let keyframes = &s.keyframes;
+                let center = keyframes.center.sample(time).unwrap_or(s.sphere.center);
+                let offset = s.motion.map_or(Vec3::ZERO, |m| m.offset(time));
+                Sphere {
+                    center: (Vec3::from(center) + offset).into(),
+                    radius: keyframes.radius.sample(time).map_or(s.sphere.radius, |[r]| r),
+                    ..s.sphere
+                }
+            })
+            .collect()
+    }
+
+    /// The time of the last keyframe of the camera or any sphere, if there are any.
+    pub fn duration(&self) -> Option<f32> {
+        let spheres = self.spheres.iter().map(|s| s.keyframes.end());
+        timeline::latest(spheres.chain(Some(self.camera.keyframes.end())))
+    }
+}
+
+impl Default for SceneCamera {
+    fn default() -> Self {
+        SceneCamera {
+            from: [0.25, 1.125, 0.0],
+            to: [0.0, 1.0, -3.0],
+            vfov: 90.0,
+            aperture: 0.0,
+            focus_dist: None,
+            motion: None,
+            keyframes: Default::default(),
+            pinned: None,
+        }
+    }
+}
+
+impl SceneCamera {
+    /// Point the camera of the constants as described at `ShaderConstants::time`, along with the
+    /// lens parameters that are keyed.
+    pub fn aim(&self, push_constants: &mut ShaderConstants) {
+        let pc = push_constants;
+        let keyframes = &self.keyframes;
+        if let Some((from, to)) = self.pinned {
+            pc.camera_from = from;
+            pc.camera_to = to;
+        } else {
+            let from = keyframes.from.sample(pc.time).unwrap_or(self.from);
+            let offset = self.motion.map_or(Vec3::ZERO, |m| m.offset(pc.time));
+            pc.camera_from = (Vec3::from(from) + offset).into();
+            pc.camera_to = keyframes.to.sample(pc.time).unwrap_or(self.to);
+        }
+        if let Some([vfov]) = keyframes.vfov.sample(pc.time) {
+            pc.vfov = vfov.to_radians();
+        }
+        if let Some([aperture]) = keyframes.aperture.sample(pc.time) {
+            pc.aperture = aperture;
+        }
+        if let Some([focus_dist]) = keyframes.focus_dist.sample(pc.time) {
+            pc.focus_dist = focus_dist;
+        }
+    }
+
+    /// Reset the field of view, aperture and focus of the constants to those described, as well as
+    /// aiming the camera.
+    pub fn reset(&self, push_constants: &mut ShaderConstants) {
+        let distance = (Vec3::from(self.from) - Vec3::from(self.to)).length();
+        push_constants.vfov = self.vfov.to_radians();
+        push_constants.aperture = self.aperture;
+        push_constants.focus_dist = self.focus_dist.unwrap_or(distance);
+        self.aim(push_constants);
+    }
+}
+
+impl Motion {
+    /// The offset from the sphere's center at the given time.
+    pub fn offset(&self, time: f32) -> Vec3 {
+        let [ax, ay, az] = self.amplitude;
+        let [fx, fy, fz] = self.frequency;
+        let [px, py, pz] = self.phase;
+        vec3(
+            ax * (fx * time + px).sin(),
+            ay * (fy * time + py).sin(),
+            az * (fz * time + pz).sin(),
+        )
+    }
+}
+
+impl CameraKeyframe {
+    // The key of a parameter, if this keyframe sets it.
+    fn key<const N: usize>(&self, value: Option<[f32; N]>) -> Option<Key<N>> {
+        Some(Key {
+            time: self.time,
+            value: value?,
+            interpolation: self.interpolation,
+            ease: self.ease,
+        })
+    }
+}
+
+impl SphereKeyframe {
+    // The key of a parameter, if this keyframe sets it.
+    fn key<const N: usize>(&self, value: Option<[f32; N]>) -> Option<Key<N>> {
+        Some(Key {
+            time: self.time,
+            value: value?,
+            interpolation: self.interpolation,
+            ease: self.ease,
+        })
+    }
+}
+
+impl CameraTimeline {
+    /// The time of the last keyframe, if any.
+    pub fn end(&self) -> Option<f32> {
+        timeline::latest(vec![
+            self.from.end(),
+            self.to.end(),
+            self.vfov.end(),
+            self.aperture.end(),
+            self.focus_dist.end(),
+        ])
+    }
+}
+
+impl SphereTimeline {
+    /// The time of the last keyframe, if any.
+    pub fn end(&self) -> Option<f32> {
+        timeline::latest(vec![self.center.end(), self.radius.end()])
+    }
+}
+
+impl From<Vec<CameraKeyframe>> for CameraTimeline {
+    fn from(keyframes: Vec<CameraKeyframe>) -> Self {
+        let keyframes = keyframes.iter();
+        CameraTimeline {
+            from: keyframes.clone().filter_map(|k| k.key(k.from)).collect(),
+            to: keyframes.clone().filter_map(|k| k.key(k.to)).collect(),
+            vfov: keyframes.clone().filter_map(|k| k.key(k.vfov.map(|v| [v]))).collect(),
+            aperture: keyframes.clone().filter_map(|k| k.key(k.aperture.map(|a| [a]))).collect(),
+            focus_dist: keyframes.filter_map(|k| k.key(k.focus_dist.map(|d| [d]))).collect(),
+        }
+    }
+}
+
+impl From<Vec<SphereKeyframe>> for SphereTimeline {
+    fn from(keyframes: Vec<SphereKeyframe>) -> Self {
+        let keyframes = keyframes.iter();
+        SphereTimeline {
+            center: keyframes.clone().filter_map(|k| k.key(k.center)).collect(),
+            radius: keyframes.filter_map(|k| k.key(k.radius.map(|r| [r]))).collect(),
+        }
+    }
+}
+
+impl Bindings {
+    /// Create buffers with room for the largest scene, bound along with the environment map.
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        env_map: &env_map::Bindings,
+    ) -> Self {
+        let upload = |label, size| Upload {
+            buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size as u64,
+                usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            bytes: vec![],
+        };
+        let spheres = upload(
+            "nannou-raytracer-scene-spheres",
+            MAX_SPHERES * std::mem::size_of::<Sphere>(),
+        );
+        let lambertian = upload(
+            "nannou-raytracer-scene-lambertian",
+            MAX_MATERIALS * std::mem::size_of::<Lambertian>(),
+        );
+        let metal = upload(
+            "nannou-raytracer-scene-metal",
+            MAX_MATERIALS * std::mem::size_of::<Metal>(),
+        );
+        let dielectric = upload(
+            "nannou-raytracer-scene-dielectric",
+            MAX_MATERIALS * std::mem::size_of::<Dielectric>(),
+        );
+        let volume = upload(
+            "nannou-raytracer-scene-volume",
+            MAX_MATERIALS * std::mem::size_of::<Volume>(),
+        );
+        let bind_group = create_bind_group(
+            device,
+            layout,
+            env_map,
+            [
+                &spheres.buffer,
+                &lambertian.buffer,
+                &metal.buffer,
+                &dielectric.buffer,
+                &volume.buffer,
+            ],
+        );
+        Bindings {
+            bind_group,
+            spheres,
+            lambertian,
+            metal,
+            dielectric,
+            volume,
+        }
+    }
+
+    /// Bind a newly loaded environment map in place of the last.
+    pub fn set_env_map(
+        &mut self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        env_map: &env_map::Bindings,
+    ) {
+        let buffers = [
+            &self.spheres.buffer,
+            &self.lambertian.buffer,
+            &self.metal.buffer,
+            &self.dielectric.buffer,
+            &self.volume.buffer,
+        ];
+        self.bind_group = create_bind_group(device, layout, env_map, buffers);
+    }
+
+    /// Write the scene at the given time to the buffers whose contents changed, returning whether
+    /// any did.
+    pub fn upload(&mut self, queue: &wgpu::Queue, scene: &Scene, time: f32) -> bool {
+        let spheres = scene.spheres(time);
+        let mut changed = false;
+        changed |= self.spheres.write(queue, &spheres);
+        changed |= self.lambertian.write(queue, &scene.lambertian);
+        changed |= self.metal.write(queue, &scene.metal);
+        changed |= self.dielectric.write(queue, &scene.dielectric);
+        changed |= self.volume.write(queue, &scene.volume);
+        changed
+    }
+}
+
+impl Upload {
+    // Write `items` to the buffer unless they were the last written.
+    fn write<T: Pod>(&mut self, queue: &wgpu::Queue, items: &[T]) -> bool {
+        let bytes: &[u8] = bytemuck::cast_slice(items);
+        if bytes == &self.bytes[..] {
+            return false;
+        }
+        queue.write_buffer(&self.buffer, 0, bytes);
+        self.bytes = bytes.to_vec();
+        true
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "failed to read scene: {}", err),
+            LoadError::Parse(err) => write!(f, "failed to parse scene: {}", err),
+            LoadError::UnknownMaterial(name) => write!(f, "unknown material `{}`", name),
+            LoadError::TooManySpheres(count) => {
+                write!(f, "{} spheres, at most {} are supported", count, MAX_SPHERES)
+            }
+            LoadError::TooManyMaterials(kind, count) => write!(
+                f,
+                "{} {} materials, at most {} are supported",
+                count, kind, MAX_MATERIALS
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+    fn from(err: std::io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for LoadError {
+    fn from(err: toml::de::Error) -> Self {
+        LoadError::Parse(err)
+    }
+}
+
+fn ease_in_out() -> [f32; 4] {
+    timeline::EASE_IN_OUT
+}
+
+/// The path of a scene file named relative to the repository root, as in `LIBRARY`.
+///
+/// The repository is looked for from the working directory, then from the executable's directory
+/// up, and finally where the executable was built. Without one, the path stays relative, and
+/// `Scene::load` falls back to the built-in copies of the library.
+pub fn library_path(file: impl AsRef<Path>) -> PathBuf {
+    LIBRARY_ROOT.with(|root| root.join(file))
+}
+
+thread_local! {
+    // The directory holding the library, looked for on first use.
+    static LIBRARY_ROOT: PathBuf = find_library_root();
+}
+
+fn find_library_root() -> PathBuf {
+    let exe_dirs = std::env::current_exe()
+        .map(|exe| exe.ancestors().skip(1).map(Path::to_path_buf).collect())
+        .unwrap_or_else(|_| vec![]);
+    let build_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+    std::env::current_dir()
+        .into_iter()
+        .chain(exe_dirs)
+        .chain(Some(build_dir))
+        .find(|dir| dir.join(DEFAULT_SCENE).is_file())
+        .unwrap_or_default()
+}
+
+// The built-in copy of the library scene at `path`, if it is one.
+fn embedded(path: &Path) -> Option<&'static str> {
+    EMBEDDED
+        .iter()
+        .find(|(file, _)| library_path(file) == path)
+        .map(|&(_, text)| text)
+}
+
+/// Watch the scene file, reloading it on a background thread whenever it changes.
+pub fn spawn(path: PathBuf) -> Watch {
+    let (tx, events) = mpsc::channel();
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    std::thread::spawn(move || {
+        let mut watcher = Watcher::new(vec![path.clone()]);
+        while !stopped.load(Ordering::Relaxed) {
+            std::thread::sleep(POLL_INTERVAL);
+            if !watcher.changed() {
+                continue;
+            }
+            // Wait for editors to finish writing.
+            while watcher.changed() && !stopped.load(Ordering::Relaxed) {
+                std::thread::sleep(POLL_INTERVAL);
+            }
+            if stopped.load(Ordering::Relaxed) || tx.send(Scene::load(&path)).is_err() {
+                return;
+            }
+        }
+    });
+    Watch { events, stop }
+}
+
+impl Watch {
+    /// The scenes reloaded since the last call, or the errors loading them.
+    pub fn try_iter(&self) -> mpsc::TryIter<'_, Result<Scene, LoadError>> {
+        self.events.try_iter()
+    }
+}
+
+// Stop watching the file, ending the thread within `POLL_INTERVAL`.
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The layout of the scene bind group used by `main_fs`, `main_cs` and the wavefront kernels,
+/// binding the environment map followed by the spheres and each kind of material.
+pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let visibility = wgpu::ShaderStage::FRAGMENT | wgpu::ShaderStage::COMPUTE;
+    let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("nannou-raytracer-scene-bind-group-layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility,
+                ty: wgpu::BindingType::Sampler {
+                    filtering: false,
+                    comparison: false,
+                },
+                count: None,
+            },
+            storage_entry(2),
+            storage_entry(3),
+            storage_entry(4),
+            storage_entry(5),
+            storage_entry(6),
+            storage_entry(7),
+        ],
+    })
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    env_map: &env_map::Bindings,
+    [spheres, lambertian, metal, dielectric, volume]: [&wgpu::Buffer; 5],
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("nannou-raytracer-scene-bind-group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&env_map.texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&env_map.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: env_map.cdf_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: spheres.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: lambertian.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: metal.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: dielectric.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: volume.as_entire_binding(),
+            },
+        ],
+    })
+}
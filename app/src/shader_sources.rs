@@ -0,0 +1,62 @@
+//! The fingerprint of the shader's sources recorded next to the prebuilt SPIR-V module, so that
+//! `build.rs` refuses to embed a module built from older sources.
+//!
+//! Shared by `build.rs`, which checks the fingerprint, and `hot_reload`, which records it.
+
+use std::path::{Path, PathBuf};
+
+/// The prebuilt module in the repository root, embedded by default.
+pub const PREBUILT_MODULE: &str = "nannou-raytracer-shader.spv";
+
+/// The files and directories relative to the repository root that the shader is built from.
+pub const SOURCES: &[&str] = &[
+    "shader/Cargo.toml",
+    "shader/src",
+    "shared/Cargo.toml",
+    "shared/src",
+];
+
+/// The file holding the fingerprint of the sources `module` was built from.
+pub fn stamp_path(module: &Path) -> PathBuf {
+    let mut stamp = module.as_os_str().to_owned();
+    stamp.push(".sources");
+    PathBuf::from(stamp)
+}
+
+/// Hash the path and contents of every file in `SOURCES` under `root` with 64-bit FNV-1a.
+///
+/// Line endings are ignored, so checkouts converting them agree.
+pub fn fingerprint(root: &Path) -> std::io::Result<String> {
+    let mut files = vec![];
+    for source in SOURCES {
+        visit(root, Path::new(source), &mut files)?;
+    }
+    files.sort();
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for file in files {
+        let contents = std::fs::read(root.join(&file))?;
+        let name = file.to_string_lossy().replace('\\', "/");
+        for &byte in name.as_bytes().iter().chain(&[0]).chain(&contents) {
+            if byte != b'\r' {
+                hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+    }
+    Ok(format!("{:016x}", hash))
+}
+
+// Collect the files at `path` relative to `root`, skipping hidden and `target` directories.
+fn visit(root: &Path, path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if !root.join(path).is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(root.join(path))? {
+        let name = entry?.file_name();
+        let hidden = name.to_string_lossy().starts_with('.');
+        if !hidden && name != "target" {
+            visit(root, &path.join(name), files)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,142 @@
+//! Splitting the scene pass into tiles drawn over several frames.
+//!
+//! A single full screen draw with many rays and bounces can run long enough for the driver to
+//! reset the GPU. Drawing the image a few scissored tiles at a time keeps each submission short,
+//! while the scene texture persists between frames until every tile has been drawn.
+
+use serde::{Deserialize, Serialize};
+use shared::ShaderConstants;
+use std::ops::Range;
+
+/// User controls for tiled rendering.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub enabled: bool,
+    /// The width and height of each tile in pixels.
+    pub tile_size: u32,
+    /// The number of tiles drawn each frame.
+    pub tiles_per_frame: u32,
+}
+
+/// A region of the scene texture in pixels.
+#[derive(Copy, Clone, Debug)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+/// Tracks the progress through the tiles of the image being drawn.
+pub struct Tiler {
+    // The constants of the image being drawn, which changes to the GUI don't affect until the
+    // next image starts.
+    constants: ShaderConstants,
+    tile_size: u32,
+    tile_count: u32,
+    // The tiles to draw this frame.
+    tiles: Range<u32>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            enabled: false,
+            tile_size: 128,
+            tiles_per_frame: 8,
+        }
+    }
+}
+
+impl Default for Tiler {
+    fn default() -> Self {
+        Tiler {
+            constants: Default::default(),
+            tile_size: 1,
+            tile_count: 0,
+            tiles: 0..0,
+        }
+    }
+}
+
+impl Settings {
+    /// The tile size for an image of the given size, which is a single tile while disabled.
+    pub fn tile_size(&self, [w, h]: [u32; 2]) -> u32 {
+        match self.enabled {
+            true => self.tile_size.max(1),
+            false => w.max(h).max(1),
+        }
+    }
+
+    /// The number of tiles drawn per frame for an image of the given size.
+    pub fn tiles_per_frame(&self, size: [u32; 2]) -> u32 {
+        match self.enabled {
+            true => self.tiles_per_frame.max(1),
+            false => tile_count(size, self.tile_size(size)),
+        }
+    }
+}
+
+impl Tiler {
+    /// Whether every tile of the image has been drawn, so the next frame starts a new image.
+    pub fn finished(&self) -> bool {
+        self.tiles.end >= self.tile_count
+    }
+
+    /// The fraction of the image's tiles drawn by the end of this frame.
+    pub fn progress(&self) -> f32 {
+        match self.tile_count {
+            0 => 1.0,
+            count => self.tiles.end as f32 / count as f32,
+        }
+    }
+
+    /// Start drawing a new image with the given constants.
+    pub fn start(&mut self, settings: &Settings, constants: ShaderConstants) {
+        let size = constants.view_size_pixels;
+        self.constants = constants;
+        self.tile_size = settings.tile_size(size);
+        self.tile_count = tile_count(size, self.tile_size);
+        self.tiles = 0..0;
+    }
+
+    /// Select the tiles to draw this frame. Call once per frame after starting any new image.
+    pub fn advance(&mut self, settings: &Settings) {
+        let size = self.constants.view_size_pixels;
+        let start = self.tiles.end;
+        let end = start.saturating_add(settings.tiles_per_frame(size)).min(self.tile_count);
+        self.tiles = start..end;
+    }
+
+    /// The constants that the image is drawn with.
+    pub fn constants(&self) -> &ShaderConstants {
+        &self.constants
+    }
+
+    /// The tiles to draw this frame.
+    pub fn frame_tiles(&self) -> Vec<Tile> {
+        let size = self.constants.view_size_pixels;
+        self.tiles.clone().map(|i| tile(size, self.tile_size, i)).collect()
+    }
+}
+
+/// The number of tiles covering an image of the given size.
+pub fn tile_count([w, h]: [u32; 2], tile_size: u32) -> u32 {
+    let columns = (w + tile_size - 1) / tile_size;
+    let rows = (h + tile_size - 1) / tile_size;
+    columns * rows
+}
+
+/// The `index`th tile of an image of the given size in row-major order, clipped to the image.
+pub fn tile([w, h]: [u32; 2], tile_size: u32, index: u32) -> Tile {
+    let columns = (w + tile_size - 1) / tile_size;
+    let x = index % columns * tile_size;
+    let y = index / columns * tile_size;
+    Tile {
+        x,
+        y,
+        w: tile_size.min(w - x),
+        h: tile_size.min(h - y),
+    }
+}
@@ -0,0 +1,407 @@
+//! Keyframed animation of the scene, and the GUI's control over the time it's shown at.
+//!
+//! A `Track` animates a value of one or more components through keys at given times. Between two
+//! keys the value follows the first key's `Interpolation`, and before the first or after the last
+//! it holds that key's value. The scene file keys the camera and each sphere, see `scene`.
+
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::iter::FromIterator;
+
+/// The shortest scrubber for scenes without keyframes, whose time runs on without end.
+pub const DEFAULT_DURATION: f32 = 10.0;
+
+/// The timing of `Interpolation::Bezier` keys that don't give their own: ease in and out.
+pub const EASE_IN_OUT: [f32; 4] = [0.42, 0.0, 0.58, 1.0];
+
+/// How the value of a key changes into the next key's.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    /// At a constant rate.
+    Linear,
+    /// Along a Catmull-Rom spline through the keys, passing each with the slope between its
+    /// neighbours so that motion doesn't jolt at the keys.
+    CatmullRom,
+    /// In a straight line with the timing of a cubic Bézier curve from `(0, 0)` to `(1, 1)`
+    /// through the key's `ease` control points `[x1, y1, x2, y2]`, as CSS's `cubic-bezier`.
+    Bezier,
+}
+
+/// A value at a time of the animation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Key<const N: usize> {
+    pub time: f32,
+    pub value: [f32; N],
+    /// How the value changes until the next key.
+    pub interpolation: Interpolation,
+    /// The control points of `Interpolation::Bezier`.
+    pub ease: [f32; 4],
+}
+
+/// A value animated by keys, ordered by time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Track<const N: usize> {
+    keys: Vec<Key<N>>,
+}
+
+/// The time at which the scene's animation is shown, played back or scrubbed in the GUI.
+#[derive(Clone, Debug)]
+pub struct Playback {
+    /// Seconds from the start of the animation.
+    pub time: f32,
+    pub playing: bool,
+    /// Whether playing past the end of a keyframed animation starts over rather than pausing.
+    pub looping: bool,
+}
+
+impl Default for Interpolation {
+    fn default() -> Self {
+        Interpolation::Linear
+    }
+}
+
+impl<const N: usize> Default for Track<N> {
+    fn default() -> Self {
+        Track { keys: vec![] }
+    }
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Playback {
+            time: 0.0,
+            playing: true,
+            looping: true,
+        }
+    }
+}
+
+impl<const N: usize> Track<N> {
+    /// A track through the keys in any order. Keys at the same time keep their order, so the
+    /// value jumps from the first to the last.
+    pub fn new(mut keys: Vec<Key<N>>) -> Self {
+        keys.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(Ordering::Equal));
+        Track { keys }
+    }
+
+    /// The time of the last key, if any.
+    pub fn end(&self) -> Option<f32> {
+        self.keys.last().map(|key| key.time)
+    }
+
+    /// The value at the given time, or `None` if the track has no keys.
+    pub fn sample(&self, time: f32) -> Option<[f32; N]> {
+        let first = self.keys.first()?;
+        let last = self.keys.last()?;
+        if time <= first.time {
+            return Some(first.value);
+        }
+        if time >= last.time {
+            return Some(last.value);
+        }
+        // The key starting the segment `time` falls in, which isn't the last.
+        let i = self.keys.partition_point(|key| key.time <= time) - 1;
+        let (a, b) = (&self.keys[i], &self.keys[i + 1]);
+        let span = b.time - a.time;
+        let t = (time - a.time) / span;
+        let value = match a.interpolation {
+            Interpolation::Linear => lerp(a.value, b.value, t),
+            Interpolation::Bezier => lerp(a.value, b.value, ease(a.ease, t)),
+            Interpolation::CatmullRom => {
+                let (ma, mb) = (self.tangent(i), self.tangent(i + 1));
+                hermite(a.value, scale(ma, span), b.value, scale(mb, span), t)
+            }
+        };
+        Some(value)
+    }
+
+    // The rate of change of the value through key `i`, from its neighbours or the key itself at
+    // either end of the track.
+    fn tangent(&self, i: usize) -> [f32; N] {
+        let prev = &self.keys[i.saturating_sub(1)];
+        let next = &self.keys[(i + 1).min(self.keys.len() - 1)];
+        let span = next.time - prev.time;
+        if span <= 0.0 {
+            return [0.0; N];
+        }
+        scale(sub(next.value, prev.value), 1.0 / span)
+    }
+}
+
+impl<const N: usize> FromIterator<Key<N>> for Track<N> {
+    fn from_iter<I: IntoIterator<Item = Key<N>>>(keys: I) -> Self {
+        Track::new(keys.into_iter().collect())
+    }
+}
+
+impl Playback {
+    /// Move `dt` seconds on if playing. Past the `duration` of a keyframed animation it starts over
+    /// if looping, otherwise it pauses at the end. Without a duration time runs on freely.
+    pub fn advance(&mut self, dt: f32, duration: Option<f32>) {
+        if !self.playing {
+            return;
+        }
+        self.time += dt;
+        let duration = match duration {
+            Some(duration) => duration,
+            None => return,
+        };
+        if self.time > duration {
+            if self.looping && duration > 0.0 {
+                self.time %= duration;
+            } else {
+                self.time = duration;
+                self.playing = false;
+            }
+        }
+    }
+
+    /// Start playing, from the start if paused at the end of a keyframed animation.
+    pub fn play(&mut self, duration: Option<f32>) {
+        if duration.map_or(false, |duration| self.time >= duration) {
+            self.time = 0.0;
+        }
+        self.playing = true;
+    }
+}
+
+/// The latest of the times given, if any.
+pub fn latest(times: impl IntoIterator<Item = Option<f32>>) -> Option<f32> {
+    times
+        .into_iter()
+        .flatten()
+        .fold(None, |latest, time| match latest {
+            Some(latest) => Some(time.max(latest)),
+            None => Some(time),
+        })
+}
+
+// The height of the cubic Bézier timing curve through the control points `[x1, y1, x2, y2]` at
+// `t` along the horizontal axis. The control points' `x` are clamped to `0..=1`, which keeps the
+// curve a function of `x`.
+fn ease([x1, y1, x2, y2]: [f32; 4], t: f32) -> f32 {
+    let (x1, x2) = (x1.clamp(0.0, 1.0), x2.clamp(0.0, 1.0));
+    let bezier = |p1: f32, p2: f32, s: f32| {
+        let r = 1.0 - s;
+        3.0 * r * r * s * p1 + 3.0 * r * s * s * p2 + s * s * s
+    };
+    // `x` increases with the curve's parameter, so bisect for the parameter at `t`.
+    let (mut lo, mut hi) = (0.0, 1.0);
+    for _ in 0..24 {
+        let mid = 0.5 * (lo + hi);
+        if bezier(x1, x2, mid) < t {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    bezier(y1, y2, 0.5 * (lo + hi))
+}
+
+// The cubic Hermite spline from `p0` to `p1` with the tangents `m0` and `m1`, scaled to the
+// segment, at `t` in `0..=1`.
+fn hermite<const N: usize>(
+    p0: [f32; N],
+    m0: [f32; N],
+    p1: [f32; N],
+    m1: [f32; N],
+    t: f32,
+) -> [f32; N] {
+    let (t2, t3) = (t * t, t * t * t);
+    let h00 = 2.0 * t3 - 3.0 * t2 + 1.0;
+    let h10 = t3 - 2.0 * t2 + t;
+    let h01 = -2.0 * t3 + 3.0 * t2;
+    let h11 = t3 - t2;
+    let mut out = [0.0; N];
+    for (i, out) in out.iter_mut().enumerate() {
+        *out = h00 * p0[i] + h10 * m0[i] + h01 * p1[i] + h11 * m1[i];
+    }
+    out
+}
+
+fn lerp<const N: usize>(a: [f32; N], b: [f32; N], t: f32) -> [f32; N] {
+    let mut out = a;
+    for (out, b) in out.iter_mut().zip(b) {
+        *out += (b - *out) * t;
+    }
+    out
+}
+
+fn sub<const N: usize>(a: [f32; N], b: [f32; N]) -> [f32; N] {
+    let mut out = a;
+    for (out, b) in out.iter_mut().zip(b) {
+        *out -= b;
+    }
+    out
+}
+
+fn scale<const N: usize>(a: [f32; N], s: f32) -> [f32; N] {
+    let mut out = a;
+    for out in out.iter_mut() {
+        *out *= s;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!(
+            (a - b).abs() <= EPSILON,
+            "{} differs from {} by more than {}",
+            a,
+            b,
+            EPSILON
+        );
+    }
+
+    // A track of one component through `(time, value)` keys, all with the same interpolation.
+    fn track(interpolation: Interpolation, keys: &[(f32, f32)]) -> Track<1> {
+        keys.iter()
+            .map(|&(time, value)| Key {
+                time,
+                value: [value],
+                interpolation,
+                ease: EASE_IN_OUT,
+            })
+            .collect()
+    }
+
+    fn sample(track: &Track<1>, time: f32) -> f32 {
+        track.sample(time).unwrap()[0]
+    }
+
+    const KINDS: [Interpolation; 3] = [
+        Interpolation::Linear,
+        Interpolation::CatmullRom,
+        Interpolation::Bezier,
+    ];
+
+    #[test]
+    fn empty_track_has_no_value() {
+        assert_eq!(Track::<1>::default().sample(0.0), None);
+        assert_eq!(Track::<1>::default().end(), None);
+    }
+
+    #[test]
+    fn keys_are_sorted_by_time() {
+        let track = track(Interpolation::Linear, &[(2.0, 1.0), (0.0, 0.0)]);
+        assert_eq!(track.end(), Some(2.0));
+        assert_close(sample(&track, 1.0), 0.5);
+    }
+
+    #[test]
+    fn values_at_keys() {
+        let keys = [(0.0, 1.0), (1.0, 3.0), (3.0, -2.0)];
+        for &kind in &KINDS {
+            let track = track(kind, &keys);
+            for &(time, value) in &keys {
+                assert_close(sample(&track, time), value);
+            }
+        }
+    }
+
+    #[test]
+    fn values_clamp_outside_the_keys() {
+        for &kind in &KINDS {
+            let track = track(kind, &[(1.0, 2.0), (2.0, 5.0)]);
+            assert_close(sample(&track, -10.0), 2.0);
+            assert_close(sample(&track, 0.999), 2.0);
+            assert_close(sample(&track, 2.001), 5.0);
+            assert_close(sample(&track, 100.0), 5.0);
+        }
+        let single = track(Interpolation::CatmullRom, &[(1.0, 4.0)]);
+        assert_close(sample(&single, 0.0), 4.0);
+        assert_close(sample(&single, 2.0), 4.0);
+    }
+
+    #[test]
+    fn linear_between_keys() {
+        let track = track(Interpolation::Linear, &[(0.0, 0.0), (2.0, 1.0), (3.0, 0.0)]);
+        assert_close(sample(&track, 0.5), 0.25);
+        assert_close(sample(&track, 1.5), 0.75);
+        assert_close(sample(&track, 2.5), 0.5);
+    }
+
+    #[test]
+    fn catmull_rom_between_keys() {
+        // Keys along a line are followed exactly, at any spacing.
+        let line = track(
+            Interpolation::CatmullRom,
+            &[(0.0, 0.0), (1.0, 2.0), (4.0, 8.0)],
+        );
+        for &time in &[0.25, 0.5, 2.0, 3.5] {
+            assert_close(sample(&line, time), 2.0 * time);
+        }
+        // Through a peak, the slope at the peak is zero and the ends take the slope to their
+        // neighbour, so the curve overshoots the straight line on the way up.
+        let peak = track(
+            Interpolation::CatmullRom,
+            &[(0.0, 0.0), (1.0, 1.0), (2.0, 0.0)],
+        );
+        assert_close(sample(&peak, 0.5), 0.625);
+        assert_close(sample(&peak, 1.5), 0.625);
+    }
+
+    #[test]
+    fn bezier_between_keys() {
+        let track = track(Interpolation::Bezier, &[(0.0, 0.0), (2.0, 4.0)]);
+        // Easing in and out is symmetric about the middle, slow at the ends.
+        assert_close(sample(&track, 1.0), 2.0);
+        assert!(sample(&track, 0.5) < 1.0);
+        assert!(sample(&track, 1.5) > 3.0);
+        assert_close(sample(&track, 0.5) + sample(&track, 1.5), 4.0);
+    }
+
+    #[test]
+    fn ease_in_out_is_monotonic_from_0_to_1() {
+        assert_close(ease(EASE_IN_OUT, 0.0), 0.0);
+        assert_close(ease(EASE_IN_OUT, 1.0), 1.0);
+        let mut last = 0.0;
+        for i in 1..=100 {
+            let y = ease(EASE_IN_OUT, i as f32 / 100.0);
+            assert!(y >= last, "ease fell from {} to {} at {}", last, y, i);
+            last = y;
+        }
+    }
+
+    #[test]
+    fn playback_loops_at_the_duration() {
+        let mut playback = Playback::default();
+        playback.advance(4.0, Some(3.0));
+        assert_close(playback.time, 1.0);
+        assert!(playback.playing);
+    }
+
+    #[test]
+    fn playback_stops_at_the_duration() {
+        let mut playback = Playback {
+            looping: false,
+            ..Default::default()
+        };
+        playback.advance(4.0, Some(3.0));
+        assert_close(playback.time, 3.0);
+        assert!(!playback.playing);
+        // Playing again starts over.
+        playback.play(Some(3.0));
+        assert_close(playback.time, 0.0);
+        assert!(playback.playing);
+    }
+
+    #[test]
+    fn playback_runs_on_without_keyframes() {
+        let mut playback = Playback::default();
+        playback.advance(25.0, None);
+        playback.advance(5.0, None);
+        assert_close(playback.time, 30.0);
+        assert!(playback.playing);
+        playback.playing = false;
+        playback.advance(1.0, None);
+        assert_close(playback.time, 30.0);
+    }
+}
@@ -0,0 +1,61 @@
+//! Polling files for changes.
+//!
+//! Comparing modification times every so often is plenty for reloading on save, and avoids
+//! depending on each platform's file notification API.
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Tracks the files under a set of paths.
+pub struct Watcher {
+    paths: Vec<PathBuf>,
+    stamps: Vec<(PathBuf, SystemTime)>,
+}
+
+impl Watcher {
+    /// Watch the given files, and every file within the given directories.
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        let stamps = snapshot(&paths);
+        Watcher { paths, stamps }
+    }
+
+    /// Whether any watched file was added, removed or modified since the last call.
+    pub fn changed(&mut self) -> bool {
+        let stamps = snapshot(&self.paths);
+        let changed = stamps != self.stamps;
+        self.stamps = stamps;
+        changed
+    }
+}
+
+// The modification time of every file under `paths`, skipping hidden and `target` directories.
+fn snapshot(paths: &[PathBuf]) -> Vec<(PathBuf, SystemTime)> {
+    let mut stamps = vec![];
+    for path in paths {
+        visit(path, &mut stamps);
+    }
+    stamps.sort();
+    stamps
+}
+
+fn visit(path: &Path, stamps: &mut Vec<(PathBuf, SystemTime)>) {
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return,
+    };
+    if metadata.is_dir() {
+        let entries = match std::fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name != "target" && !name.starts_with('.') {
+                visit(&entry.path(), stamps);
+            }
+        }
+    } else if let Ok(modified) = metadata.modified() {
+        stamps.push((path.to_path_buf(), modified));
+    }
+}
@@ -0,0 +1,220 @@
+//! Tracing the scene with the wavefront kernels described in `shared::wavefront`.
+//!
+//! Each tile is traced a wavefront of at most `CAPACITY` pixels at a time. The kernels of each
+//! bounce are dispatched indirectly, sized by `wavefront_dispatch_cs` from the number of paths left
+//! in the queue they read, so once every path of a sample has ended the remaining bounces dispatch
+//! no workgroups.
+
+use crate::compute::ComputeRenderer;
+use crate::shaders;
+use crate::tiles::Tile;
+use nannou::wgpu;
+use shared::wavefront::{self, PathState, WavefrontConstants, WORKGROUP_SIZE};
+use shared::ShaderConstants;
+
+pub struct Wavefront {
+    clear: wgpu::ComputePipeline,
+    dispatch: wgpu::ComputePipeline,
+    generate: wgpu::ComputePipeline,
+    extend: wgpu::ComputePipeline,
+    // One pipeline per `MaterialKind`.
+    shade: [wgpu::ComputePipeline; 4],
+    shadow: wgpu::ComputePipeline,
+    finish: wgpu::ComputePipeline,
+    // Binds the path states, queues and dispatch arguments, following the bind groups of
+    // `main_cs`.
+    bind_group: wgpu::BindGroup,
+    _paths: wgpu::Buffer,
+    _queues: wgpu::Buffer,
+    dispatch_args: wgpu::Buffer,
+}
+
+impl Wavefront {
+    /// The kernels share `main_cs`'s bind groups, including its storage images, which
+    /// `wavefront_finish_cs` writes in its place.
+    pub fn new(
+        device: &wgpu::Device,
+        shader_mod: &wgpu::ShaderModule,
+        scene_layout: &wgpu::BindGroupLayout,
+        accum_layout: &wgpu::BindGroupLayout,
+        compute: &ComputeRenderer,
+    ) -> Self {
+        let buffer_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("nannou-raytracer-wavefront-bind-group-layout"),
+            entries: &[buffer_entry(0), buffer_entry(1), buffer_entry(2)],
+        });
+        let paths = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("nannou-raytracer-wavefront-paths"),
+            size: wavefront::CAPACITY as u64 * std::mem::size_of::<PathState>() as u64,
+            usage: wgpu::BufferUsage::STORAGE,
+            mapped_at_creation: false,
+        });
+        let queues = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("nannou-raytracer-wavefront-queues"),
+            size: wavefront::QUEUE_BUFFER_LEN as u64 * std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsage::STORAGE,
+            mapped_at_creation: false,
+        });
+        let dispatch_args = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("nannou-raytracer-wavefront-dispatch-args"),
+            size: wavefront::DISPATCH_ARGS_LEN as u64 * std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::INDIRECT,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("nannou-raytracer-wavefront-bind-group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: paths.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: queues.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: dispatch_args.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("nannou-raytracer-wavefront-pipeline-layout"),
+            bind_group_layouts: &[
+                scene_layout,
+                accum_layout,
+                compute.bind_group_layout(),
+                &bind_group_layout,
+            ],
+            push_constant_ranges: &[wgpu::PushConstantRange {
+                stages: wgpu::ShaderStage::COMPUTE,
+                range: 0..std::mem::size_of::<WavefrontConstants>() as u32,
+            }],
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("nannou-raytracer-wavefront-pipeline"),
+                layout: Some(&pipeline_layout),
+                module: shader_mod,
+                entry_point,
+            })
+        };
+
+        Wavefront {
+            clear: pipeline(shaders::wavefront_clear_cs),
+            dispatch: pipeline(shaders::wavefront_dispatch_cs),
+            generate: pipeline(shaders::wavefront_generate_cs),
+            extend: pipeline(shaders::wavefront_extend_cs),
+            shade: [
+                pipeline(shaders::wavefront_shade_lambertian_cs),
+                pipeline(shaders::wavefront_shade_metal_cs),
+                pipeline(shaders::wavefront_shade_dielectric_cs),
+                pipeline(shaders::wavefront_shade_volume_cs),
+            ],
+            shadow: pipeline(shaders::wavefront_shadow_cs),
+            finish: pipeline(shaders::wavefront_finish_cs),
+            bind_group,
+            _paths: paths,
+            _queues: queues,
+            dispatch_args,
+        }
+    }
+
+    /// Ray trace the given `tiles` of the scene into the targets bound by `images`, the bind group
+    /// of `main_cs`'s storage images, leaving the rest of the textures as they are.
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        scene_bind_group: &wgpu::BindGroup,
+        accum_bind_group: &wgpu::BindGroup,
+        images: &wgpu::BindGroup,
+        push_constants: &ShaderConstants,
+        tiles: &[Tile],
+    ) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("nannou-raytracer-wavefront-pass"),
+        });
+        compute_pass.set_bind_group(0, scene_bind_group, &[]);
+        compute_pass.set_bind_group(1, accum_bind_group, &[]);
+        compute_pass.set_bind_group(2, images, &[]);
+        compute_pass.set_bind_group(3, &self.bind_group, &[]);
+        for tile in tiles {
+            let scene = ShaderConstants {
+                compute_region: [tile.x, tile.y, tile.w, tile.h],
+                ..*push_constants
+            };
+            let pixels = tile.w * tile.h;
+            for first_path in (0..pixels).step_by(wavefront::CAPACITY as usize) {
+                let path_count = (pixels - first_path).min(wavefront::CAPACITY);
+                let groups = (path_count + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+                let mut constants = WavefrontConstants {
+                    scene,
+                    first_path,
+                    path_count,
+                    ..Default::default()
+                };
+                for sample in 0..push_constants.rays_per_pixel {
+                    // Camera rays are written to the queue read by the first bounce.
+                    constants.sample = sample;
+                    constants.parity = 1;
+                    dispatch(&mut compute_pass, &self.clear, &constants, 1);
+                    dispatch(&mut compute_pass, &self.generate, &constants, groups);
+                    dispatch(&mut compute_pass, &self.dispatch, &constants, 1);
+                    for bounce in 0..=push_constants.ray_bounce_limit {
+                        constants.parity = bounce % 2;
+                        dispatch(&mut compute_pass, &self.clear, &constants, 1);
+                        let rays_in = constants.rays_in();
+                        self.dispatch_queue(&mut compute_pass, &self.extend, &constants, rays_in);
+                        dispatch(&mut compute_pass, &self.dispatch, &constants, 1);
+                        for (shade, queue) in self.shade.iter().zip(wavefront::LAMBERTIAN..) {
+                            self.dispatch_queue(&mut compute_pass, shade, &constants, queue);
+                        }
+                        // Also sizes the next bounce's extension by the rays queued here.
+                        dispatch(&mut compute_pass, &self.dispatch, &constants, 1);
+                        let shadow = wavefront::SHADOW;
+                        self.dispatch_queue(&mut compute_pass, &self.shadow, &constants, shadow);
+                    }
+                }
+                dispatch(&mut compute_pass, &self.finish, &constants, groups);
+            }
+        }
+    }
+
+    // Dispatch a kernel over the paths in `queue`, as counted by the last `wavefront_dispatch_cs`.
+    fn dispatch_queue<'a>(
+        &'a self,
+        compute_pass: &mut wgpu::ComputePass<'a>,
+        pipeline: &'a wgpu::ComputePipeline,
+        constants: &WavefrontConstants,
+        queue: u32,
+    ) {
+        compute_pass.set_pipeline(pipeline);
+        compute_pass.set_push_constants(0, bytemuck::bytes_of(constants));
+        let offset = 3 * queue as u64 * std::mem::size_of::<u32>() as u64;
+        compute_pass.dispatch_indirect(&self.dispatch_args, offset);
+    }
+}
+
+fn dispatch<'a>(
+    compute_pass: &mut wgpu::ComputePass<'a>,
+    pipeline: &'a wgpu::ComputePipeline,
+    constants: &WavefrontConstants,
+    groups: u32,
+) {
+    compute_pass.set_pipeline(pipeline);
+    let pc_bytes = bytemuck::bytes_of(constants);
+    compute_pass.set_push_constants(0, pc_bytes);
+    compute_pass.dispatch(groups, 1, 1);
+}
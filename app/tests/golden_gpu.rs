@@ -0,0 +1,350 @@
+//! Golden image test rendering the canonical scenes with `main_cs` and comparing them with the
+//! CPU reference render of `shared`.
+//!
+//! Needs a Vulkan adapter with push constants and the app's limits, such as lavapipe selected via
+//! `VK_ICD_FILENAMES` on a machine without a GPU. Without one the test reports that it was skipped
+//! and passes.
+
+#[path = "../../shared/tests/common/mod.rs"]
+mod common;
+
+use common::GoldenScene;
+use glam::Vec3;
+use nannou::wgpu;
+use nannou::wgpu::util::DeviceExt;
+use shared::ShaderConstants;
+use std::num::NonZeroU32;
+use std::path::Path;
+
+const SHADER_SPV: &[u8] = include_bytes!(env!("NANNOU_RAYTRACER_SHADER_SPV"));
+
+// The GPU follows the same sample sequences as the CPU, but the odd path still diverges through
+// differences in floating point precision, and the radiance is stored as half floats.
+const TOLERANCE: f64 = 0.04;
+
+// The width and height of the workgroups declared by `main_cs`.
+const WORKGROUP_SIZE: u32 = 8;
+
+const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+#[test]
+fn gpu_matches_cpu() {
+    let (device, queue) = match request_device() {
+        Some(device) => device,
+        None => {
+            eprintln!("skipping: no adapter with the app's limits and push constants, e.g. lavapipe");
+            return;
+        }
+    };
+    let renderer = Renderer::new(&device);
+    let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    let mut failures = vec![];
+    for scene in common::scenes() {
+        let expected = scene.to_image(&scene.render_cpu());
+        let actual = scene.to_image(&renderer.render(&device, &queue, &scene));
+        if let Err(failure) = common::compare(scene.name, "gpu", &actual, &expected, TOLERANCE, &out_dir) {
+            failures.push(failure);
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+fn request_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(wgpu::BackendBit::VULKAN);
+    let adapter = futures::executor::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::default(),
+        compatible_surface: None,
+    }))?;
+    if !adapter.features().contains(wgpu::Features::PUSH_CONSTANTS) {
+        return None;
+    }
+    let limits = wgpu::Limits {
+        max_push_constant_size: shared::layout::MAX_PUSH_CONSTANT_SIZE as u32,
+        max_storage_buffers_per_shader_stage:
+            shared::layout::MAX_STORAGE_BUFFERS_PER_SHADER_STAGE,
+        ..Default::default()
+    };
+    let supported = adapter.limits();
+    if supported.max_storage_buffers_per_shader_stage < limits.max_storage_buffers_per_shader_stage
+        || supported.max_push_constant_size < limits.max_push_constant_size
+    {
+        return None;
+    }
+    let desc = wgpu::DeviceDescriptor {
+        label: Some("nannou-raytracer-golden-device"),
+        features: wgpu::Features::PUSH_CONSTANTS,
+        limits,
+    };
+    futures::executor::block_on(adapter.request_device(&desc, None)).ok()
+}
+
+// `main_cs` with the bind group layouts of the app, see `scene`, `accumulate` and `compute`.
+struct Renderer {
+    pipeline: wgpu::ComputePipeline,
+    scene_layout: wgpu::BindGroupLayout,
+    accum_layout: wgpu::BindGroupLayout,
+    output_layout: wgpu::BindGroupLayout,
+}
+
+impl Renderer {
+    fn new(device: &wgpu::Device) -> Self {
+        let visibility = wgpu::ShaderStage::COMPUTE;
+        let texture = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let sampler = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Sampler {
+                filtering: false,
+                comparison: false,
+            },
+            count: None,
+        };
+        let storage = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let storage_texture = |binding, format| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let layout = |entries: &[wgpu::BindGroupLayoutEntry]| {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor { label: None, entries })
+        };
+        let scene_layout = layout(&[
+            texture(0),
+            sampler(1),
+            storage(2),
+            storage(3),
+            storage(4),
+            storage(5),
+            storage(6),
+            storage(7),
+        ]);
+        let accum_layout = layout(&[sampler(0), texture(1), texture(2)]);
+        let output_layout = layout(&[
+            storage_texture(0, HDR_FORMAT),
+            storage_texture(1, wgpu::TextureFormat::Rgba32Float),
+            storage_texture(2, wgpu::TextureFormat::Rgba16Float),
+            storage_texture(3, wgpu::TextureFormat::Rgba32Float),
+        ]);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&scene_layout, &accum_layout, &output_layout],
+            push_constant_ranges: &[wgpu::PushConstantRange {
+                stages: wgpu::ShaderStage::COMPUTE,
+                range: 0..std::mem::size_of::<ShaderConstants>() as u32,
+            }],
+        });
+        let spirv = wgpu::util::make_spirv(SHADER_SPV);
+        let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("nannou-raytracer-shader"),
+            source: spirv,
+            flags: wgpu::ShaderFlags::default(),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: "main_cs",
+        });
+        Renderer {
+            pipeline,
+            scene_layout,
+            accum_layout,
+            output_layout,
+        }
+    }
+
+    // Trace the scene without accumulation or an environment map and read back its radiance.
+    fn render(&self, device: &wgpu::Device, queue: &wgpu::Queue, scene: &GoldenScene) -> Vec<Vec3> {
+        let [w, h] = scene.constants.view_size_pixels;
+        let constants = ShaderConstants {
+            compute_region: [0, 0, w, h],
+            accumulate: 0,
+            ..scene.constants
+        };
+        let texture = |size, format, usage| {
+            wgpu::TextureBuilder::new()
+                .size(size)
+                .format(format)
+                .usage(usage)
+                .build(device)
+        };
+        // Bindings can't be empty, so scenes without a kind of material get a buffer of zeros.
+        let buffer = |contents: &[u8]| {
+            let zeros = [0; 16];
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: if contents.is_empty() { &zeros } else { contents },
+                usage: wgpu::BufferUsage::STORAGE,
+            })
+        };
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+        let sampled = wgpu::TextureUsage::SAMPLED;
+        let env_map = texture([1, 1], wgpu::TextureFormat::Rgba32Float, sampled);
+        let accum_color = texture([1, 1], wgpu::TextureFormat::Rgba16Float, sampled);
+        let accum_moments = texture([1, 1], wgpu::TextureFormat::Rgba16Float, sampled);
+        let storage = wgpu::TextureUsage::STORAGE | wgpu::TextureUsage::COPY_SRC;
+        let outputs = [
+            texture([w, h], HDR_FORMAT, storage),
+            texture([w, h], wgpu::TextureFormat::Rgba32Float, storage),
+            texture([w, h], wgpu::TextureFormat::Rgba16Float, storage),
+            texture([w, h], wgpu::TextureFormat::Rgba32Float, storage),
+        ];
+        let env_cdf = buffer(bytemuck::cast_slice(&[1.0f32]));
+        let spheres = buffer(bytemuck::cast_slice(&scene.spheres));
+        let lambertian = buffer(bytemuck::cast_slice(&scene.lambertian));
+        let metal = buffer(bytemuck::cast_slice(&scene.metal));
+        let dielectric = buffer(bytemuck::cast_slice(&scene.dielectric));
+        let volume = buffer(bytemuck::cast_slice(&scene.volume));
+
+        let env_view = env_map.view().build();
+        let scene_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.scene_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&env_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: env_cdf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: spheres.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: lambertian.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: metal.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: dielectric.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: volume.as_entire_binding(),
+                },
+            ],
+        });
+        let accum_views = [accum_color.view().build(), accum_moments.view().build()];
+        let accum_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.accum_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&accum_views[0]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&accum_views[1]),
+                },
+            ],
+        });
+        let output_views: Vec<_> = outputs.iter().map(|texture| texture.view().build()).collect();
+        let output_entries: Vec<_> = output_views
+            .iter()
+            .enumerate()
+            .map(|(binding, view)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: wgpu::BindingResource::TextureView(view),
+            })
+            .collect();
+        let output_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.output_layout,
+            entries: &output_entries,
+        });
+
+        // Rows of `Rgba16Float` texels, padded to the alignment required of copies.
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = (w * 8 + align - 1) / align * align;
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: padded_bytes_per_row as u64 * h as u64,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &scene_bind_group, &[]);
+            pass.set_bind_group(1, &accum_bind_group, &[]);
+            pass.set_bind_group(2, &output_bind_group, &[]);
+            pass.set_push_constants(0, bytemuck::bytes_of(&constants));
+            let groups = |n| (n + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+            pass.dispatch(groups(w), groups(h), 1);
+        }
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &outputs[0],
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: NonZeroU32::new(h),
+                },
+            },
+            wgpu::Extent3d {
+                width: w,
+                height: h,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let slice = readback.slice(..);
+        let mapped = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        futures::executor::block_on(mapped).expect("failed to map the readback buffer");
+        let bytes = slice.get_mapped_range();
+        let half = |b: &[u8]| exr::prelude::f16::from_bits(u16::from_le_bytes([b[0], b[1]])).to_f32();
+        bytes
+            .chunks(padded_bytes_per_row as usize)
+            .flat_map(|row| row[..(w * 8) as usize].chunks_exact(8))
+            .map(|t| Vec3::new(half(&t[0..2]), half(&t[2..4]), half(&t[4..6])))
+            .collect()
+    }
+}
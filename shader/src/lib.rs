@@ -5,8 +5,17 @@
     register_attr(spirv)
 )]
 
-use shared::{color, Camera, Dielectric, Lambertian, MaterialInfo, MaterialKind, Materials, Metal, Rng, ShaderConstants, Sphere};
-use spirv_std::glam::{vec2, vec3, vec4, Vec2, Vec4};
+use shared::accumulate;
+use shared::aov;
+use shared::debug::{self, PixelStats};
+use shared::denoise::{self, DenoiseConstants, Surface};
+use shared::environment::{EnvMap, EnvMapDistribution, Environments};
+use shared::sky::PreethamSky;
+use shared::tonemap::{srgb_to_linear, tonemap};
+use shared::wavefront::{self, PathState, WavefrontConstants};
+use shared::{trace_pixel, Camera, Dielectric, Hit, Lambertian, Material, MaterialKind, Materials, Metal, PathTermination, ShaderConstants, Sphere, Volume, World};
+use spirv_std::glam::{uvec2, vec2, vec3, vec4, UVec3, Vec2, Vec3, Vec4};
+use spirv_std::{image::Image2d, Image, Sampler};
 
 // Note: This cfg is incorrect on its surface, it really should be "are we compiling with std", but
 // we tie #[no_std] above to the same condition, so it's fine.
@@ -15,145 +24,908 @@ use spirv_std::num_traits::Float;
 #[cfg(not(target_arch = "spirv"))]
 use spirv_std::macros::spirv;
 
+/// The radiance and albedo targets written by `main_cs`.
+pub type Rgba16fImage = Image!(2D, format=rgba16f, sampled=false);
+/// The normal and depth, and material targets written by `main_cs`.
+pub type Rgba32fImage = Image!(2D, format=rgba32f, sampled=false);
+
 #[spirv(fragment)]
 pub fn main_fs(
     #[spirv(frag_coord)]
     in_frag_coord: Vec4,
     #[spirv(push_constant)]
     constants: &ShaderConstants,
+    #[spirv(descriptor_set = 0, binding = 0)]
+    env_map: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 1)]
+    env_sampler: &Sampler,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)]
+    env_cdf: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)]
+    spheres: &[Sphere],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)]
+    lambertian: &[Lambertian],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 5)]
+    metal: &[Metal],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 6)]
+    dielectric: &[Dielectric],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 7)]
+    volume: &[Volume],
+    #[spirv(descriptor_set = 1, binding = 0)]
+    accum_sampler: &Sampler,
+    #[spirv(descriptor_set = 1, binding = 1)]
+    accum_color: &Image2d,
+    #[spirv(descriptor_set = 1, binding = 2)]
+    accum_moments: &Image2d,
     output: &mut Vec4,
+    out_normal_depth: &mut Vec4,
+    out_albedo: &mut Vec4,
+    out_material: &mut Vec4,
 ) {
-    // Calc uv coords (i.e. left 0.0, right 1.0, bottom 0.0, top 1.0);
     let frag_coord = vec2(in_frag_coord.x, in_frag_coord.y);
+    render_pixel(
+        constants,
+        frag_coord,
+        scene_world(constants, spheres),
+        scene_materials(lambertian, metal, dielectric, volume),
+        env_map,
+        *env_sampler,
+        env_cdf,
+        *accum_sampler,
+        accum_color,
+        accum_moments,
+        output,
+        out_normal_depth,
+        out_albedo,
+        out_material,
+    );
+}
+
+/// Ray traces the pixels of `ShaderConstants::compute_region` like `main_fs`, writing the same
+/// outputs to storage images instead of render targets.
+#[spirv(compute(threads(8, 8)))]
+pub fn main_cs(
+    #[spirv(global_invocation_id)]
+    id: UVec3,
+    #[spirv(push_constant)]
+    constants: &ShaderConstants,
+    #[spirv(descriptor_set = 0, binding = 0)]
+    env_map: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 1)]
+    env_sampler: &Sampler,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)]
+    env_cdf: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)]
+    spheres: &[Sphere],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)]
+    lambertian: &[Lambertian],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 5)]
+    metal: &[Metal],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 6)]
+    dielectric: &[Dielectric],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 7)]
+    volume: &[Volume],
+    #[spirv(descriptor_set = 1, binding = 0)]
+    accum_sampler: &Sampler,
+    #[spirv(descriptor_set = 1, binding = 1)]
+    accum_color: &Image2d,
+    #[spirv(descriptor_set = 1, binding = 2)]
+    accum_moments: &Image2d,
+    #[spirv(descriptor_set = 2, binding = 0)]
+    output: &Rgba16fImage,
+    #[spirv(descriptor_set = 2, binding = 1)]
+    out_normal_depth: &Rgba32fImage,
+    #[spirv(descriptor_set = 2, binding = 2)]
+    out_albedo: &Rgba16fImage,
+    #[spirv(descriptor_set = 2, binding = 3)]
+    out_material: &Rgba32fImage,
+) {
+    let [x, y, w, h] = constants.compute_region;
+    if id.x >= w || id.y >= h {
+        return;
+    }
+    let pixel = uvec2(x + id.x, y + id.y);
+    let frag_coord = vec2(pixel.x as f32 + 0.5, pixel.y as f32 + 0.5);
+    let mut color = Vec4::ZERO;
+    let mut normal_depth = Vec4::ZERO;
+    let mut albedo = Vec4::ZERO;
+    let mut material = Vec4::ZERO;
+    render_pixel(
+        constants,
+        frag_coord,
+        scene_world(constants, spheres),
+        scene_materials(lambertian, metal, dielectric, volume),
+        env_map,
+        *env_sampler,
+        env_cdf,
+        *accum_sampler,
+        accum_color,
+        accum_moments,
+        &mut color,
+        &mut normal_depth,
+        &mut albedo,
+        &mut material,
+    );
+    unsafe {
+        output.write(pixel, color);
+        out_normal_depth.write(pixel, normal_depth);
+        out_albedo.write(pixel, albedo);
+        out_material.write(pixel, material);
+    }
+}
+
+/// Trace the rays of the pixel at `frag_coord` and write its radiance and AOVs.
+#[allow(clippy::too_many_arguments)]
+fn render_pixel(
+    constants: &ShaderConstants,
+    frag_coord: Vec2,
+    world: World,
+    materials: Materials,
+    env_map: &Image2d,
+    env_sampler: Sampler,
+    env_cdf: &[f32],
+    accum_sampler: Sampler,
+    accum_color: &Image2d,
+    accum_moments: &Image2d,
+    output: &mut Vec4,
+    out_normal_depth: &mut Vec4,
+    out_albedo: &mut Vec4,
+    out_material: &mut Vec4,
+) {
+    // Calc uv coords (i.e. left 0.0, right 1.0, bottom 0.0, top 1.0);
+    let [w_px, h_px] = constants.view_size_pixels;
+    let cam = constants.camera();
+
+    // Pixels that have converged stop taking samples while accumulating.
+    let px = frag_coord - Vec2::splat(0.5);
+    let mut prev_color = Vec4::ZERO;
+    let converged = pixel_converged(constants, px, accum_sampler, accum_color, accum_moments, &mut prev_color);
+    let rays_per_pixel = if converged { 0 } else { constants.rays_per_pixel };
+
+    let env = scene_environment(constants, env_map, env_sampler, env_cdf);
+
+    // Cast some rays and average their result.
+    let mut stats = PixelStats::default();
+    let mut col = trace_pixel(constants, &cam, frag_coord, rays_per_pixel, world, materials, env, &mut stats);
+    if converged {
+        col = prev_color.truncate();
+    }
+
+    // Find the surface seen through the pixel centre for the AOVs and debug modes.
+    let uv = vec2(frag_coord.x / w_px as f32, (h_px as f32 - frag_coord.y) / h_px as f32);
+    let first_hit = aov::first_hit(&cam.pinhole_ray(uv), world, materials);
+
+    if constants.debug_mode != debug::OFF {
+        let max_tests = world.test_cost() * (constants.ray_bounce_limit + 1);
+        col = debug::visualize(constants.debug_mode, col, &first_hit, &stats, constants.ray_bounce_limit, max_tests);
+    }
+
+    // Write the result.
+    *output = vec4(col.x, col.y, col.z, 1.0);
+
+    // Write the AOVs, also used to guide the denoiser.
+    let [kind, index] = first_hit.material_id();
+    let mean_bounces = stats.bounces as f32 / rays_per_pixel.max(1) as f32;
+    *out_normal_depth = first_hit.normal.extend(first_hit.depth);
+    *out_albedo = first_hit.albedo.extend(1.0);
+    *out_material = vec4(kind as f32, index as f32, mean_bounces, 1.0);
+}
+
+/// Whether the pixel at `px` has converged while accumulating, writing its accumulated colour to
+/// `prev_color`.
+fn pixel_converged(
+    constants: &ShaderConstants,
+    px: Vec2,
+    accum_sampler: Sampler,
+    accum_color: &Image2d,
+    accum_moments: &Image2d,
+    prev_color: &mut Vec4,
+) -> bool {
     let [w_px, h_px] = constants.view_size_pixels;
+    let size = vec2(w_px as f32, h_px as f32);
+    *prev_color = texel(accum_color, accum_sampler, px, size);
+    constants.accumulate != 0
+        && constants.accumulated_frames > 0
+        && accumulate::converged(*prev_color, texel(accum_moments, accum_sampler, px, size), constants.adaptive_threshold)
+}
+
+/// Clears the counters of every queue but the rays read by this bounce of the wavefront.
+#[spirv(compute(threads(8)))]
+pub fn wavefront_clear_cs(
+    #[spirv(global_invocation_id)]
+    id: UVec3,
+    #[spirv(push_constant)]
+    constants: &WavefrontConstants,
+    #[spirv(storage_buffer, descriptor_set = 3, binding = 1)]
+    queues: &mut [u32],
+) {
+    if id.x < wavefront::QUEUE_COUNT && id.x != constants.rays_in() {
+        queues[id.x as usize] = 0;
+    }
+}
 
-    let time = constants.time;
-    let vfov = constants.vfov;
-    let aperture = constants.aperture;
-    let aspect = w_px as f32 / h_px as f32;
-    let from = vec3((time * 0.77).cos() * 0.125 + 0.125, 1.0 + time.sin() * 0.125 + 0.125, 0.0);
-    let to = vec3(0.0, 1.0, -3.0);
-    let vup = vec3(0.0, 1.0, 0.0);
-    let focus_dist = (from - to).length() - 0.25; // subtract a little to get sphere surface.
-    let cam = Camera::new(from, to, vup, vfov, aspect, aperture, focus_dist);
-
-    let seed = frag_coord + Vec2::splat(constants.rng_seed_offset);
-    let mut rng = Rng { seed };
-
-    let materials = Materials {
-        lambertian: [
-            Lambertian::new(vec3(0.8 + (constants.time * 1.7).sin() + 0.5, 0.3, 0.3)),
-            Lambertian::new(vec3(1.0, 0.1, 0.1)),
-            Lambertian::new(vec3(0.1, 1.0, 0.1)),
-            Lambertian::new(vec3(0.9, 0.9, 0.9)),
-            Lambertian::new(vec3(5.0, 5.0, 5.0)),
-        ],
-        metal: [
-            Metal::new(vec3(0.8, 0.6, 0.2), 0.0),
-            Metal::new(vec3(0.8, 0.6, 0.2), 0.05),
-            Metal::new(vec3(0.2, 0.6, 0.8), 0.05),
-            Metal::new(vec3(0.9, 0.9, 0.9), 0.5),
-        ],
-        dielectric: [
-            Dielectric::new(1.5)
-        ],
+/// Sizes the indirect dispatches over each queue by its counter, so the kernels of a queue that
+/// is empty, as every queue is once all paths have ended, dispatch no workgroups.
+#[spirv(compute(threads(8)))]
+pub fn wavefront_dispatch_cs(
+    #[spirv(global_invocation_id)]
+    id: UVec3,
+    #[spirv(storage_buffer, descriptor_set = 3, binding = 1)]
+    queues: &[u32],
+    #[spirv(storage_buffer, descriptor_set = 3, binding = 2)]
+    dispatch_args: &mut [u32],
+) {
+    if id.x < wavefront::QUEUE_COUNT {
+        wavefront::dispatch_args(queues, id.x, dispatch_args);
+    }
+}
+
+/// Starts a sample of each pixel of the wavefront that hasn't converged, queueing its camera ray.
+#[spirv(compute(threads(64)))]
+pub fn wavefront_generate_cs(
+    #[spirv(global_invocation_id)]
+    id: UVec3,
+    #[spirv(push_constant)]
+    constants: &WavefrontConstants,
+    #[spirv(descriptor_set = 1, binding = 0)]
+    accum_sampler: &Sampler,
+    #[spirv(descriptor_set = 1, binding = 1)]
+    accum_color: &Image2d,
+    #[spirv(descriptor_set = 1, binding = 2)]
+    accum_moments: &Image2d,
+    #[spirv(storage_buffer, descriptor_set = 3, binding = 0)]
+    paths: &mut [PathState],
+    #[spirv(storage_buffer, descriptor_set = 3, binding = 1)]
+    queues: &mut [u32],
+) {
+    if id.x >= constants.path_count {
+        return;
+    }
+    let pc = &constants.scene;
+    let pixel = wavefront::path_pixel(constants, id.x);
+    let px = vec2(pixel[0] as f32, pixel[1] as f32);
+    let mut prev_color = Vec4::ZERO;
+    if pixel_converged(pc, px, *accum_sampler, accum_color, accum_moments, &mut prev_color) {
+        return;
+    }
+    paths[id.x as usize].generate(pc, &pc.camera(), pixel, constants.sample);
+    wavefront::push(queues, constants.rays_out(), id.x);
+}
+
+/// Intersects the queued rays with the world, queueing each hit by the kind of its material.
+#[spirv(compute(threads(64)))]
+pub fn wavefront_extend_cs(
+    #[spirv(global_invocation_id)]
+    id: UVec3,
+    #[spirv(push_constant)]
+    constants: &WavefrontConstants,
+    #[spirv(descriptor_set = 0, binding = 0)]
+    env_map: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 1)]
+    env_sampler: &Sampler,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)]
+    env_cdf: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)]
+    spheres: &[Sphere],
+    #[spirv(storage_buffer, descriptor_set = 3, binding = 0)]
+    paths: &mut [PathState],
+    #[spirv(storage_buffer, descriptor_set = 3, binding = 1)]
+    queues: &mut [u32],
+) {
+    let mut path = 0;
+    if !wavefront::queued(queues, constants.rays_in(), id.x, &mut path) {
+        return;
+    }
+    let pc = &constants.scene;
+    let world = scene_world(pc, spheres);
+    let env = scene_environment(pc, env_map, *env_sampler, env_cdf);
+    let mut queue = 0;
+    if paths[path as usize].extend(world, env, &mut queue) {
+        wavefront::push(queues, queue, path);
+    }
+}
+
+/// Shades the queued hits on `Lambertian` materials, which also sample the environment.
+#[spirv(compute(threads(64)))]
+pub fn wavefront_shade_lambertian_cs(
+    #[spirv(global_invocation_id)]
+    id: UVec3,
+    #[spirv(push_constant)]
+    constants: &WavefrontConstants,
+    #[spirv(descriptor_set = 0, binding = 0)]
+    env_map: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 1)]
+    env_sampler: &Sampler,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)]
+    env_cdf: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)]
+    lambertian: &[Lambertian],
+    #[spirv(storage_buffer, descriptor_set = 3, binding = 0)]
+    paths: &mut [PathState],
+    #[spirv(storage_buffer, descriptor_set = 3, binding = 1)]
+    queues: &mut [u32],
+) {
+    let mut path = 0;
+    if !wavefront::queued(queues, wavefront::LAMBERTIAN, id.x, &mut path) {
+        return;
+    }
+    let pc = &constants.scene;
+    let state = &mut paths[path as usize];
+    let material = lambertian[state.material_index as usize];
+    let env = scene_environment(pc, env_map, *env_sampler, env_cdf);
+    shade(constants, state, material, MaterialKind::Lambertian, env, true, queues, path);
+}
+
+/// Shades the queued hits on `Metal` materials.
+#[spirv(compute(threads(64)))]
+pub fn wavefront_shade_metal_cs(
+    #[spirv(global_invocation_id)]
+    id: UVec3,
+    #[spirv(push_constant)]
+    constants: &WavefrontConstants,
+    #[spirv(descriptor_set = 0, binding = 0)]
+    env_map: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 1)]
+    env_sampler: &Sampler,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)]
+    env_cdf: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 5)]
+    metal: &[Metal],
+    #[spirv(storage_buffer, descriptor_set = 3, binding = 0)]
+    paths: &mut [PathState],
+    #[spirv(storage_buffer, descriptor_set = 3, binding = 1)]
+    queues: &mut [u32],
+) {
+    let mut path = 0;
+    if !wavefront::queued(queues, wavefront::METAL, id.x, &mut path) {
+        return;
+    }
+    let pc = &constants.scene;
+    let state = &mut paths[path as usize];
+    let material = metal[state.material_index as usize];
+    let env = scene_environment(pc, env_map, *env_sampler, env_cdf);
+    shade(constants, state, material, MaterialKind::Metal, env, false, queues, path);
+}
+
+/// Shades the queued hits on `Dielectric` materials.
+#[spirv(compute(threads(64)))]
+pub fn wavefront_shade_dielectric_cs(
+    #[spirv(global_invocation_id)]
+    id: UVec3,
+    #[spirv(push_constant)]
+    constants: &WavefrontConstants,
+    #[spirv(descriptor_set = 0, binding = 0)]
+    env_map: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 1)]
+    env_sampler: &Sampler,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)]
+    env_cdf: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 6)]
+    dielectric: &[Dielectric],
+    #[spirv(storage_buffer, descriptor_set = 3, binding = 0)]
+    paths: &mut [PathState],
+    #[spirv(storage_buffer, descriptor_set = 3, binding = 1)]
+    queues: &mut [u32],
+) {
+    let mut path = 0;
+    if !wavefront::queued(queues, wavefront::DIELECTRIC, id.x, &mut path) {
+        return;
+    }
+    let pc = &constants.scene;
+    let state = &mut paths[path as usize];
+    let material = dielectric[state.material_index as usize];
+    let env = scene_environment(pc, env_map, *env_sampler, env_cdf);
+    shade(constants, state, material, MaterialKind::Dielectric, env, false, queues, path);
+}
+
+/// Shades the queued hits on `Volume` materials.
+#[spirv(compute(threads(64)))]
+pub fn wavefront_shade_volume_cs(
+    #[spirv(global_invocation_id)]
+    id: UVec3,
+    #[spirv(push_constant)]
+    constants: &WavefrontConstants,
+    #[spirv(descriptor_set = 0, binding = 0)]
+    env_map: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 1)]
+    env_sampler: &Sampler,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)]
+    env_cdf: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 7)]
+    volume: &[Volume],
+    #[spirv(storage_buffer, descriptor_set = 3, binding = 0)]
+    paths: &mut [PathState],
+    #[spirv(storage_buffer, descriptor_set = 3, binding = 1)]
+    queues: &mut [u32],
+) {
+    let mut path = 0;
+    if !wavefront::queued(queues, wavefront::VOLUME, id.x, &mut path) {
+        return;
+    }
+    let pc = &constants.scene;
+    let state = &mut paths[path as usize];
+    let material = volume[state.material_index as usize];
+    let env = scene_environment(pc, env_map, *env_sampler, env_cdf);
+    shade(constants, state, material, MaterialKind::Volume, env, false, queues, path);
+}
+
+/// Traces the queued shadow rays towards the environment.
+#[spirv(compute(threads(64)))]
+pub fn wavefront_shadow_cs(
+    #[spirv(global_invocation_id)]
+    id: UVec3,
+    #[spirv(push_constant)]
+    constants: &WavefrontConstants,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)]
+    spheres: &[Sphere],
+    #[spirv(storage_buffer, descriptor_set = 3, binding = 0)]
+    paths: &mut [PathState],
+    #[spirv(storage_buffer, descriptor_set = 3, binding = 1)]
+    queues: &mut [u32],
+) {
+    let mut path = 0;
+    if !wavefront::queued(queues, wavefront::SHADOW, id.x, &mut path) {
+        return;
+    }
+    paths[path as usize].trace_shadow(scene_world(&constants.scene, spheres));
+}
+
+/// Writes the mean radiance of each pixel of the wavefront and its AOVs, as `main_cs` does.
+#[spirv(compute(threads(64)))]
+pub fn wavefront_finish_cs(
+    #[spirv(global_invocation_id)]
+    id: UVec3,
+    #[spirv(push_constant)]
+    constants: &WavefrontConstants,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)]
+    spheres: &[Sphere],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)]
+    lambertian: &[Lambertian],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 5)]
+    metal: &[Metal],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 6)]
+    dielectric: &[Dielectric],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 7)]
+    volume: &[Volume],
+    #[spirv(descriptor_set = 1, binding = 0)]
+    accum_sampler: &Sampler,
+    #[spirv(descriptor_set = 1, binding = 1)]
+    accum_color: &Image2d,
+    #[spirv(descriptor_set = 1, binding = 2)]
+    accum_moments: &Image2d,
+    #[spirv(descriptor_set = 2, binding = 0)]
+    output: &Rgba16fImage,
+    #[spirv(descriptor_set = 2, binding = 1)]
+    out_normal_depth: &Rgba32fImage,
+    #[spirv(descriptor_set = 2, binding = 2)]
+    out_albedo: &Rgba16fImage,
+    #[spirv(descriptor_set = 2, binding = 3)]
+    out_material: &Rgba32fImage,
+    #[spirv(storage_buffer, descriptor_set = 3, binding = 0)]
+    paths: &mut [PathState],
+) {
+    if id.x >= constants.path_count {
+        return;
+    }
+    let pc = &constants.scene;
+    let pixel = wavefront::path_pixel(constants, id.x);
+    let px = vec2(pixel[0] as f32, pixel[1] as f32);
+    let mut prev_color = Vec4::ZERO;
+    let rays_per_pixel = pc.rays_per_pixel.max(1) as f32;
+    let mut mean_bounces = 0.0;
+    let col = if pixel_converged(pc, px, *accum_sampler, accum_color, accum_moments, &mut prev_color) {
+        prev_color.truncate()
+    } else {
+        let state = &mut paths[id.x as usize];
+        state.finish_sample(pc.radiance_clamp);
+        mean_bounces = state.pixel_bounces as f32 / rays_per_pixel;
+        Vec3::from(state.pixel_radiance) / rays_per_pixel
     };
 
-    let world = [
-        Sphere {
-            center: to,
-            radius: 0.5,
-            material: MaterialInfo {
-                kind: MaterialKind::Metal,
-                index: 0,
+    let [w_px, h_px] = pc.view_size_pixels;
+    let cam = pc.camera();
+    let uv = vec2((px.x + 0.5) / w_px as f32, (h_px as f32 - px.y - 0.5) / h_px as f32);
+    let world = scene_world(pc, spheres);
+    let materials = scene_materials(lambertian, metal, dielectric, volume);
+    let first_hit = aov::first_hit(&cam.pinhole_ray(uv), world, materials);
+    let [kind, index] = first_hit.material_id();
+    let pixel = uvec2(pixel[0], pixel[1]);
+    unsafe {
+        output.write(pixel, col.extend(1.0));
+        out_normal_depth.write(pixel, first_hit.normal.extend(first_hit.depth));
+        out_albedo.write(pixel, first_hit.albedo.extend(1.0));
+        out_material.write(pixel, vec4(kind as f32, index as f32, mean_bounces, 1.0));
+    }
+}
+
+/// Scatter a queued path off the material it hit, queueing its next ray and any shadow ray.
+#[allow(clippy::too_many_arguments)]
+fn shade(
+    constants: &WavefrontConstants,
+    state: &mut PathState,
+    material: impl Copy + Material,
+    kind: MaterialKind,
+    env: Environments,
+    sample_env: bool,
+    queues: &mut [u32],
+    path: u32,
+) {
+    let termination = PathTermination {
+        bounce_limit: constants.scene.ray_bounce_limit,
+        roulette_depth: constants.scene.roulette_depth,
+    };
+    let mut shadow = false;
+    if state.shade(material, kind, termination, env, sample_env, &mut shadow) {
+        wavefront::push(queues, constants.rays_out(), path);
+    }
+    if shadow {
+        wavefront::push(queues, wavefront::SHADOW, path);
+    }
+}
+
+/// The spheres of the scene uploaded by the app.
+fn scene_world<'a>(constants: &ShaderConstants, spheres: &'a [Sphere]) -> World<'a> {
+    World {
+        spheres,
+        count: constants.sphere_count,
+    }
+}
+
+/// The materials of the scene uploaded by the app, indexed by the spheres' `MaterialInfo`.
+fn scene_materials<'a>(
+    lambertian: &'a [Lambertian],
+    metal: &'a [Metal],
+    dielectric: &'a [Dielectric],
+    volume: &'a [Volume],
+) -> Materials<'a> {
+    Materials {
+        lambertian,
+        metal,
+        dielectric,
+        volume,
+    }
+}
+
+/// The environment lighting the scene, as configured by the constants.
+fn scene_environment<'a>(
+    constants: &ShaderConstants,
+    env_map: &'a Image2d,
+    env_sampler: Sampler,
+    env_cdf: &'a [f32],
+) -> Environments<'a> {
+    let [env_w, env_h] = constants.env_map_size;
+    Environments {
+        kind: constants.environment,
+        sky: PreethamSky::new(
+            constants.sun_elevation,
+            constants.sun_azimuth,
+            constants.turbidity,
+            constants.env_intensity,
+        ),
+        map: EnvMap {
+            image: env_map,
+            sampler: env_sampler,
+            distribution: EnvMapDistribution {
+                cdf: env_cdf,
+                width: env_w,
+                height: env_h,
             },
+            rotation: constants.env_rotation,
+            intensity: constants.env_intensity,
         },
-        Sphere {
-            center: vec3(to.x - 1.0, 0.0, to.z + 1.0),
-            radius: 0.5,
-            material: MaterialInfo {
-                kind: MaterialKind::Dielectric,
-                index: 0,
+    }
+}
+
+/// Adds the radiance written by `main_fs` to the accumulation of each pixel that hasn't converged.
+#[spirv(fragment)]
+pub fn accumulate_fs(
+    #[spirv(frag_coord)]
+    in_frag_coord: Vec4,
+    #[spirv(push_constant)]
+    constants: &ShaderConstants,
+    #[spirv(descriptor_set = 0, binding = 0)]
+    sampler: &Sampler,
+    #[spirv(descriptor_set = 0, binding = 1)]
+    color: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 2)]
+    prev_color: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 3)]
+    prev_moments: &Image2d,
+    out_color: &mut Vec4,
+    out_moments: &mut Vec4,
+) {
+    let [w_px, h_px] = constants.view_size_pixels;
+    let size = vec2(w_px as f32, h_px as f32);
+    let px = vec2(in_frag_coord.x, in_frag_coord.y) - Vec2::splat(0.5);
+    let frame = texel(color, *sampler, px, size).truncate();
+    if constants.accumulated_frames == 0 {
+        accumulate::add_frame(Vec4::ZERO, Vec4::ZERO, frame, constants.rays_per_pixel, out_color, out_moments);
+        return;
+    }
+    let prev_color = texel(prev_color, *sampler, px, size);
+    let prev_moments = texel(prev_moments, *sampler, px, size);
+    if accumulate::converged(prev_color, prev_moments, constants.adaptive_threshold) {
+        *out_color = prev_color;
+        *out_moments = prev_moments;
+    } else {
+        accumulate::add_frame(prev_color, prev_moments, frame, constants.rays_per_pixel, out_color, out_moments);
+    }
+}
+
+/// Accumulates the demodulated illumination and its moments over time, reprojecting last frame's
+/// history onto the current surfaces.
+#[spirv(fragment)]
+pub fn denoise_temporal_fs(
+    #[spirv(frag_coord)]
+    in_frag_coord: Vec4,
+    #[spirv(push_constant)]
+    constants: &DenoiseConstants,
+    #[spirv(descriptor_set = 0, binding = 0)]
+    sampler: &Sampler,
+    #[spirv(descriptor_set = 0, binding = 1)]
+    color: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 2)]
+    normal_depth: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 3)]
+    albedo: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 4)]
+    prev_illumination: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 5)]
+    prev_moments: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 6)]
+    prev_normal_depth: &Image2d,
+    out_illumination: &mut Vec4,
+    out_moments: &mut Vec4,
+) {
+    let [w_px, h_px] = constants.view_size_pixels;
+    let size = vec2(w_px as f32, h_px as f32);
+    let px = vec2(in_frag_coord.x, in_frag_coord.y) - Vec2::splat(0.5);
+    let sampler = *sampler;
+
+    let center = surface(texel(normal_depth, sampler, px, size));
+    let illumination = demodulated(color, albedo, sampler, px, size);
+
+    // Estimate the moments spatially for use while the history is short.
+    let mut spatial_moments = Vec2::ZERO;
+    let mut count = 0.0;
+    for y in -1..2 {
+        for x in -1..2 {
+            let tap = px + vec2(x as f32, y as f32);
+            if in_bounds(tap, size) {
+                let l = denoise::luminance(demodulated(color, albedo, sampler, tap, size));
+                spatial_moments += vec2(l, l * l);
+                count += 1.0;
             }
-        },
-        Sphere {
-            center: vec3(1.0, 0.0, -1.0 + (constants.time * 1.32).cos()),
-            radius: 0.5,
-            material: MaterialInfo {
-                kind: MaterialKind::Metal,
-                index: 2,
-            },
-        },
+        }
+    }
+    spatial_moments /= count;
 
-        // Light
-        Sphere {
-            center: to + vec3(
-                        (constants.time * 0.67).sin(),
-                        (constants.time * 0.33).cos(),
-                        (constants.time * 0.57).cos(),
-                    ),
-            radius: 0.1,
-            material: MaterialInfo {
-                kind: MaterialKind::Lambertian,
-                index: 3,
+    // Find the pixel last frame that saw the same point and bilinearly filter the history there,
+    // skipping taps that belong to a different surface.
+    let mut history_illumination = Vec3::ZERO;
+    let mut history_moments = Vec3::ZERO;
+    let mut history_weight = 0.0;
+    if constants.history_valid != 0 && center.is_hit() {
+        let aspect = size.x / size.y;
+        let camera = |from, to, vfov| Camera::pinhole(Vec3::from(from), Vec3::from(to), vfov, aspect);
+        let cam = camera(constants.camera_from, constants.camera_to, constants.vfov);
+        let prev_cam = camera(constants.prev_camera_from, constants.prev_camera_to, constants.prev_vfov);
+        let uv = vec2(in_frag_coord.x / size.x, (size.y - in_frag_coord.y) / size.y);
+        let dir = shared::unit_vector(cam.pinhole_ray(uv).direction());
+        let prev_uv = prev_cam.project(cam.origin + dir * center.depth);
+        let prev_px = vec2(prev_uv.x * size.x, size.y - prev_uv.y * size.y) - Vec2::splat(0.5);
+        let base = prev_px.floor();
+        let f = prev_px - base;
+        for y in 0..2 {
+            for x in 0..2 {
+                let tap = base + vec2(x as f32, y as f32);
+                let w = (if x == 0 { 1.0 - f.x } else { f.x }) * (if y == 0 { 1.0 - f.y } else { f.y });
+                if in_bounds(tap, size)
+                    && denoise::consistent(center, surface(texel(prev_normal_depth, sampler, tap, size)))
+                {
+                    history_illumination += texel(prev_illumination, sampler, tap, size).truncate() * w;
+                    history_moments += texel(prev_moments, sampler, tap, size).truncate() * w;
+                    history_weight += w;
+                }
             }
-        },
+        }
+    }
 
-        // Floor
-        Sphere {
-            center: vec3(0.0, -1000.5, -1.0),
-            radius: 1000.0,
-            material: MaterialInfo {
-                kind: MaterialKind::Lambertian,
-                index: 3,
-            },
-        },
+    let history_length = if history_weight > 0.01 {
+        history_illumination /= history_weight;
+        history_moments /= history_weight;
+        history_moments.z
+    } else {
+        0.0
+    };
 
-        // Left wall.
-        Sphere {
-            center: vec3(-22.0, 0.0, -1.0),
-            radius: 20.0,
-            material: MaterialInfo {
-                kind: MaterialKind::Lambertian,
-                index: 1,
-            },
-        },
+    let a = denoise::blend_weight(history_length, constants.alpha);
+    let illumination = history_illumination * (1.0 - a) + illumination * a;
+    let mut variance = 0.0;
+    let moments = denoise::integrate_moments(
+        history_moments.truncate(),
+        history_length,
+        denoise::luminance(illumination),
+        spatial_moments,
+        constants.moments_alpha,
+        &mut variance,
+    );
+    let history_length = (history_length + 1.0).min(denoise::MAX_HISTORY_LENGTH);
 
-        // Right wall.
-        Sphere {
-            center: vec3(22.0, 0.0, -1.0),
-            radius: 20.0,
-            material: MaterialInfo {
-                kind: MaterialKind::Lambertian,
-                index: 2,
-            },
-        },
+    *out_illumination = illumination.extend(variance);
+    *out_moments = vec4(moments.x, moments.y, history_length, 1.0);
+}
 
-        // Back wall.
-        Sphere {
-            center: vec3(0.0, 0.0, -24.0),
-            radius: 20.0,
-            material: MaterialInfo {
-                kind: MaterialKind::Lambertian,
-                index: 3,
-            },
-        },
-    ];
+/// A single edge-avoiding à-trous wavelet iteration over the illumination and its variance.
+#[spirv(fragment)]
+pub fn denoise_atrous_fs(
+    #[spirv(frag_coord)]
+    in_frag_coord: Vec4,
+    #[spirv(push_constant)]
+    constants: &DenoiseConstants,
+    #[spirv(descriptor_set = 0, binding = 0)]
+    sampler: &Sampler,
+    #[spirv(descriptor_set = 0, binding = 1)]
+    illumination: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 2)]
+    normal_depth: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 3)]
+    albedo: &Image2d,
+    output: &mut Vec4,
+) {
+    let [w_px, h_px] = constants.view_size_pixels;
+    let size = vec2(w_px as f32, h_px as f32);
+    let px = vec2(in_frag_coord.x, in_frag_coord.y) - Vec2::splat(0.5);
+    let sampler = *sampler;
 
-    // Cast some rays and average their result.
-    let mut col = vec3(0.0, 0.0, 0.0);
-    for _ in 0..constants.rays_per_pixel {
-        let uv = vec2(
-            (frag_coord.x + rng.gen()) / w_px as f32,
-            ((h_px as f32 - frag_coord.y) + rng.gen()) / h_px as f32,
-        );
-        let ray = cam.ray(&mut rng, uv);
-        col += color(constants.ray_bounce_limit, &mut rng, ray, world, &materials);
+    let center_illumination = texel(illumination, sampler, px, size);
+    let center = surface(texel(normal_depth, sampler, px, size));
+    let mut result = center_illumination;
+    if center.is_hit() {
+        // Prefilter the variance with a 3x3 gaussian to steady the luminance edge-stopping.
+        let mut variance = 0.0;
+        for y in -1i32..2 {
+            for x in -1i32..2 {
+                let tap = px + vec2(x as f32, y as f32);
+                let k = [0.25, 0.125, 0.0625][(x.abs() + y.abs()) as usize];
+                if in_bounds(tap, size) {
+                    variance += texel(illumination, sampler, tap, size).w * k;
+                }
+            }
+        }
+        let std_dev = variance.max(0.0).sqrt();
+
+        // Use the smaller one-sided difference per axis so that silhouettes don't inflate it.
+        let depth_at = |offset: Vec2| {
+            let tap = px + offset;
+            if in_bounds(tap, size) {
+                (surface(texel(normal_depth, sampler, tap, size)).depth - center.depth).abs()
+            } else {
+                core::f32::MAX
+            }
+        };
+        let gradient_x = depth_at(vec2(1.0, 0.0)).min(depth_at(vec2(-1.0, 0.0)));
+        let gradient_y = depth_at(vec2(0.0, 1.0)).min(depth_at(vec2(0.0, -1.0)));
+        let depth_gradient = gradient_x.max(gradient_y).min(center.depth);
+
+        let center_luminance = denoise::luminance(center_illumination.truncate());
+        let step = constants.step_size as f32;
+        let mut sum = Vec3::ZERO;
+        let mut sum_variance = 0.0;
+        let mut sum_weight = 0.0;
+        for y in -2i32..3 {
+            for x in -2i32..3 {
+                let offset = vec2(x as f32, y as f32);
+                let tap = px + offset * step;
+                if in_bounds(tap, size) {
+                    let k = denoise::KERNEL[x.abs() as usize] * denoise::KERNEL[y.abs() as usize];
+                    let tap_illumination = texel(illumination, sampler, tap, size);
+                    let w = if x == 0 && y == 0 {
+                        k
+                    } else {
+                        k * denoise::edge_stopping_weight(
+                            center,
+                            surface(texel(normal_depth, sampler, tap, size)),
+                            center_luminance,
+                            denoise::luminance(tap_illumination.truncate()),
+                            depth_gradient,
+                            offset.length() * step,
+                            std_dev,
+                            constants,
+                        )
+                    };
+                    sum += tap_illumination.truncate() * w;
+                    sum_variance += tap_illumination.w * w * w;
+                    sum_weight += w;
+                }
+            }
+        }
+        result = (sum / sum_weight).extend(sum_variance / (sum_weight * sum_weight));
     }
-    col /= constants.rays_per_pixel as f32;
 
-    // Write the result.
-    *output = vec4(col.x, col.y, col.z, 1.0);
+    if constants.remodulate != 0 {
+        let albedo = texel(albedo, sampler, px, size).truncate();
+        result = denoise::remodulate(result.truncate(), albedo).extend(1.0);
+    }
+    *output = result;
+}
+
+// Fetch the texel at the integer pixel coordinate `px` via a nearest sampler.
+fn texel(image: &Image2d, sampler: Sampler, px: Vec2, size: Vec2) -> Vec4 {
+    image.sample_by_lod(sampler, (px + Vec2::splat(0.5)) / size, 0.0)
+}
+
+fn in_bounds(px: Vec2, size: Vec2) -> bool {
+    px.x >= 0.0 && px.y >= 0.0 && px.x < size.x && px.y < size.y
+}
+
+fn surface(normal_depth: Vec4) -> Surface {
+    Surface {
+        normal: normal_depth.truncate(),
+        depth: normal_depth.w,
+    }
+}
+
+fn demodulated(color: &Image2d, albedo: &Image2d, sampler: Sampler, px: Vec2, size: Vec2) -> Vec3 {
+    let c = texel(color, sampler, px, size).truncate();
+    let a = texel(albedo, sampler, px, size).truncate();
+    denoise::demodulate(c, a)
+}
+
+/// Tone maps the raw HDR output of `main_fs` for display.
+#[spirv(fragment)]
+pub fn post_fs(
+    #[spirv(frag_coord)]
+    in_frag_coord: Vec4,
+    #[spirv(push_constant)]
+    constants: &ShaderConstants,
+    #[spirv(descriptor_set = 0, binding = 0)]
+    hdr: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 1)]
+    hdr_sampler: &Sampler,
+    #[spirv(descriptor_set = 0, binding = 2)]
+    normal_depth: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 3)]
+    albedo: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 4)]
+    material: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 5)]
+    aov_sampler: &Sampler,
+    #[spirv(descriptor_set = 0, binding = 6)]
+    accumulation: &Image2d,
+    output: &mut Vec4,
+) {
+    let [w_px, h_px] = constants.view_size_pixels;
+    let uv = vec2(in_frag_coord.x / w_px as f32, in_frag_coord.y / h_px as f32);
+    let accumulated: Vec4 = accumulation.sample_by_lod(*aov_sampler, uv, 0.0);
+    let radiance: Vec4 = if constants.accumulate != 0 {
+        accumulated
+    } else {
+        hdr.sample_by_lod(*hdr_sampler, uv, 0.0)
+    };
+    let col = if constants.aov == aov::BEAUTY && constants.debug_mode == debug::OFF {
+        tonemap(radiance.truncate(), constants.tonemap, constants.exposure)
+    } else if constants.aov == aov::BEAUTY {
+        // Debug modes output display values, so undo the target's encoding.
+        let v = radiance;
+        vec3(srgb_to_linear(v.x), srgb_to_linear(v.y), srgb_to_linear(v.z))
+    } else {
+        // AOV visualisations are display values, so undo the target's encoding.
+        let albedo: Vec4 = albedo.sample_by_lod(*aov_sampler, uv, 0.0);
+        let normal_depth = normal_depth.sample_by_lod(*aov_sampler, uv, 0.0);
+        let material = material.sample_by_lod(*aov_sampler, uv, 0.0);
+        let bounce_limit = constants.ray_bounce_limit;
+        let samples = if constants.accumulate != 0 {
+            accumulated.w
+        } else {
+            constants.rays_per_pixel as f32
+        };
+        let v = aov::visualize(constants.aov, albedo.truncate(), normal_depth, material, bounce_limit, samples);
+        vec3(srgb_to_linear(v.x), srgb_to_linear(v.y), srgb_to_linear(v.z))
+    };
+
+    // Linear output, sRGB encoding is handled by the `Rgba8UnormSrgb` target.
+    *output = col.extend(1.0);
 }
 
 #[spirv(vertex)]
@@ -0,0 +1,63 @@
+//! Progressive accumulation of the samples of each pixel over frames, with adaptive sampling.
+//!
+//! The accumulation stores two texels per pixel. The first holds the mean radiance in `rgb` and the
+//! number of samples taken in `a`. The second holds the sum of squared differences of each frame's
+//! mean luminance from the running mean, per Welford, in `x` and the number of frames in `y`.
+//!
+//! Every frame traces the same number of samples per pixel, so the frame means are identically
+//! distributed and their spread gives the standard error of the accumulated mean.
+
+use crate::denoise::luminance;
+use spirv_std::{
+    glam::{vec4, Vec3, Vec4},
+    num_traits::Float,
+};
+
+/// The number of frames a pixel accumulates before it may be considered converged, as the error
+/// estimate is unreliable with fewer.
+pub const MIN_FRAMES: f32 = 8.0;
+
+// Added to the luminance the error is relative to, so that black pixels converge too.
+const LUMINANCE_FLOOR: f32 = 0.01;
+
+/// The standard error of a pixel's accumulated mean luminance, relative to the mean.
+pub fn relative_error(color: Vec4, moments: Vec4) -> f32 {
+    let frames = moments.y;
+    if frames < 2.0 {
+        return core::f32::MAX;
+    }
+    let variance = moments.x / (frames - 1.0);
+    (variance / frames).sqrt() / (luminance(color.truncate()) + LUMINANCE_FLOOR)
+}
+
+/// Whether a pixel's relative error has fallen below `threshold`, so it no longer takes samples.
+///
+/// Pixels never converge with a threshold of zero.
+pub fn converged(color: Vec4, moments: Vec4, threshold: f32) -> bool {
+    threshold > 0.0 && moments.y >= MIN_FRAMES && relative_error(color, moments) < threshold
+}
+
+/// Add the mean radiance of a frame's `samples` samples to a pixel's accumulation.
+///
+/// Frames with a non-finite mean are dropped, so that a single NaN doesn't ruin the pixel.
+pub fn add_frame(
+    color: Vec4,
+    moments: Vec4,
+    frame: Vec3,
+    samples: u32,
+    out_color: &mut Vec4,
+    out_moments: &mut Vec4,
+) {
+    let l = luminance(frame);
+    if !l.is_finite() {
+        *out_color = color;
+        *out_moments = moments;
+        return;
+    }
+    let frames = moments.y + 1.0;
+    let mean = color.truncate();
+    let new_mean = mean + (frame - mean) / frames;
+    let m2 = moments.x + (l - luminance(mean)) * (l - luminance(new_mean));
+    *out_color = new_mean.extend(color.w + samples as f32);
+    *out_moments = vec4(m2, frames, 0.0, 0.0);
+}
@@ -0,0 +1,111 @@
+//! Auxiliary output variables written alongside the beauty pass for compositing and debugging.
+
+use crate::debug::heatmap;
+use crate::{Hit, HitData, Material, MaterialInfo, MaterialKind, Ray};
+use spirv_std::{
+    glam::{vec3, Vec3, Vec4},
+    num_traits::Float,
+};
+
+/// `ShaderConstants::aov` value displaying the tone mapped render.
+pub const BEAUTY: u32 = 0;
+/// `ShaderConstants::aov` value displaying the albedo of the first hit.
+pub const ALBEDO: u32 = 1;
+/// `ShaderConstants::aov` value displaying the world space normal of the first hit.
+pub const NORMAL: u32 = 2;
+/// `ShaderConstants::aov` value displaying the distance to the first hit.
+pub const DEPTH: u32 = 3;
+/// `ShaderConstants::aov` value displaying a colour per material kind and index.
+pub const MATERIAL: u32 = 4;
+/// `ShaderConstants::aov` value displaying the mean number of bounces per ray.
+pub const BOUNCES: u32 = 5;
+/// `ShaderConstants::aov` value displaying the number of samples taken per pixel on a log scale.
+pub const SAMPLES: u32 = 6;
+
+// The distance at which the depth visualisation fades to roughly a third.
+const DEPTH_FALLOFF: f32 = 8.0;
+// The sample count mapped to the hottest colour.
+const MAX_SAMPLES: f32 = 4096.0;
+
+/// The surface seen through a pixel, taken from the `HitData` of the first hit.
+#[derive(Copy, Clone, Default)]
+pub struct FirstHit {
+    pub normal: Vec3,
+    /// The distance to the hit, or zero where the ray escaped.
+    pub depth: f32,
+    pub albedo: Vec3,
+    pub material: MaterialInfo,
+}
+
+/// Trace a ray to its first hit.
+pub fn first_hit(ray: &Ray, world: impl Copy + Hit, materials: impl Copy + Material) -> FirstHit {
+    let mut hit = HitData::default();
+    if world.hit(ray, 0.001, core::f32::MAX, &mut hit) {
+        FirstHit {
+            normal: hit.normal,
+            depth: hit.t * ray.direction().length(),
+            albedo: materials.albedo(&hit),
+            material: hit.material,
+        }
+    } else {
+        FirstHit {
+            albedo: Vec3::ONE,
+            ..Default::default()
+        }
+    }
+}
+
+impl FirstHit {
+    pub fn is_hit(&self) -> bool {
+        self.depth > 0.0
+    }
+
+    /// The material encoded as `(kind + 1, index)`, with a kind of zero where the ray escaped.
+    pub fn material_id(&self) -> [u32; 2] {
+        if !self.is_hit() {
+            return [0, 0];
+        }
+        let kind = match self.material.kind() {
+            MaterialKind::Lambertian => 1,
+            MaterialKind::Metal => 2,
+            MaterialKind::Dielectric => 3,
+            MaterialKind::Volume => 4,
+        };
+        [kind, self.material.index]
+    }
+}
+
+/// A displayable linear colour for the selected AOV.
+///
+/// `normal_depth` and `material` hold the texels written by `main_fs`, with the material's
+/// `(kind + 1, index)` in `xy` and the mean bounce count in `z`. `samples` is the number of samples
+/// taken for the pixel.
+pub fn visualize(
+    aov: u32,
+    albedo: Vec3,
+    normal_depth: Vec4,
+    material: Vec4,
+    ray_bounce_limit: u32,
+    samples: f32,
+) -> Vec3 {
+    let is_hit = normal_depth.w > 0.0;
+    match aov {
+        ALBEDO => albedo,
+        NORMAL if is_hit => normal_depth.truncate() * 0.5 + Vec3::splat(0.5),
+        DEPTH if is_hit => Vec3::splat((-normal_depth.w / DEPTH_FALLOFF).exp()),
+        MATERIAL if is_hit => id_color(material.x as u32 * 31 + material.y as u32),
+        BOUNCES => heatmap(material.z / ray_bounce_limit.max(1) as f32),
+        SAMPLES => heatmap((1.0 + samples).ln() / (1.0 + MAX_SAMPLES).ln()),
+        _ => Vec3::ZERO,
+    }
+}
+
+// A distinct, stable colour for an integer ID.
+fn id_color(id: u32) -> Vec3 {
+    let mut h = id.wrapping_mul(0x9e37_79b9);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    let channel = |shift: u32| 0.2 + 0.8 * ((h >> shift) & 0xff) as f32 / 255.0;
+    vec3(channel(0), channel(8), channel(16))
+}
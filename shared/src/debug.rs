@@ -0,0 +1,111 @@
+//! Debug visualisations of the integrator, selected by `ShaderConstants::debug_mode`.
+
+use crate::aov::FirstHit;
+use crate::denoise::luminance;
+use crate::PathStats;
+use spirv_std::{
+    glam::{vec3, Vec3},
+    num_traits::Float,
+};
+
+/// `ShaderConstants::debug_mode` value rendering the scene as usual.
+pub const OFF: u32 = 0;
+/// Shows the first hit's normal mapped to `0.0..=1.0`.
+pub const NORMALS: u32 = 1;
+/// A heatmap of the distance to the first hit on a log scale.
+pub const HIT_DISTANCE: u32 = 2;
+/// A heatmap of the mean bounces per path relative to the bounce limit.
+pub const BOUNCES: u32 = 3;
+/// A heatmap of the mean full object intersection tests per path on a log scale, i.e. the objects
+/// that each ray of the path passed close to, see `Hit::hit_counted`.
+pub const TEST_COST: u32 = 4;
+/// Highlights pixels with a NaN sample in magenta and infinite samples in cyan.
+pub const NAN_INF: u32 = 5;
+/// A heatmap of the variance of luminance between the samples of each pixel on a log scale.
+pub const VARIANCE: u32 = 6;
+
+// The hit distance mapped to the hottest colour.
+const MAX_HIT_DISTANCE: f32 = 100.0;
+// The luminance variance mapped to the hottest colour.
+const MAX_VARIANCE: f32 = 10.0;
+
+/// Statistics gathered over the samples of a single pixel.
+#[derive(Copy, Clone, Default)]
+pub struct PixelStats {
+    pub samples: u32,
+    pub bounces: u32,
+    pub tests: u32,
+    pub nans: u32,
+    pub infs: u32,
+    /// Running mean and sum of squared differences of sample luminance, per Welford.
+    pub mean: f32,
+    pub m2: f32,
+}
+
+impl PixelStats {
+    /// Record a sample's radiance and the statistics of its path.
+    pub fn add(&mut self, radiance: Vec3, path: &PathStats) {
+        self.samples += 1;
+        self.bounces += path.bounces;
+        self.tests += path.tests;
+        let c = radiance;
+        if c.x.is_nan() || c.y.is_nan() || c.z.is_nan() {
+            self.nans += 1;
+        } else if !c.x.is_finite() || !c.y.is_finite() || !c.z.is_finite() {
+            self.infs += 1;
+        } else {
+            let l = luminance(c);
+            let delta = l - self.mean;
+            self.mean += delta / self.samples as f32;
+            self.m2 += delta * (l - self.mean);
+        }
+    }
+
+    /// The unbiased sample variance of luminance.
+    pub fn variance(&self) -> f32 {
+        let finite = self.samples - self.nans - self.infs;
+        if finite > 1 {
+            self.m2 / (finite - 1) as f32
+        } else {
+            0.0
+        }
+    }
+}
+
+/// The colour to output in place of `beauty` for the given debug mode.
+///
+/// `max_tests` is the number of tests per path considered expensive, e.g. the cost of testing
+/// every object at every bounce.
+pub fn visualize(
+    mode: u32,
+    beauty: Vec3,
+    first_hit: &FirstHit,
+    stats: &PixelStats,
+    ray_bounce_limit: u32,
+    max_tests: u32,
+) -> Vec3 {
+    let samples = stats.samples.max(1) as f32;
+    match mode {
+        NORMALS if first_hit.is_hit() => first_hit.normal * 0.5 + Vec3::splat(0.5),
+        HIT_DISTANCE if first_hit.is_hit() => {
+            heatmap((1.0 + first_hit.depth).ln() / (1.0 + MAX_HIT_DISTANCE).ln())
+        }
+        BOUNCES => heatmap(stats.bounces as f32 / samples / ray_bounce_limit.max(1) as f32),
+        TEST_COST => {
+            let tests = stats.tests as f32 / samples;
+            heatmap((1.0 + tests).ln() / (1.0 + max_tests.max(1) as f32).ln())
+        }
+        NAN_INF if stats.nans > 0 => vec3(1.0, 0.0, 1.0),
+        NAN_INF if stats.infs > 0 => vec3(0.0, 1.0, 1.0),
+        NAN_INF => Vec3::splat(luminance(beauty).max(0.0).min(1.0) * 0.5),
+        VARIANCE => heatmap((1.0 + stats.variance()).ln() / (1.0 + MAX_VARIANCE).ln()),
+        OFF => beauty,
+        _ => Vec3::ZERO,
+    }
+}
+
+/// A blue to green to red ramp over `0.0..=1.0`.
+pub fn heatmap(t: f32) -> Vec3 {
+    let t = t.max(0.0).min(1.0);
+    vec3(t, 1.0 - (2.0 * t - 1.0).abs(), 1.0 - t)
+}
@@ -0,0 +1,161 @@
+//! Spatio-temporal variance-guided filtering of noisy low sample count frames.
+//!
+//! Based on "Spatiotemporal Variance-Guided Filtering" by Schied et al. Illumination is
+//! demodulated by the primary surface albedo, accumulated over time with reprojection and then
+//! smoothed with a few iterations of an edge-avoiding à-trous wavelet filter guided by the normal,
+//! depth and variance of each pixel.
+
+use crate::Camera;
+use bytemuck::{Pod, Zeroable};
+use spirv_std::{
+    glam::{Vec2, Vec3},
+    num_traits::Float,
+};
+
+/// The weights of the 5x5 B3 spline à-trous kernel, indexed by the absolute tap offset.
+pub const KERNEL: [f32; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// The number of frames over which the history length saturates.
+pub const MAX_HISTORY_LENGTH: f32 = 32.0;
+
+// Below this many frames of history, variance is estimated spatially instead of temporally.
+const MIN_HISTORY_FOR_TEMPORAL_VARIANCE: f32 = 4.0;
+
+/// Push constants for the `denoise_*` passes.
+#[derive(Copy, Clone, Default, Pod, Zeroable)]
+#[repr(C)]
+pub struct DenoiseConstants {
+    pub view_size_pixels: [u32; 2],
+    /// Where the scene's camera looks from and at and its field of view this frame and last
+    /// frame, used for reprojection.
+    pub camera_from: [f32; 3],
+    pub camera_to: [f32; 3],
+    pub prev_camera_from: [f32; 3],
+    pub prev_camera_to: [f32; 3],
+    pub vfov: f32,
+    pub prev_vfov: f32,
+    /// Zero when the history textures hold nothing useful, e.g. after a resize.
+    pub history_valid: u32,
+    /// The minimum weight of the current frame when accumulating colour and moments.
+    pub alpha: f32,
+    pub moments_alpha: f32,
+    /// The distance between taps of the current à-trous iteration in pixels.
+    pub step_size: u32,
+    /// Non-zero on the last à-trous iteration to multiply the albedo back in.
+    pub remodulate: u32,
+    /// Edge-stopping sensitivity to luminance, scaled by the local standard deviation.
+    pub phi_color: f32,
+    /// Edge-stopping exponent applied to the cosine between normals.
+    pub phi_normal: f32,
+    /// Edge-stopping sensitivity to depth, scaled by the local depth gradient.
+    pub phi_depth: f32,
+}
+
+/// The guide values of a single pixel, written by `main_fs` and read by the filter passes.
+#[derive(Copy, Clone, Default)]
+pub struct Surface {
+    pub normal: Vec3,
+    /// The distance to the primary hit, or zero where the primary ray escaped.
+    pub depth: f32,
+}
+
+impl Surface {
+    /// Whether the primary ray hit anything.
+    pub fn is_hit(&self) -> bool {
+        self.depth > 0.0
+    }
+}
+
+impl Camera {
+    /// The `uv` at which the point `p` is seen, matching the `uv` passed to `Camera::ray`.
+    pub fn project(&self, p: Vec3) -> Vec2 {
+        let d = p - self.origin;
+        let plane_dist = (self.lower_left_corner - self.origin).dot(-self.w);
+        let q = self.origin + d * (plane_dist / d.dot(-self.w)) - self.lower_left_corner;
+        Vec2::new(
+            q.dot(self.horizontal) / self.horizontal.length_squared(),
+            q.dot(self.vertical) / self.vertical.length_squared(),
+        )
+    }
+}
+
+/// Relative luminance of a linear sRGB colour.
+pub fn luminance(c: Vec3) -> f32 {
+    c.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+/// Divide the albedo out of a colour, leaving the untextured illumination to be filtered.
+pub fn demodulate(color: Vec3, albedo: Vec3) -> Vec3 {
+    color / albedo.max(Vec3::splat(0.001))
+}
+
+/// The inverse of `demodulate`.
+pub fn remodulate(illumination: Vec3, albedo: Vec3) -> Vec3 {
+    illumination * albedo.max(Vec3::splat(0.001))
+}
+
+/// Whether a reprojected history sample belongs to the same surface as the current pixel.
+pub fn consistent(current: Surface, previous: Surface) -> bool {
+    current.is_hit()
+        && previous.is_hit()
+        && (current.depth - previous.depth).abs() < 0.1 * current.depth
+        && current.normal.dot(previous.normal) > 0.9
+}
+
+/// Accumulate the first and second moments of luminance and derive the variance.
+///
+/// `history_length` is the number of frames already accumulated and `spatial_moments` the moments
+/// of the current frame's neighbourhood, used while the history is too short to trust. Returns the
+/// integrated moments and writes the variance estimate to `variance`.
+pub fn integrate_moments(
+    prev_moments: Vec2,
+    history_length: f32,
+    luminance: f32,
+    spatial_moments: Vec2,
+    moments_alpha: f32,
+    variance: &mut f32,
+) -> Vec2 {
+    let current = Vec2::new(luminance, luminance * luminance);
+    let a = blend_weight(history_length, moments_alpha);
+    let moments = prev_moments * (1.0 - a) + current * a;
+    let m = if history_length + 1.0 < MIN_HISTORY_FOR_TEMPORAL_VARIANCE {
+        spatial_moments
+    } else {
+        moments
+    };
+    *variance = (m.y - m.x * m.x).max(0.0);
+    moments
+}
+
+/// The weight of the current frame in an exponential moving average with `history_length` frames
+/// already accumulated.
+///
+/// Falls back to a cumulative average while the history is short so that the first frames after
+/// a disocclusion converge quickly.
+pub fn blend_weight(history_length: f32, alpha: f32) -> f32 {
+    (1.0 / (history_length + 1.0)).max(alpha)
+}
+
+/// The weight of an à-trous tap based on how likely it lies on the same surface as the centre.
+///
+/// `depth_gradient` is the change in depth per pixel around the centre, `step` the distance to the
+/// tap in pixels and `std_dev` the prefiltered standard deviation of the centre's luminance.
+#[allow(clippy::too_many_arguments)]
+pub fn edge_stopping_weight(
+    center: Surface,
+    tap: Surface,
+    center_luminance: f32,
+    tap_luminance: f32,
+    depth_gradient: f32,
+    step: f32,
+    std_dev: f32,
+    c: &DenoiseConstants,
+) -> f32 {
+    if !tap.is_hit() {
+        return 0.0;
+    }
+    let w_normal = center.normal.dot(tap.normal).max(0.0).powf(c.phi_normal);
+    let w_depth = (tap.depth - center.depth).abs() / (c.phi_depth * depth_gradient * step + 1e-3);
+    let w_lum = (center_luminance - tap_luminance).abs() / (c.phi_color * std_dev + 1e-6);
+    w_normal * (-w_depth - w_lum).exp()
+}
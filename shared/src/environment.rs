@@ -0,0 +1,181 @@
+//! Environments providing the radiance of rays that escape the scene.
+
+use crate::sky::PreethamSky;
+use crate::{unit_vector, Environment};
+use core::f32::consts::PI;
+use spirv_std::{
+    glam::{vec2, vec3, Vec2, Vec3, Vec4},
+    image::Image2d,
+    num_traits::Float,
+    Sampler,
+};
+
+/// `ShaderConstants::environment` value selecting the analytic `PreethamSky`.
+pub const SKY: u32 = 0;
+/// `ShaderConstants::environment` value selecting the `EnvMap`.
+pub const MAP: u32 = 1;
+
+/// An equirectangular environment map bound as a texture.
+#[derive(Copy, Clone)]
+pub struct EnvMap<'a> {
+    pub image: &'a Image2d,
+    pub sampler: Sampler,
+    pub distribution: EnvMapDistribution<'a>,
+    /// Rotation about the vertical axis in radians.
+    pub rotation: f32,
+    /// Scales the radiance of every texel.
+    pub intensity: f32,
+}
+
+/// A piecewise-constant 2D distribution over the texels of an environment map.
+///
+/// `cdf` holds a normalised conditional CDF of `width + 1` values for each of the `height` rows,
+/// followed by the normalised marginal CDF of `height + 1` values over the rows. Texel weights are
+/// their luminance scaled by `sin(theta)` to account for the stretching of the equirectangular
+/// projection towards the poles.
+#[derive(Copy, Clone)]
+pub struct EnvMapDistribution<'a> {
+    pub cdf: &'a [f32],
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Selects between environments using the `ShaderConstants::environment` kind.
+#[derive(Copy, Clone)]
+pub struct Environments<'a> {
+    pub kind: u32,
+    pub sky: PreethamSky,
+    pub map: EnvMap<'a>,
+}
+
+impl<'a> Environment for EnvMap<'a> {
+    fn radiance(self, dir: Vec3) -> Vec3 {
+        let uv = direction_to_equirect(unit_vector(dir), self.rotation);
+        let texel: Vec4 = self.image.sample_by_lod(self.sampler, uv, 0.0);
+        texel.truncate() * self.intensity
+    }
+
+    fn sample(self, u: Vec2, pdf: &mut f32) -> Vec3 {
+        let mut uv_pdf = 0.0;
+        let uv = self.distribution.sample(u, &mut uv_pdf);
+        let sin_theta = (uv.y * PI).sin();
+        *pdf = if sin_theta > 0.0 {
+            uv_pdf / (2.0 * PI * PI * sin_theta)
+        } else {
+            0.0
+        };
+        equirect_to_direction(uv, self.rotation)
+    }
+
+    fn pdf(self, dir: Vec3) -> f32 {
+        let uv = direction_to_equirect(unit_vector(dir), self.rotation);
+        let sin_theta = (uv.y * PI).sin();
+        if sin_theta > 0.0 {
+            self.distribution.pdf(uv) / (2.0 * PI * PI * sin_theta)
+        } else {
+            0.0
+        }
+    }
+}
+
+impl<'a> Environment for Environments<'a> {
+    fn radiance(self, dir: Vec3) -> Vec3 {
+        if self.kind == MAP {
+            self.map.radiance(dir)
+        } else {
+            self.sky.radiance(dir)
+        }
+    }
+
+    fn sample(self, u: Vec2, pdf: &mut f32) -> Vec3 {
+        if self.kind == MAP {
+            self.map.sample(u, pdf)
+        } else {
+            self.sky.sample(u, pdf)
+        }
+    }
+
+    fn pdf(self, dir: Vec3) -> f32 {
+        if self.kind == MAP {
+            self.map.pdf(dir)
+        } else {
+            self.sky.pdf(dir)
+        }
+    }
+}
+
+impl<'a> EnvMapDistribution<'a> {
+    /// Sample texture coordinates proportional to the texel weights.
+    ///
+    /// `pdf` is the density with respect to the area of the unit `uv` square.
+    pub fn sample(&self, u: Vec2, pdf: &mut f32) -> Vec2 {
+        let (w, h) = (self.width as usize, self.height as usize);
+        let marginal = self.marginal_offset();
+        let row = find_interval(self.cdf, marginal, h, u.y);
+        let (v0, v1) = (self.cdf[marginal + row], self.cdf[marginal + row + 1]);
+        let dv = if v1 > v0 { (u.y - v0) / (v1 - v0) } else { 0.5 };
+
+        let conditional = row * (w + 1);
+        let col = find_interval(self.cdf, conditional, w, u.x);
+        let (u0, u1) = (self.cdf[conditional + col], self.cdf[conditional + col + 1]);
+        let du = if u1 > u0 { (u.x - u0) / (u1 - u0) } else { 0.5 };
+
+        *pdf = (v1 - v0) * h as f32 * (u1 - u0) * w as f32;
+        vec2((col as f32 + du) / w as f32, (row as f32 + dv) / h as f32)
+    }
+
+    /// The density of `sample` producing the given texture coordinates.
+    pub fn pdf(&self, uv: Vec2) -> f32 {
+        let (w, h) = (self.width as usize, self.height as usize);
+        let col = ((uv.x * w as f32) as usize).min(w - 1);
+        let row = ((uv.y * h as f32) as usize).min(h - 1);
+        let marginal = self.marginal_offset();
+        let conditional = row * (w + 1);
+        let pdf_v = (self.cdf[marginal + row + 1] - self.cdf[marginal + row]) * h as f32;
+        let pdf_u = (self.cdf[conditional + col + 1] - self.cdf[conditional + col]) * w as f32;
+        pdf_v * pdf_u
+    }
+
+    fn marginal_offset(&self) -> usize {
+        self.height as usize * (self.width as usize + 1)
+    }
+}
+
+/// Equirectangular texture coordinates for a unit direction, with `v` of `0.0` straight up.
+pub fn direction_to_equirect(dir: Vec3, rotation: f32) -> Vec2 {
+    let phi = dir.z.atan2(dir.x) + rotation;
+    let theta = dir.y.max(-1.0).min(1.0).acos();
+    let u = phi / (2.0 * PI) + 0.5;
+    vec2(u - u.floor(), theta / PI)
+}
+
+/// The inverse of `direction_to_equirect`.
+pub fn equirect_to_direction(uv: Vec2, rotation: f32) -> Vec3 {
+    let phi = (uv.x - 0.5) * 2.0 * PI - rotation;
+    let theta = uv.y * PI;
+    let sin_theta = theta.sin();
+    vec3(sin_theta * phi.cos(), theta.cos(), sin_theta * phi.sin())
+}
+
+/// Map a point on the unit square to a uniformly distributed direction.
+pub fn uniform_sphere(u: Vec2) -> Vec3 {
+    let z = 1.0 - 2.0 * u.x;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+    vec3(r * phi.cos(), r * phi.sin(), z)
+}
+
+// Binary search for the interval `i` in `0..count` where `cdf[offset + i] <= u`.
+fn find_interval(cdf: &[f32], offset: usize, count: usize, u: f32) -> usize {
+    let mut lo = 0;
+    let mut hi = count;
+    while lo + 1 < hi {
+        let mid = (lo + hi) / 2;
+        if cdf[offset + mid] <= u {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
@@ -0,0 +1,46 @@
+//! Compile-time checks of the layout of types shared between the app and the shader.
+//!
+//! Push constants and storage buffers are written from the host as bytes and read by the shader
+//! with the SPIR-V target's layout of the same types, so the two layouts have to agree and follow
+//! std430, the layout of push constants and storage buffers. Types written by the host are
+//! `bytemuck::Pod`, which rules out implicit padding, and only contain 4 byte scalars and arrays
+//! of them, which are aligned to 4 bytes under both std430 and `repr(C)`.
+//!
+//! glam's `Vec3` is the trap: on the SPIR-V target it is a SIMD type of size and alignment 16
+//! while on the host it is three `f32`s, so any struct containing one has a different size and
+//! field offsets on each side. Store `[f32; 3]` instead and convert with `Vec3::from`, or use
+//! `Vec3A`, which is 16 bytes on both, in buffers only the shader writes.
+//!
+//! These assertions are evaluated when compiling for either target, so a change that breaks the
+//! layout on one of them fails to build. `tests/layout.rs` also checks the field offsets.
+
+use crate::denoise::DenoiseConstants;
+use crate::wavefront::{PathState, WavefrontConstants};
+use crate::{Dielectric, Lambertian, MaterialInfo, Metal, ShaderConstants, Sphere, Volume};
+use core::mem::{align_of, size_of};
+
+/// The push constant size the app requires of the device, which every push constant type must
+/// fit in.
+pub const MAX_PUSH_CONSTANT_SIZE: usize = 256;
+
+/// The storage buffers per shader stage the app requires of the device: the environment map's
+/// CDF, the spheres and the four material tables of the scene, along with the wavefront's paths,
+/// queues and dispatch arguments.
+pub const MAX_STORAGE_BUFFERS_PER_SHADER_STAGE: u32 = 9;
+
+// Push constants.
+const _: () = assert!(size_of::<ShaderConstants>() == 164 && align_of::<ShaderConstants>() == 4);
+const _: () = assert!(size_of::<WavefrontConstants>() == 180 && align_of::<WavefrontConstants>() == 4);
+const _: () = assert!(size_of::<DenoiseConstants>() == 96 && align_of::<DenoiseConstants>() == 4);
+const _: () = assert!(size_of::<WavefrontConstants>() <= MAX_PUSH_CONSTANT_SIZE);
+
+// Scene storage buffers.
+const _: () = assert!(size_of::<Sphere>() == 24 && align_of::<Sphere>() == 4);
+const _: () = assert!(size_of::<MaterialInfo>() == 8 && align_of::<MaterialInfo>() == 4);
+const _: () = assert!(size_of::<Lambertian>() == 24 && align_of::<Lambertian>() == 4);
+const _: () = assert!(size_of::<Metal>() == 16 && align_of::<Metal>() == 4);
+const _: () = assert!(size_of::<Dielectric>() == 16 && align_of::<Dielectric>() == 4);
+const _: () = assert!(size_of::<Volume>() == 16 && align_of::<Volume>() == 4);
+
+// The wavefront path buffer, allocated by the host and only accessed by the shader.
+const _: () = assert!(size_of::<PathState>() == 240 && align_of::<PathState>() == 16);
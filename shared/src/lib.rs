@@ -2,30 +2,157 @@
 
 #![no_std]
 
+use bytemuck::{Pod, Zeroable};
+use core::f32::consts::{FRAC_1_PI, FRAC_PI_2, FRAC_PI_4, PI};
+use debug::PixelStats;
+use sampler::{Sampler, Samplers};
 use spirv_std::{
     glam::{vec2, vec3, Vec2, Vec3},
     num_traits::Float,
 };
 
+pub mod accumulate;
+pub mod aov;
+pub mod debug;
+pub mod denoise;
+pub mod environment;
+pub mod layout;
+pub mod sampler;
+pub mod sky;
+pub mod spectral;
+pub mod tonemap;
+pub mod wavefront;
+
+// Russian roulette never continues a path with certainty, so that paths between bright surfaces
+// still end.
+const MAX_CONTINUE_PROBABILITY: f32 = 0.95;
+
 /// Types that may be hit by a ray.
 pub trait Hit {
     /// Whether or not the Ray hits the object along with the associated hit data.
     fn hit(self, r: &Ray, t_min: f32, t_max: f32, data: &mut HitData) -> bool;
+
+    /// As `hit`, also adding to `tests` the number of objects the ray passed close enough to for
+    /// a full intersection test, rather than being rejected early.
+    fn hit_counted(self, r: &Ray, t_min: f32, t_max: f32, data: &mut HitData, tests: &mut u32) -> bool
+    where
+        Self: Sized,
+    {
+        *tests += 1;
+        self.hit(r, t_min, t_max, data)
+    }
+
+    /// The most full intersection tests each call to `hit` may perform.
+    fn test_cost(self) -> u32
+    where
+        Self: Sized,
+    {
+        1
+    }
 }
 
 /// Used to describe the surface of different materials.
 pub trait Material {
-    /// Produce a scattered ray (or say it absorved the incident ray).
+    /// Sample a direction to scatter the incident ray in (or say it absorbed the incident ray).
+    ///
+    /// If scattered, describes the direction along with the BSDF value and PDF for it.
+    fn scatter(self, r_in: &Ray, hit: &HitData, sampler: &mut impl Sampler, sample: &mut BsdfSample) -> bool;
+
+    /// The BSDF value for scattering the incident ray in the unit direction `wi`.
     ///
-    /// If scattered, describes how much the ray should be attenuated.
-    fn scatter(
+    /// Writes the solid angle probability density of `scatter` producing `wi` to `pdf`. Delta
+    /// distributions can't produce a given direction, so evaluate to zero.
+    fn eval(self, r_in: &Ray, hit: &HitData, wi: Vec3, pdf: &mut f32) -> Vec3;
+
+    /// Sample a scattered direction for a single wavelength in nanometres.
+    ///
+    /// Every component of the sample's value holds the value at the wavelength. By default the RGB
+    /// value produced by `scatter` is uplifted to a spectral value.
+    fn scatter_spectral(
         self,
         r_in: &Ray,
         hit: &HitData,
-        rng: &mut Rng,
-        attenuation: &mut Vec3,
-        r_out: &mut Ray,
-    ) -> bool;
+        wavelength: f32,
+        sampler: &mut impl Sampler,
+        sample: &mut BsdfSample,
+    ) -> bool
+    where
+        Self: Sized,
+    {
+        let scattered = self.scatter(r_in, hit, sampler, sample);
+        sample.value = Vec3::splat(spectral::rgb_to_spectrum(sample.value, wavelength));
+        scattered
+    }
+
+    /// The surface colour at the hit, used to demodulate texture detail when denoising.
+    fn albedo(self, _hit: &HitData) -> Vec3
+    where
+        Self: Sized,
+    {
+        Vec3::ONE
+    }
+
+    /// The radiance emitted by the surface at the hit, in every direction.
+    fn emitted(self, _hit: &HitData) -> Vec3
+    where
+        Self: Sized,
+    {
+        Vec3::ZERO
+    }
+}
+
+/// Describes the radiance arriving from outside the scene.
+pub trait Environment {
+    /// The radiance arriving from the given direction.
+    fn radiance(self, dir: Vec3) -> Vec3;
+
+    /// Sample a direction towards the environment, importance sampled where possible.
+    ///
+    /// Writes the solid angle probability density of the direction to `pdf`.
+    fn sample(self, u: Vec2, pdf: &mut f32) -> Vec3;
+
+    /// The solid angle probability density of `sample` producing the given direction.
+    fn pdf(self, dir: Vec3) -> f32;
+}
+
+/// A direction sampled by `Material::scatter`.
+#[derive(Copy, Clone, Default)]
+pub struct BsdfSample {
+    /// The point the scattered ray leaves from. This is the hit itself for surfaces, but may lie
+    /// inside a `Volume`.
+    pub origin: Vec3,
+    /// The unit direction to scatter in.
+    pub direction: Vec3,
+    /// The BSDF value for the direction.
+    ///
+    /// For delta distributions this is instead the weight of the sample, as the BSDF value is
+    /// infinite.
+    pub value: Vec3,
+    /// The solid angle probability density of sampling the direction, one for delta distributions.
+    pub pdf: f32,
+    /// Whether the direction was sampled from a delta distribution, such as a perfect mirror.
+    pub delta: bool,
+}
+
+/// Controls when `color` and `color_spectral` stop tracing a path.
+///
+/// Passing through the boundary of a `Volume` counts as a bounce.
+#[derive(Copy, Clone, Default)]
+pub struct PathTermination {
+    /// Paths still bouncing after this many bounces are cut off, returning black.
+    pub bounce_limit: u32,
+    /// The bounces after which paths are randomly terminated by Russian roulette, or zero to
+    /// disable it.
+    pub roulette_depth: u32,
+}
+
+/// Statistics about a traced path, used for debug visualisations and AOVs.
+#[derive(Copy, Clone, Default)]
+pub struct PathStats {
+    /// The number of times the path scattered.
+    pub bounces: u32,
+    /// The number of full object intersection tests performed, see `Hit::hit_counted`.
+    pub tests: u32,
 }
 
 #[derive(Copy, Clone, Default)]
@@ -38,41 +165,48 @@ pub struct HitData {
 }
 
 #[derive(Copy, Clone)]
-#[repr(C)]
+#[repr(u32)]
 pub enum MaterialKind {
-    Lambertian,
-    Metal,
-    Dielectric,
+    Lambertian = 0,
+    Metal = 1,
+    Dielectric = 2,
+    Volume = 3,
 }
 
-#[derive(Copy, Clone)]
+/// Stored in the scene's storage buffers, so the kind is a plain `u32` that any bit pattern is
+/// valid for. Unknown kinds are treated as Lambertian.
+#[derive(Copy, Clone, Pod, Zeroable)]
 #[repr(C)]
 pub struct MaterialInfo {
-    pub kind: MaterialKind,
-    pub index: usize,
+    /// The `MaterialKind` as a `u32`.
+    pub kind: u32,
+    /// The index of the material within those of its kind.
+    pub index: u32,
 }
 
-/// All materials in the world.
+/// All materials in the world, one slice per `MaterialKind`.
 // TODO: Not a portable way of storing materials for a world... Need ADTs or trait objects.
 #[derive(Copy, Clone)]
-#[repr(C)]
-pub struct Materials<const NL: usize, const NM: usize, const ND: usize> {
-    pub lambertian: [Lambertian; NL],
-    pub metal: [Metal; NM],
-    pub dielectric: [Dielectric; ND],
+pub struct Materials<'a> {
+    pub lambertian: &'a [Lambertian],
+    pub metal: &'a [Metal],
+    pub dielectric: &'a [Dielectric],
+    pub volume: &'a [Volume],
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Pod, Zeroable)]
 #[repr(C)]
 pub struct Lambertian {
-    pub albedo: Vec3,
+    pub albedo: [f32; 3],
+    /// The radiance emitted by the surface, zero for surfaces that aren't lights.
+    pub emission: [f32; 3],
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Pod, Zeroable)]
 #[repr(C)]
 pub struct Dielectric {
     // TODO: This should just be a float, but for some reason results in this error if it isn't a
-    // Vec3.
+    // vector.
     //
     // error: Cannot cast between pointer types
     //    --> shared/./src/lib.rs:207:6
@@ -82,32 +216,97 @@ pub struct Dielectric {
     //     |
     //     = note: from: *struct Dielectric { ref_idx: f32 }
     //     = note: to: *u32
-    pub ref_idx: Vec3,
+    //
+    // Only the first component is used, and the others repeat it.
+    pub ref_idx: [f32; 3],
+    /// The Cauchy `B` coefficient in µm², describing how the index of refraction varies with
+    /// wavelength in spectral mode. Zero disables dispersion.
+    pub cauchy_b: f32,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Pod, Zeroable)]
 #[repr(C)]
 pub struct Metal {
-    pub albedo: Vec3,
+    pub albedo: [f32; 3],
     pub fuzz: f32,
 }
 
-#[derive(Copy, Clone, Default)]
+/// A sphere filled with a medium of constant density that scatters light equally in every
+/// direction, such as smoke or fog.
+///
+/// The medium is sampled when a path leaves the sphere, over the distance it travelled inside, so
+/// objects within the sphere see it as empty.
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub struct Volume {
+    pub albedo: [f32; 3],
+    /// The probability per unit distance of a path scattering within the medium.
+    pub density: f32,
+}
+
+#[derive(Copy, Clone, Default, Pod, Zeroable)]
 #[repr(C)]
 pub struct ShaderConstants {
     pub view_size_pixels: [u32; 2],
     pub mouse_pixels: [f32; 2],
     pub time: f32,
-    pub rng_seed_offset: f32,
+    /// Counts frames while sampling is animated, offsetting the sample indices of each pixel.
+    pub frame: u32,
+    /// The x, y, width and height in pixels of the region traced by a `main_cs` dispatch.
+    pub compute_region: [u32; 4],
 
     // Rendering
     pub rays_per_pixel: u32,
     pub ray_bounce_limit: u32,
+    /// The bounces after which paths are terminated by Russian roulette, or zero to disable it.
+    pub roulette_depth: u32,
+    /// The maximum of any component of a sample's radiance, or zero to disable clamping.
+    pub radiance_clamp: f32,
+    /// One of the kinds in the `sampler` module.
+    pub sampler: u32,
+    /// Non-zero to trace a single wavelength per ray rather than RGB.
+    pub spectral: u32,
+
+    // Accumulation
+    /// Non-zero to progressively accumulate frames, see the `accumulate` module.
+    pub accumulate: u32,
+    /// The number of frames accumulated before this one, zero to discard the accumulation.
+    pub accumulated_frames: u32,
+    /// The relative error below which accumulating pixels stop taking samples, or zero to keep
+    /// sampling every pixel.
+    pub adaptive_threshold: f32,
+
+    // Environment
+    /// One of the kinds in the `environment` module.
+    pub environment: u32,
+    pub env_rotation: f32,
+    pub env_intensity: f32,
+    pub env_map_size: [u32; 2],
+    pub sun_elevation: f32,
+    pub sun_azimuth: f32,
+    pub turbidity: f32,
+
+    // Display
+    /// Exposure applied before tone mapping in EV.
+    pub exposure: f32,
+    /// One of the operators in the `tonemap` module.
+    pub tonemap: u32,
+    /// One of the outputs in the `aov` module to display in place of the render.
+    pub aov: u32,
+    /// One of the modes in the `debug` module, replacing the radiance written by `main_fs`.
+    pub debug_mode: u32,
+
+    // Scene
+    /// The number of spheres bound in the scene's sphere buffer.
+    pub sphere_count: u32,
 
     // Camera
+    pub camera_from: [f32; 3],
+    pub camera_to: [f32; 3],
     pub vfov: f32,
     pub aperture: f32,
-    //pub focus_dist: f32,
+    /// The distance from the camera to the plane in focus.
+    pub focus_dist: f32,
 
     // TODO: This would be awesome for automatically improving scene quality when the camera
     // reaches a resting state.
@@ -121,19 +320,24 @@ pub struct Ray {
     pub b: Vec3,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Pod, Zeroable)]
 #[repr(C)]
 pub struct Sphere {
-    pub center: Vec3,
+    pub center: [f32; 3],
     pub radius: f32,
     pub material: MaterialInfo,
 }
 
-#[derive(Clone, Default)]
-pub struct Rng {
-    pub seed: Vec2,
+/// The first `count` spheres of a slice, e.g. a storage buffer bound with room to spare.
+#[derive(Copy, Clone)]
+pub struct World<'a> {
+    pub spheres: &'a [Sphere],
+    pub count: u32,
 }
 
+/// The up direction of the scene's camera.
+pub const CAMERA_UP: Vec3 = spirv_std::glam::const_vec3!([0.0, 1.0, 0.0]);
+
 #[derive(Clone)]
 pub struct Camera {
     pub origin: Vec3,
@@ -192,14 +396,38 @@ impl Camera {
         }
     }
 
-    pub fn ray(&self, rng: &mut Rng, uv: Vec2) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk(rng);
+    /// The ray through `uv` from the centre of the lens, ignoring depth of field.
+    pub fn pinhole_ray(&self, uv: Vec2) -> Ray {
+        Ray {
+            a: self.origin,
+            b: self.lower_left_corner + uv.x * self.horizontal + uv.y * self.vertical - self.origin,
+        }
+    }
+
+    pub fn ray(&self, sampler: &mut impl Sampler, uv: Vec2) -> Ray {
+        let rd = self.lens_radius * random_in_unit_disk(sampler.next_2d());
         let offset = self.u * rd.x + self.v * rd.y;
         Ray {
             a: self.origin + offset,
             b: self.lower_left_corner + uv.x * self.horizontal + uv.y * self.vertical - self.origin - offset,
         }
     }
+
+    /// A camera without depth of field, e.g. for reprojecting points.
+    pub fn pinhole(from: Vec3, to: Vec3, vfov: f32, aspect: f32) -> Self {
+        Camera::new(from, to, CAMERA_UP, vfov, aspect, 0.0, 1.0)
+    }
+}
+
+impl ShaderConstants {
+    /// The scene's camera, as configured by the constants.
+    pub fn camera(&self) -> Camera {
+        let [w_px, h_px] = self.view_size_pixels;
+        let aspect = w_px as f32 / h_px as f32;
+        let from = Vec3::from(self.camera_from);
+        let to = Vec3::from(self.camera_to);
+        Camera::new(from, to, CAMERA_UP, self.vfov, aspect, self.aperture, self.focus_dist)
+    }
 }
 
 impl Ray {
@@ -220,58 +448,130 @@ impl Ray {
     }
 }
 
-impl Rng {
-    pub fn gen_signed(&mut self) -> f32 {
-        let res = (self.seed.dot(vec2(12.9898, 78.233)).sin() * 43758.5453).fract();
-        self.seed = vec2(
-            (self.seed.x + res + 17.825) % 3718.0,
-            (self.seed.y + res + 72.7859) % 1739.0,
-        );
-        res
+impl PathTermination {
+    /// The probability of a path with the given throughput continuing after `bounces` bounces.
+    ///
+    /// Paths that continue must be weighted by the inverse of the probability to stay unbiased.
+    pub fn continue_probability(&self, bounces: u32, throughput: f32) -> f32 {
+        if self.roulette_depth == 0 || bounces < self.roulette_depth {
+            1.0
+        } else {
+            throughput.min(MAX_CONTINUE_PROBABILITY)
+        }
     }
+}
 
-    pub fn gen(&mut self) -> f32 {
-        self.gen_signed() * 0.5 + 0.5
+impl BsdfSample {
+    /// The factor the throughput of a path is multiplied by when scattering with this sample,
+    /// `f * |cos θ| / pdf` for the surface normal `n`.
+    pub fn weight(&self, n: Vec3) -> Vec3 {
+        if self.delta {
+            self.value
+        } else {
+            self.value * self.direction.dot(n).abs() / self.pdf
+        }
     }
 }
 
 impl Lambertian {
     pub fn new(albedo: Vec3) -> Self {
-        Self { albedo }
+        Self::with_emission(albedo, Vec3::ZERO)
     }
 
-    pub fn scatter_ray(
-        &self,
-        hit: &HitData,
-        rng: &mut Rng,
-        attenuation: &mut Vec3,
-        r_out: &mut Ray,
-    ) {
-        let target = hit.p + hit.normal + random_in_unit_sphere(rng);
-        *r_out = Ray::new(hit.p, target - hit.p);
-        *attenuation = self.albedo;
+    /// A surface that also emits the given radiance, i.e. a light.
+    pub fn with_emission(albedo: Vec3, emission: Vec3) -> Self {
+        Self {
+            albedo: albedo.into(),
+            emission: emission.into(),
+        }
     }
 }
 
 impl Metal {
     pub fn new(albedo: Vec3, fuzz: f32) -> Self {
-        Self { albedo, fuzz }
+        Self { albedo: albedo.into(), fuzz }
+    }
+}
+
+impl Volume {
+    pub fn new(albedo: Vec3, density: f32) -> Self {
+        Self { albedo: albedo.into(), density }
     }
 }
 
 impl Dielectric {
     pub fn new(ref_idx: f32) -> Self {
-        Self { ref_idx: Vec3::splat(ref_idx) }
+        Self::with_dispersion(ref_idx, 0.0)
+    }
+
+    /// `ref_idx` is the index of refraction at the d line, `cauchy_b` is in µm².
+    pub fn with_dispersion(ref_idx: f32, cauchy_b: f32) -> Self {
+        Self {
+            ref_idx: [ref_idx; 3],
+            cauchy_b,
+        }
+    }
+
+    /// Approximate a glass from its Sellmeier coefficients.
+    ///
+    /// The Cauchy coefficient is fit to the glass's index of refraction at the F and C lines.
+    pub fn sellmeier(b: Vec3, c: Vec3) -> Self {
+        let n_d = spectral::sellmeier_ior(b, c, spectral::WAVELENGTH_D);
+        let n_f = spectral::sellmeier_ior(b, c, spectral::WAVELENGTH_F);
+        let n_c = spectral::sellmeier_ior(b, c, spectral::WAVELENGTH_C);
+        let inv_sq = |l: f32| 1.0 / (l * l * 1e-6);
+        let cauchy_b = (n_f - n_c) / (inv_sq(spectral::WAVELENGTH_F) - inv_sq(spectral::WAVELENGTH_C));
+        Self::with_dispersion(n_d, cauchy_b)
+    }
+
+    /// The index of refraction at the given wavelength in nanometres.
+    pub fn ior(&self, wavelength: f32) -> f32 {
+        let l_d = spectral::WAVELENGTH_D * 1e-3;
+        let a = self.ref_idx[0] - self.cauchy_b / (l_d * l_d);
+        spectral::cauchy_ior(a, self.cauchy_b, wavelength)
+    }
+
+    fn scatter_ray(ref_idx: f32, ray_in: &Ray, hit: &HitData, sampler: &mut impl Sampler, sample: &mut BsdfSample) {
+        let ray_in_dir = ray_in.direction();
+        let reflected = reflect(ray_in_dir, hit.normal);
+        let ray_in_dir_dot_normal = ray_in_dir.dot(hit.normal);
+        let (outward_normal, ni_over_nt, cos) = if ray_in_dir_dot_normal > 0.0 {
+            let cos = ref_idx * ray_in_dir_dot_normal / ray_in_dir.length();
+            (-hit.normal, ref_idx, cos)
+        } else {
+            let cos = -ray_in_dir_dot_normal / ray_in_dir.length();
+            (hit.normal, 1.0 / ref_idx, cos)
+        };
+        let mut refracted = Vec3::ZERO;
+        let reflect_prob = if refract(ray_in.direction(), outward_normal, ni_over_nt, &mut refracted) {
+            schlick(cos, ref_idx)
+        } else {
+            1.0
+        };
+        // Choosing between reflection and refraction with the Fresnel term cancels it out.
+        sample.origin = hit.p;
+        sample.direction = if sampler.next_1d() < reflect_prob {
+            unit_vector(reflected)
+        } else {
+            refracted
+        };
+        sample.value = Vec3::ONE;
+        sample.pdf = 1.0;
+        sample.delta = true;
     }
 }
 
 impl<T: Copy + Hit, const N: usize> Hit for [T; N] {
     fn hit(self, r: &Ray, t_min: f32, t_max: f32, hit: &mut HitData) -> bool {
+        self.hit_counted(r, t_min, t_max, hit, &mut 0)
+    }
+
+    fn hit_counted(self, r: &Ray, t_min: f32, t_max: f32, hit: &mut HitData, tests: &mut u32) -> bool {
         let mut did_hit = false;
         let mut closest_t = t_max;
         let mut temp_hit = HitData::default();
         for i in 0..N {
-            if self[i].hit(r, t_min, closest_t, &mut temp_hit) {
+            if self[i].hit_counted(r, t_min, closest_t, &mut temp_hit, tests) {
                 did_hit = true;
                 closest_t = temp_hit.t;
                 *hit = temp_hit;
@@ -279,17 +579,60 @@ impl<T: Copy + Hit, const N: usize> Hit for [T; N] {
         }
         did_hit
     }
+
+    fn test_cost(self) -> u32 {
+        let mut cost = 0;
+        for i in 0..N {
+            cost += self[i].test_cost();
+        }
+        cost
+    }
+}
+
+impl<'a> Hit for World<'a> {
+    fn hit(self, r: &Ray, t_min: f32, t_max: f32, hit: &mut HitData) -> bool {
+        self.hit_counted(r, t_min, t_max, hit, &mut 0)
+    }
+
+    fn hit_counted(self, r: &Ray, t_min: f32, t_max: f32, hit: &mut HitData, tests: &mut u32) -> bool {
+        let mut did_hit = false;
+        let mut closest_t = t_max;
+        let mut temp_hit = HitData::default();
+        for i in 0..self.count as usize {
+            if self.spheres[i].hit_counted(r, t_min, closest_t, &mut temp_hit, tests) {
+                did_hit = true;
+                closest_t = temp_hit.t;
+                *hit = temp_hit;
+            }
+        }
+        did_hit
+    }
+
+    fn test_cost(self) -> u32 {
+        self.count
+    }
 }
 
 impl Hit for Sphere {
     fn hit(self, r: &Ray, t_min: f32, t_max: f32, hit: &mut HitData) -> bool {
         (&self).hit(r, t_min, t_max, hit)
     }
+
+    fn hit_counted(self, r: &Ray, t_min: f32, t_max: f32, hit: &mut HitData, tests: &mut u32) -> bool {
+        (&self).hit_counted(r, t_min, t_max, hit, tests)
+    }
 }
 
 impl<'a> Hit for &'a Sphere {
     fn hit(self, r: &Ray, t_min: f32, t_max: f32, hit: &mut HitData) -> bool {
+        self.hit_counted(r, t_min, t_max, hit, &mut 0)
+    }
+
+    // Rays missing the sphere are rejected by the sign of the discriminant, before solving for
+    // the roots.
+    fn hit_counted(self, r: &Ray, t_min: f32, t_max: f32, hit: &mut HitData, tests: &mut u32) -> bool {
         let Sphere { center, radius, material } = *self;
+        let center = Vec3::from(center);
         let origin = r.origin();
         let direction = r.direction();
         let oc = origin - center;
@@ -298,6 +641,7 @@ impl<'a> Hit for &'a Sphere {
         let c = oc.dot(oc) - radius * radius;
         let discriminant = b * b - a * c;
         if discriminant > 0.0 {
+            *tests += 1;
             let mut temp = (-b - (b * b - a * c).sqrt()) / a;
             if temp < t_max && temp > t_min {
                 hit.t = temp;
@@ -320,98 +664,193 @@ impl<'a> Hit for &'a Sphere {
 }
 
 impl Material for Lambertian {
-    fn scatter(
-        self,
-        _: &Ray,
-        hit: &HitData,
-        rng: &mut Rng,
-        attenuation: &mut Vec3,
-        ray_out: &mut Ray,
-    ) -> bool {
-        self.scatter_ray(hit, rng, attenuation, ray_out);
-        true
+    fn scatter(self, ray_in: &Ray, hit: &HitData, sampler: &mut impl Sampler, sample: &mut BsdfSample) -> bool {
+        let n = face_forward(hit.normal, ray_in.direction());
+        let (t, b) = orthonormal_basis(n);
+        let local = cosine_sample_hemisphere(sampler.next_2d());
+        sample.origin = hit.p;
+        sample.direction = t * local.x + b * local.y + n * local.z;
+        sample.value = Vec3::from(self.albedo) * FRAC_1_PI;
+        sample.pdf = local.z * FRAC_1_PI;
+        sample.delta = false;
+        sample.pdf > 0.0
+    }
+
+    fn eval(self, ray_in: &Ray, hit: &HitData, wi: Vec3, pdf: &mut f32) -> Vec3 {
+        let cos = face_forward(hit.normal, ray_in.direction()).dot(wi);
+        if cos <= 0.0 {
+            *pdf = 0.0;
+            return Vec3::ZERO;
+        }
+        *pdf = cos * FRAC_1_PI;
+        Vec3::from(self.albedo) * FRAC_1_PI
+    }
+
+    fn albedo(self, _: &HitData) -> Vec3 {
+        self.albedo.into()
+    }
+
+    fn emitted(self, _: &HitData) -> Vec3 {
+        self.emission.into()
     }
 }
 
+// The fuzzed reflection has no closed form PDF, so is treated as a delta distribution.
 impl Material for Metal {
-    fn scatter(
-        self,
-        ray_in: &Ray,
-        hit: &HitData,
-        rng: &mut Rng,
-        attenuation: &mut Vec3,
-        ray_out: &mut Ray,
-    ) -> bool {
+    fn scatter(self, ray_in: &Ray, hit: &HitData, sampler: &mut impl Sampler, sample: &mut BsdfSample) -> bool {
         let reflected = reflect(unit_vector(ray_in.direction()), hit.normal);
-        *ray_out = Ray::new(hit.p, reflected + self.fuzz * random_in_unit_sphere(rng));
-        *attenuation = self.albedo;
-        ray_out.direction().dot(hit.normal) > 0.0
+        sample.origin = hit.p;
+        sample.direction = unit_vector(reflected + self.fuzz * random_in_unit_sphere(sampler));
+        sample.value = self.albedo.into();
+        sample.pdf = 1.0;
+        sample.delta = true;
+        sample.direction.dot(hit.normal) > 0.0
+    }
+
+    fn eval(self, _: &Ray, _: &HitData, _: Vec3, pdf: &mut f32) -> Vec3 {
+        *pdf = 0.0;
+        Vec3::ZERO
+    }
+
+    fn albedo(self, _: &HitData) -> Vec3 {
+        self.albedo.into()
     }
 }
 
 impl Material for Dielectric {
-    fn scatter(
+    fn scatter(self, ray_in: &Ray, hit: &HitData, sampler: &mut impl Sampler, sample: &mut BsdfSample) -> bool {
+        Self::scatter_ray(self.ref_idx[0], ray_in, hit, sampler, sample);
+        true
+    }
+
+    fn eval(self, _: &Ray, _: &HitData, _: Vec3, pdf: &mut f32) -> Vec3 {
+        *pdf = 0.0;
+        Vec3::ZERO
+    }
+
+    fn scatter_spectral(
         self,
         ray_in: &Ray,
         hit: &HitData,
-        rng: &mut Rng,
-        attenuation: &mut Vec3,
-        ray_out: &mut Ray,
+        wavelength: f32,
+        sampler: &mut impl Sampler,
+        sample: &mut BsdfSample,
     ) -> bool {
-        let ray_in_dir = ray_in.direction();
-        let reflected = reflect(ray_in_dir, hit.normal);
-        *attenuation = Vec3::ONE;
-        let ray_in_dir_dot_normal = ray_in_dir.dot(hit.normal);
-        let (outward_normal, ni_over_nt, cos) = if ray_in_dir_dot_normal > 0.0 {
-            let cos = self.ref_idx.x * ray_in_dir_dot_normal / ray_in_dir.length();
-            (-hit.normal, self.ref_idx.x, cos)
-        } else {
-            let cos = -ray_in_dir_dot_normal / ray_in_dir.length();
-            (hit.normal, 1.0 / self.ref_idx.x, cos)
-        };
-        let mut refracted = Vec3::ZERO;
-        let reflect_prob = if refract(ray_in.direction(), outward_normal, ni_over_nt, &mut refracted) {
-            schlick(cos, self.ref_idx.x)
-        } else {
-            1.0
-        };
-        if rng.gen() < reflect_prob {
-            *ray_out = Ray::new(hit.p, reflected);
-        } else {
-            *ray_out = Ray::new(hit.p, refracted);
+        Self::scatter_ray(self.ior(wavelength), ray_in, hit, sampler, sample);
+        true
+    }
+}
+
+// Paths pass straight through the boundary of the sphere, and scatter inside it with probability
+// `1 - exp(-density * distance)`. The scattering is a delta distribution as far as the integrators
+// are concerned, since nothing samples it directly.
+impl Material for Volume {
+    fn scatter(self, ray_in: &Ray, hit: &HitData, sampler: &mut impl Sampler, sample: &mut BsdfSample) -> bool {
+        let direction = ray_in.direction();
+        sample.origin = hit.p;
+        sample.direction = unit_vector(direction);
+        sample.value = Vec3::ONE;
+        sample.pdf = 1.0;
+        sample.delta = true;
+        // Leaving the sphere, the path travelled from its origin to the hit inside the medium.
+        if direction.dot(hit.normal) > 0.0 {
+            let length = direction.length();
+            let distance = -(1.0 - sampler.next_1d()).ln() / self.density;
+            if distance < hit.t * length {
+                sample.origin = ray_in.point_at_parameter(distance / length);
+                sample.direction = environment::uniform_sphere(sampler.next_2d());
+                sample.value = self.albedo.into();
+            }
         }
         true
     }
+
+    fn eval(self, _: &Ray, _: &HitData, _: Vec3, pdf: &mut f32) -> Vec3 {
+        *pdf = 0.0;
+        Vec3::ZERO
+    }
+
+    fn albedo(self, _: &HitData) -> Vec3 {
+        self.albedo.into()
+    }
 }
 
-impl<'a, const NL: usize, const NM: usize, const ND: usize> Material for &'a Materials<NL, NM, ND> {
-    fn scatter(
+impl<'a> Material for Materials<'a> {
+    fn scatter(self, ray_in: &Ray, hit: &HitData, sampler: &mut impl Sampler, sample: &mut BsdfSample) -> bool {
+        match hit.material.kind() {
+            MaterialKind::Lambertian => self.lambertian[hit.material.index as usize].scatter(ray_in, hit, sampler, sample),
+            MaterialKind::Metal => self.metal[hit.material.index as usize].scatter(ray_in, hit, sampler, sample),
+            MaterialKind::Dielectric => self.dielectric[hit.material.index as usize].scatter(ray_in, hit, sampler, sample),
+            MaterialKind::Volume => self.volume[hit.material.index as usize].scatter(ray_in, hit, sampler, sample),
+        }
+    }
+
+    fn eval(self, ray_in: &Ray, hit: &HitData, wi: Vec3, pdf: &mut f32) -> Vec3 {
+        match hit.material.kind() {
+            MaterialKind::Lambertian => self.lambertian[hit.material.index as usize].eval(ray_in, hit, wi, pdf),
+            MaterialKind::Metal => self.metal[hit.material.index as usize].eval(ray_in, hit, wi, pdf),
+            MaterialKind::Dielectric => self.dielectric[hit.material.index as usize].eval(ray_in, hit, wi, pdf),
+            MaterialKind::Volume => self.volume[hit.material.index as usize].eval(ray_in, hit, wi, pdf),
+        }
+    }
+
+    fn scatter_spectral(
         self,
         ray_in: &Ray,
         hit: &HitData,
-        rng: &mut Rng,
-        attenuation: &mut Vec3,
-        ray_out: &mut Ray,
+        wavelength: f32,
+        sampler: &mut impl Sampler,
+        sample: &mut BsdfSample,
     ) -> bool {
-        match hit.material.kind {
-            MaterialKind::Lambertian => {
-                self.lambertian[hit.material.index].scatter(ray_in, hit, rng, attenuation, ray_out)
-            }
-            MaterialKind::Metal => {
-                self.metal[hit.material.index].scatter(ray_in, hit, rng, attenuation, ray_out)
-            }
-            MaterialKind::Dielectric => {
-                self.dielectric[hit.material.index].scatter(ray_in, hit, rng, attenuation, ray_out)
-            }
+        match hit.material.kind() {
+            MaterialKind::Lambertian => self.lambertian[hit.material.index as usize]
+                .scatter_spectral(ray_in, hit, wavelength, sampler, sample),
+            MaterialKind::Metal => self.metal[hit.material.index as usize]
+                .scatter_spectral(ray_in, hit, wavelength, sampler, sample),
+            MaterialKind::Dielectric => self.dielectric[hit.material.index as usize]
+                .scatter_spectral(ray_in, hit, wavelength, sampler, sample),
+            MaterialKind::Volume => self.volume[hit.material.index as usize]
+                .scatter_spectral(ray_in, hit, wavelength, sampler, sample),
+        }
+    }
+
+    fn albedo(self, hit: &HitData) -> Vec3 {
+        match hit.material.kind() {
+            MaterialKind::Lambertian => self.lambertian[hit.material.index as usize].albedo(hit),
+            MaterialKind::Metal => self.metal[hit.material.index as usize].albedo(hit),
+            MaterialKind::Dielectric => self.dielectric[hit.material.index as usize].albedo(hit),
+            MaterialKind::Volume => self.volume[hit.material.index as usize].albedo(hit),
+        }
+    }
+
+    fn emitted(self, hit: &HitData) -> Vec3 {
+        match hit.material.kind() {
+            MaterialKind::Lambertian => self.lambertian[hit.material.index as usize].emitted(hit),
+            MaterialKind::Metal => self.metal[hit.material.index as usize].emitted(hit),
+            MaterialKind::Dielectric => self.dielectric[hit.material.index as usize].emitted(hit),
+            MaterialKind::Volume => self.volume[hit.material.index as usize].emitted(hit),
+        }
+    }
+}
+
+impl MaterialInfo {
+    pub fn new(kind: MaterialKind, index: u32) -> Self {
+        MaterialInfo { kind: kind as u32, index }
+    }
+
+    pub fn kind(&self) -> MaterialKind {
+        match self.kind {
+            1 => MaterialKind::Metal,
+            2 => MaterialKind::Dielectric,
+            3 => MaterialKind::Volume,
+            _ => MaterialKind::Lambertian,
         }
     }
 }
 
 impl Default for MaterialInfo {
     fn default() -> Self {
-        let kind = Default::default();
-        let index = 0;
-        MaterialInfo { kind, index }
+        MaterialInfo::new(Default::default(), 0)
     }
 }
 
@@ -425,27 +864,55 @@ pub fn unit_vector(v: Vec3) -> Vec3 {
     v / v.length()
 }
 
-fn random_in_unit_sphere(rng: &mut Rng) -> Vec3 {
-    let mut p;
-    loop {
-        let rv = vec3(rng.gen(), rng.gen(), rng.gen());
-        p = 2.0 * rv - Vec3::ONE;
-        let p_len2 = p.length_squared();
-        if p_len2 < 1.0 {
-            break p;
-        }
+/// Two unit vectors forming an orthonormal basis with the unit vector `n`.
+///
+/// Uses the branchless construction from Duff et al's "Building an Orthonormal Basis, Revisited".
+pub fn orthonormal_basis(n: Vec3) -> (Vec3, Vec3) {
+    let sign = 1.0.copysign(n.z);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    let t = vec3(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x);
+    let bt = vec3(b, sign + n.y * n.y * a, -n.y);
+    (t, bt)
+}
+
+// Uniform in the unit ball, mapping three dimensions directly so that stratification is kept.
+fn random_in_unit_sphere(sampler: &mut impl Sampler) -> Vec3 {
+    let u = sampler.next_2d();
+    let r = sampler.next_1d().powf(1.0 / 3.0);
+    let z = 1.0 - 2.0 * u.x;
+    let s = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+    vec3(s * phi.cos(), s * phi.sin(), z) * r
+}
+
+/// `n` flipped to face against the direction `d`.
+pub fn face_forward(n: Vec3, d: Vec3) -> Vec3 {
+    if n.dot(d) > 0.0 {
+        -n
+    } else {
+        n
     }
 }
 
-fn random_in_unit_disk(rng: &mut Rng) -> Vec3 {
-    let mut p;
-    loop {
-        let rv = vec3(rng.gen(), rng.gen(), 0.0);
-        p = 2.0 * rv - vec3(1.0, 1.0, 0.0);
-        if p.dot(p) < 1.0 {
-            break p;
-        }
+/// A cosine distributed direction about `z` with a density of `z / π`, by Malley's method.
+pub fn cosine_sample_hemisphere(u: Vec2) -> Vec3 {
+    let d = random_in_unit_disk(u);
+    vec3(d.x, d.y, (1.0 - d.x * d.x - d.y * d.y).max(0.0).sqrt())
+}
+
+// Uniform in the unit disk via Shirley and Chiu's concentric mapping.
+fn random_in_unit_disk(u: Vec2) -> Vec3 {
+    let u = 2.0 * u - Vec2::ONE;
+    if u.x == 0.0 && u.y == 0.0 {
+        return Vec3::ZERO;
     }
+    let (r, theta) = if u.x.abs() > u.y.abs() {
+        (u.x, FRAC_PI_4 * (u.y / u.x))
+    } else {
+        (u.y, FRAC_PI_2 - FRAC_PI_4 * (u.x / u.y))
+    };
+    vec3(r * theta.cos(), r * theta.sin(), 0.0)
 }
 
 pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
@@ -471,37 +938,153 @@ pub fn schlick(cos: f32, ref_idx: f32) -> f32 {
 }
 
 pub fn color(
-    ray_bounce_limit: u32,
-    rng: &mut Rng,
+    termination: PathTermination,
+    sampler: &mut impl Sampler,
     mut ray: Ray,
     world: impl Copy + Hit,
     materials: impl Copy + Material,
+    env: impl Copy + Environment,
+    stats: &mut PathStats,
 ) -> Vec3 {
     let mut hit = HitData::default();
-    let mut scattered = Ray::new(Vec3::ZERO, Vec3::ONE); // placeholder to initialise.
-    let mut attenuation = Vec3::default();
+    let mut sample = BsdfSample::default();
 
     let min_f = 0.001;
     let max_f = core::f32::MAX;
     let mut color = Vec3::ONE;
+    let mut emitted = Vec3::ZERO;
     let mut bounces = 0;
-    while world.hit(&ray, min_f, max_f, &mut hit) {
-        if bounces < ray_bounce_limit && materials.scatter(&ray, &hit, rng, &mut attenuation, &mut scattered) {
-            color *= attenuation;
-            ray = scattered;
+    while world.hit_counted(&ray, min_f, max_f, &mut hit, &mut stats.tests) {
+        emitted += color * materials.emitted(&hit);
+        if bounces < termination.bounce_limit && materials.scatter(&ray, &hit, sampler, &mut sample) {
+            color *= sample.weight(hit.normal);
+            ray = Ray::new(sample.origin, sample.direction);
         } else {
             color = Vec3::ZERO;
             break;
         }
         bounces += 1;
+        let p = termination.continue_probability(bounces, color.max_element());
+        if p < 1.0 {
+            if sampler.next_1d() >= p {
+                color = Vec3::ZERO;
+                break;
+            }
+            color /= p;
+        }
+    }
+    stats.bounces = bounces;
+
+    let sky = env.radiance(ray.direction());
+    emitted + sky * color
+}
+
+/// The spectral equivalent of `color`, tracing the ray at a single wavelength in nanometres.
+///
+/// Returns the radiance carried at that wavelength. Use `spectral::to_linear_srgb` to convert the
+/// result for display.
+pub fn color_spectral(
+    termination: PathTermination,
+    sampler: &mut impl Sampler,
+    mut ray: Ray,
+    wavelength: f32,
+    world: impl Copy + Hit,
+    materials: impl Copy + Material,
+    env: impl Copy + Environment,
+    stats: &mut PathStats,
+) -> f32 {
+    let mut hit = HitData::default();
+    let mut sample = BsdfSample::default();
+
+    let min_f = 0.001;
+    let max_f = core::f32::MAX;
+    let mut throughput = 1.0;
+    let mut emitted = 0.0;
+    let mut bounces = 0;
+    while world.hit_counted(&ray, min_f, max_f, &mut hit, &mut stats.tests) {
+        emitted += throughput * spectral::rgb_to_spectrum(materials.emitted(&hit), wavelength);
+        if bounces < termination.bounce_limit
+            && materials.scatter_spectral(&ray, &hit, wavelength, sampler, &mut sample)
+        {
+            throughput *= sample.weight(hit.normal).x;
+            ray = Ray::new(sample.origin, sample.direction);
+        } else {
+            throughput = 0.0;
+            break;
+        }
+        bounces += 1;
+        let p = termination.continue_probability(bounces, throughput);
+        if p < 1.0 {
+            if sampler.next_1d() >= p {
+                throughput = 0.0;
+                break;
+            }
+            throughput /= p;
+        }
     }
+    stats.bounces = bounces;
+
+    let sky = spectral::rgb_to_spectrum(env.radiance(ray.direction()), wavelength);
+    emitted + sky * throughput
+}
+
+/// Trace `rays_per_pixel` samples of the pixel at `frag_coord` through `cam`, returning the mean of
+/// their clamped radiance and gathering their statistics in `stats`.
+///
+/// This is the sampling loop of `main_fs` and `main_cs`, which the golden image tests also run on
+/// the CPU. Samples are numbered by `constants.frame` and `constants.rays_per_pixel` rather than
+/// `rays_per_pixel`, which is zero for pixels that have converged.
+#[allow(clippy::too_many_arguments)]
+pub fn trace_pixel(
+    constants: &ShaderConstants,
+    cam: &Camera,
+    frag_coord: Vec2,
+    rays_per_pixel: u32,
+    world: impl Copy + Hit,
+    materials: impl Copy + Material,
+    env: impl Copy + Environment,
+    stats: &mut PixelStats,
+) -> Vec3 {
+    let [w_px, h_px] = constants.view_size_pixels;
+    let pixel = [frag_coord.x as u32, frag_coord.y as u32];
+    let first_sample = constants.frame.wrapping_mul(constants.rays_per_pixel);
+    let mut sampler = Samplers::new(constants.sampler, pixel, first_sample);
 
-    let sky = color_sky(&ray);
-    sky * color
+    let termination = PathTermination {
+        bounce_limit: constants.ray_bounce_limit,
+        roulette_depth: constants.roulette_depth,
+    };
+    let mut col = vec3(0.0, 0.0, 0.0);
+    for i in 0..rays_per_pixel {
+        sampler.start_sample(first_sample.wrapping_add(i));
+        let jitter = sampler.next_2d();
+        let uv = vec2(
+            (frag_coord.x + jitter.x) / w_px as f32,
+            ((h_px as f32 - frag_coord.y) + jitter.y) / h_px as f32,
+        );
+        let ray = cam.ray(&mut sampler, uv);
+        let mut path = PathStats::default();
+        let sample = if constants.spectral != 0 {
+            let wavelength = spectral::sample_wavelength(sampler.next_1d());
+            let radiance = color_spectral(termination, &mut sampler, ray, wavelength, world, materials, env, &mut path);
+            spectral::to_linear_srgb(radiance, wavelength)
+        } else {
+            color(termination, &mut sampler, ray, world, materials, env, &mut path)
+        };
+        // Gather statistics before clamping so that the debug modes show fireflies.
+        stats.add(sample, &path);
+        col += clamp_radiance(sample, constants.radiance_clamp);
+    }
+    col / rays_per_pixel.max(1) as f32
 }
 
-fn color_sky(ray: &Ray) -> Vec3 {
-    let unit_direction = unit_vector(ray.direction()) * 2.0;
-    let t = 0.5 * (unit_direction.y + 1.0);
-    (1.0 - t) * vec3(1.0, 1.0, 1.0) + t * vec3(0.5, 0.7, 1.0)
+/// Scale `radiance` down so that no component exceeds `max`, suppressing fireflies at the cost of
+/// some energy. A `max` of zero leaves the radiance unchanged.
+pub fn clamp_radiance(radiance: Vec3, max: f32) -> Vec3 {
+    let m = radiance.max_element();
+    if max > 0.0 && m > max {
+        radiance * (max / m)
+    } else {
+        radiance
+    }
 }
@@ -0,0 +1,305 @@
+//! Sources of uniformly distributed sample values for Monte Carlo integration.
+//!
+//! Each pixel owns a sampler that produces the dimensions of one sample after another. The
+//! low-discrepancy samplers stratify each dimension over the sample indices of a pixel, which are
+//! offset by the frame so that accumulating frames keeps refining the same sequence.
+
+use spirv_std::glam::{vec2, Vec2};
+
+/// `ShaderConstants::sampler` value selecting `Pcg`.
+pub const PCG: u32 = 0;
+/// `ShaderConstants::sampler` value selecting `Sobol`.
+pub const SOBOL: u32 = 1;
+/// `ShaderConstants::sampler` value selecting `Halton`.
+pub const HALTON: u32 = 2;
+/// `ShaderConstants::sampler` value selecting `BlueNoise`.
+pub const BLUE_NOISE: u32 = 3;
+
+// The Halton bases. Dimensions beyond these fall back to independent random values, as reusing a
+// base would correlate the dimensions.
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+/// Produces the dimensions of a sample in `0.0..1.0`, one after another.
+pub trait Sampler {
+    /// Begin the sample with the given index within the pixel, restarting at the first dimension.
+    fn start_sample(&mut self, index: u32);
+
+    /// The next dimension of the current sample.
+    fn next_1d(&mut self) -> f32;
+
+    /// The next two dimensions of the current sample.
+    fn next_2d(&mut self) -> Vec2 {
+        let x = self.next_1d();
+        let y = self.next_1d();
+        vec2(x, y)
+    }
+}
+
+/// Independent uniform random numbers from the PCG RXS-M-XS generator.
+#[derive(Copy, Clone, Default)]
+pub struct Pcg {
+    pub seed: u32,
+    pub state: u32,
+}
+
+/// The Sobol sequence padded from pairs of dimensions, using Owen scrambling and index shuffling
+/// per pair as described in Burley's "Practical Hash-based Owen Scrambling".
+#[derive(Copy, Clone, Default)]
+pub struct Sobol {
+    pub seed: u32,
+    pub index: u32,
+    pub dimension: u32,
+}
+
+/// The Halton sequence, Owen scrambled per pixel and dimension.
+///
+/// The sample indices of each pixel are shuffled within aligned power of two blocks, so that any
+/// such block still contains the same Halton points while neighbouring pixels aren't correlated.
+#[derive(Copy, Clone, Default)]
+pub struct Halton {
+    pub seed: u32,
+    pub index: u32,
+    pub dimension: u32,
+}
+
+/// A Sobol sequence shared by every pixel and rotated per pixel and dimension by interleaved
+/// gradient noise, following Georgiev and Fajardo's "Blue-noise Dithered Sampling".
+///
+/// This moves the error between neighbouring pixels to high frequencies, where it is less visible
+/// and more easily removed by the denoiser.
+#[derive(Copy, Clone, Default)]
+pub struct BlueNoise {
+    pub pixel: [u32; 2],
+    pub index: u32,
+    pub dimension: u32,
+}
+
+/// Selects between samplers using the `ShaderConstants::sampler` kind.
+#[derive(Copy, Clone, Default)]
+pub struct Samplers {
+    pub kind: u32,
+    pub pcg: Pcg,
+    pub sobol: Sobol,
+    pub halton: Halton,
+    pub blue_noise: BlueNoise,
+}
+
+impl Pcg {
+    pub fn new(seed: u32) -> Self {
+        Pcg { seed, state: seed }
+    }
+}
+
+impl Samplers {
+    /// A sampler of the given kind for a pixel.
+    ///
+    /// `first_index` offsets the sample indices passed to `start_sample`, e.g. by the number of
+    /// samples taken in previous frames.
+    pub fn new(kind: u32, pixel: [u32; 2], first_index: u32) -> Self {
+        let seed = hash_combine(hash(pixel[0]), pixel[1]);
+        Samplers {
+            kind,
+            pcg: Pcg::new(hash_combine(seed, first_index)),
+            sobol: Sobol {
+                seed,
+                index: first_index,
+                dimension: 0,
+            },
+            halton: Halton {
+                seed,
+                index: first_index,
+                dimension: 0,
+            },
+            blue_noise: BlueNoise {
+                pixel,
+                index: first_index,
+                dimension: 0,
+            },
+        }
+    }
+}
+
+impl Sampler for Pcg {
+    fn start_sample(&mut self, index: u32) {
+        self.state = hash_combine(self.seed, index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        self.state = self.state.wrapping_mul(747_796_405).wrapping_add(2_891_336_453);
+        to_unit_float(pcg_output(self.state))
+    }
+}
+
+impl Sampler for Sobol {
+    fn start_sample(&mut self, index: u32) {
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let x = owen_scrambled_sobol(self.index, self.dimension, self.seed);
+        self.dimension += 1;
+        x
+    }
+}
+
+impl Sampler for Halton {
+    fn start_sample(&mut self, index: u32) {
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        let index = nested_uniform_scramble(self.index, self.seed);
+        let seed = hash_combine(self.seed, dimension);
+        if dimension as usize >= PRIMES.len() {
+            return to_unit_float(hash_combine(seed, index));
+        }
+        owen_scrambled_radical_inverse(PRIMES[dimension as usize], index, seed)
+    }
+}
+
+impl Sampler for BlueNoise {
+    fn start_sample(&mut self, index: u32) {
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        // Shift the noise per dimension so that the rotations of dimensions aren't correlated.
+        let shift = hash(self.dimension);
+        let x = (self.pixel[0].wrapping_add(shift & 0xffff) & 0xffff) as f32;
+        let y = (self.pixel[1].wrapping_add(shift >> 16) & 0xffff) as f32;
+        let rotation = interleaved_gradient_noise(x, y);
+        let x = owen_scrambled_sobol(self.index, self.dimension, 0);
+        self.dimension += 1;
+        fract(x + rotation)
+    }
+}
+
+impl Sampler for Samplers {
+    fn start_sample(&mut self, index: u32) {
+        match self.kind {
+            SOBOL => self.sobol.start_sample(index),
+            HALTON => self.halton.start_sample(index),
+            BLUE_NOISE => self.blue_noise.start_sample(index),
+            _ => self.pcg.start_sample(index),
+        }
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        match self.kind {
+            SOBOL => self.sobol.next_1d(),
+            HALTON => self.halton.next_1d(),
+            BLUE_NOISE => self.blue_noise.next_1d(),
+            _ => self.pcg.next_1d(),
+        }
+    }
+}
+
+/// A well distributed 32-bit hash, applying a single PCG step and output permutation.
+pub fn hash(x: u32) -> u32 {
+    pcg_output(x.wrapping_mul(747_796_405).wrapping_add(2_891_336_453))
+}
+
+/// Mix `v` into the hash `seed`.
+pub fn hash_combine(seed: u32, v: u32) -> u32 {
+    hash(seed ^ v.wrapping_add(0x9e37_79b9).wrapping_add(seed << 6).wrapping_add(seed >> 2))
+}
+
+/// A dimension of the Sobol sequence padded from 2D points, with the points of each pair of
+/// dimensions shuffled and their values Owen scrambled using hashes of `seed`.
+pub fn owen_scrambled_sobol(index: u32, dimension: u32, seed: u32) -> f32 {
+    let pair = dimension / 2;
+    let index = nested_uniform_scramble(index, hash_combine(seed, pair));
+    let x = sobol(index, dimension % 2);
+    to_unit_float(nested_uniform_scramble(x, hash_combine(seed, 0x8000_0000 | dimension)))
+}
+
+/// The `index`th point of the first or second Sobol dimension as a 32-bit fixed point fraction.
+pub fn sobol(index: u32, dimension: u32) -> u32 {
+    if dimension == 0 {
+        return reverse_bits(index);
+    }
+    let mut x = 0;
+    let mut v = 1 << 31;
+    let mut i = index;
+    while i != 0 {
+        if i & 1 != 0 {
+            x ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    x
+}
+
+/// The radical inverse of `index` in the given base, mirroring its digits about the point, with
+/// each digit shifted by a hash of `seed` and the digits before it.
+///
+/// Every digit is scrambled down to the precision of an f32, including the zeros past the last
+/// digit of `index`, which makes this a nested uniform scramble in the sense of Owen.
+pub fn owen_scrambled_radical_inverse(base: u32, index: u32, seed: u32) -> f32 {
+    let inv_base = 1.0 / base as f32;
+    let mut inv = inv_base;
+    let mut result = 0.0;
+    let mut i = index;
+    let mut prefix = seed;
+    while inv > f32::EPSILON * 0.5 {
+        let digit = i % base;
+        result += ((digit + hash(prefix) % base) % base) as f32 * inv;
+        prefix = hash_combine(prefix, digit);
+        i /= base;
+        inv *= inv_base;
+    }
+    result.min(ONE_MINUS_EPSILON)
+}
+
+/// Jimenez's interleaved gradient noise for a pixel.
+pub fn interleaved_gradient_noise(x: f32, y: f32) -> f32 {
+    fract(52.982_918 * fract(0.067_110_56 * x + 0.005_837_15 * y))
+}
+
+// The largest float below one.
+const ONE_MINUS_EPSILON: f32 = 0.999_999_94;
+
+fn pcg_output(state: u32) -> u32 {
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277_803_737);
+    (word >> 22) ^ word
+}
+
+// Owen scrambling of a fixed point fraction via Laine and Karras's hash-based permutation.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    reverse_bits(laine_karras_permutation(reverse_bits(x), seed))
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+fn reverse_bits(mut x: u32) -> u32 {
+    x = ((x >> 1) & 0x5555_5555) | ((x & 0x5555_5555) << 1);
+    x = ((x >> 2) & 0x3333_3333) | ((x & 0x3333_3333) << 2);
+    x = ((x >> 4) & 0x0f0f_0f0f) | ((x & 0x0f0f_0f0f) << 4);
+    x = ((x >> 8) & 0x00ff_00ff) | ((x & 0x00ff_00ff) << 8);
+    (x >> 16) | (x << 16)
+}
+
+// Map the upper 24 bits to `0.0..1.0`, the most that an f32 represents exactly.
+fn to_unit_float(x: u32) -> f32 {
+    (x >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+}
+
+fn fract(x: f32) -> f32 {
+    x - (x as i32) as f32
+}
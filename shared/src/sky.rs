@@ -0,0 +1,205 @@
+//! The Preetham analytic daylight model with a finite-size sun disk.
+//!
+//! Based on "A Practical Analytic Model for Daylight" by Preetham, Shirley & Smits.
+
+use crate::environment::uniform_sphere;
+use crate::{orthonormal_basis, spectral, unit_vector, Environment};
+use core::f32::consts::PI;
+use spirv_std::{
+    glam::{vec3, Vec2, Vec3},
+    num_traits::Float,
+};
+
+/// The angular radius of the sun disk in radians.
+///
+/// Roughly four times the real sun to keep noise down when it is hit by chance.
+pub const SUN_ANGULAR_RADIUS: f32 = 0.02;
+
+// Irradiance of the sun at the zenith before atmospheric extinction. Independent of
+// `SUN_ANGULAR_RADIUS` so that resizing the disk doesn't change the lighting.
+const SUN_IRRADIANCE: f32 = 20.0;
+
+// Scales the model's kcd/m² luminance to roughly match the brightness of the old sky gradient.
+const SKY_SCALE: f32 = 0.08;
+
+// The highest elevation reached by the sun at midday, in radians.
+const SUN_MAX_ELEVATION: f32 = PI / 3.0;
+
+// The fraction of `sample` calls that target the sun rather than the whole sphere.
+const SUN_SAMPLE_PROBABILITY: f32 = 0.9;
+
+/// A sky lit by the sun, with coefficients precomputed for a sun position and turbidity.
+#[derive(Copy, Clone)]
+pub struct PreethamSky {
+    pub sun_dir: Vec3,
+    /// The sun's radiance after atmospheric extinction.
+    pub sun_radiance: Vec3,
+    pub cos_sun_radius: f32,
+    /// Zenith luminance and chromaticity as `(Y, x, y)`.
+    zenith: Vec3,
+    // Perez distribution coefficients A to E, each for the `(Y, x, y)` channels.
+    a: Vec3,
+    b: Vec3,
+    c: Vec3,
+    d: Vec3,
+    e: Vec3,
+    // `perez(0, theta_s)`, normalising the distribution to the zenith.
+    perez_zenith: Vec3,
+    intensity: f32,
+}
+
+impl PreethamSky {
+    /// `elevation` and `azimuth` of the sun are in radians, `turbidity` is typically `2..10`.
+    pub fn new(elevation: f32, azimuth: f32, turbidity: f32, intensity: f32) -> Self {
+        let t = turbidity;
+        let sun_dir = sun_direction(elevation, azimuth);
+
+        // The model only holds with the sun above the horizon, so fade out towards night.
+        let theta_s = (PI * 0.5 - elevation).max(0.0).min(PI * 0.5 - 0.01);
+        let night = smoothstep(-0.1, 0.05, elevation);
+        let intensity = intensity * night;
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let th = vec3(theta_s * theta_s * theta_s, theta_s * theta_s, theta_s);
+        let zenith_x = (t * t * vec3(0.00166, -0.00375, 0.00209).dot(th))
+            + t * (vec3(-0.02903, 0.06377, -0.03202).dot(th) + 0.00394)
+            + vec3(0.11693, -0.21196, 0.06052).dot(th)
+            + 0.25886;
+        let zenith_yc = (t * t * vec3(0.00275, -0.00610, 0.00317).dot(th))
+            + t * (vec3(-0.04214, 0.08970, -0.04153).dot(th) + 0.00516)
+            + vec3(0.15346, -0.26756, 0.06670).dot(th)
+            + 0.26688;
+        let zenith = vec3(zenith_y, zenith_x, zenith_yc);
+
+        let a = vec3(0.1787 * t - 1.4630, -0.0193 * t - 0.2592, -0.0167 * t - 0.2608);
+        let b = vec3(-0.3554 * t + 0.4275, -0.0665 * t + 0.0008, -0.0950 * t + 0.0092);
+        let c = vec3(-0.0227 * t + 5.3251, -0.0004 * t + 0.2125, -0.0079 * t + 0.2102);
+        let d = vec3(0.1206 * t - 2.5771, -0.0641 * t - 0.8989, -0.0441 * t - 1.6537);
+        let e = vec3(-0.0670 * t + 0.3703, -0.0033 * t + 0.0452, -0.0109 * t + 0.0529);
+        let perez_zenith = perez(1.0, theta_s, theta_s.cos(), a, b, c, d, e);
+
+        let sun_radius = SUN_ANGULAR_RADIUS;
+        let cos_sun_radius = sun_radius.cos();
+        let solid_angle = 2.0 * PI * (1.0 - cos_sun_radius);
+        let sun_radiance = sun_transmittance(theta_s, t) * (SUN_IRRADIANCE / solid_angle) * intensity;
+
+        PreethamSky {
+            sun_dir,
+            sun_radiance,
+            cos_sun_radius,
+            zenith,
+            a,
+            b,
+            c,
+            d,
+            e,
+            perez_zenith,
+            intensity,
+        }
+    }
+
+    /// The radiance of the sky alone, excluding the sun disk.
+    pub fn sky_radiance(&self, dir: Vec3) -> Vec3 {
+        // Below the horizon, continue the horizon's colour.
+        let cos_theta = dir.y.max(0.01);
+        let cos_gamma = dir.dot(self.sun_dir).max(-1.0).min(1.0);
+        let gamma = cos_gamma.acos();
+        let f = perez(cos_theta, gamma, cos_gamma, self.a, self.b, self.c, self.d, self.e);
+        let yxy = self.zenith * f / self.perez_zenith;
+        let (lum, x, y) = (yxy.x, yxy.y, yxy.z);
+        let xyz = vec3(x / y * lum, lum, (1.0 - x - y) / y * lum);
+        spectral::xyz_to_linear_srgb(xyz).max(Vec3::ZERO) * (SKY_SCALE * self.intensity)
+    }
+
+    fn sun_cone_pdf(&self) -> f32 {
+        1.0 / (2.0 * PI * (1.0 - self.cos_sun_radius))
+    }
+}
+
+impl Environment for PreethamSky {
+    fn radiance(self, dir: Vec3) -> Vec3 {
+        let dir = unit_vector(dir);
+        let sky = self.sky_radiance(dir);
+        if dir.dot(self.sun_dir) >= self.cos_sun_radius {
+            sky + self.sun_radiance
+        } else {
+            sky
+        }
+    }
+
+    fn sample(self, u: Vec2, pdf: &mut f32) -> Vec3 {
+        // Mostly sample the sun cone, but cover the whole sky so that every direction with
+        // radiance may be sampled.
+        let dir = if u.x < SUN_SAMPLE_PROBABILITY {
+            let ux = u.x / SUN_SAMPLE_PROBABILITY;
+            let cos_theta = 1.0 - ux * (1.0 - self.cos_sun_radius);
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * PI * u.y;
+            let (t, b) = orthonormal_basis(self.sun_dir);
+            (t * phi.cos() + b * phi.sin()) * sin_theta + self.sun_dir * cos_theta
+        } else {
+            let ux = (u.x - SUN_SAMPLE_PROBABILITY) / (1.0 - SUN_SAMPLE_PROBABILITY);
+            uniform_sphere(Vec2::new(ux, u.y))
+        };
+        *pdf = self.pdf(dir);
+        dir
+    }
+
+    fn pdf(self, dir: Vec3) -> f32 {
+        let uniform = (1.0 - SUN_SAMPLE_PROBABILITY) / (4.0 * PI);
+        if unit_vector(dir).dot(self.sun_dir) >= self.cos_sun_radius {
+            SUN_SAMPLE_PROBABILITY * self.sun_cone_pdf() + uniform
+        } else {
+            uniform
+        }
+    }
+}
+
+/// The unit direction towards the sun, with an azimuth of zero along `+x`.
+pub fn sun_direction(elevation: f32, azimuth: f32) -> Vec3 {
+    let cos_elevation = elevation.cos();
+    vec3(
+        cos_elevation * azimuth.cos(),
+        elevation.sin(),
+        cos_elevation * azimuth.sin(),
+    )
+}
+
+/// The sun's `(elevation, azimuth)` in radians for an hour of the day in `0.0..24.0`.
+///
+/// The sun rises along `+x` at 6:00, is behind the default camera at midday and sets along `-x`
+/// at 18:00.
+pub fn sun_position(time_of_day: f32) -> (f32, f32) {
+    let angle = (time_of_day - 6.0) / 12.0 * PI;
+    (SUN_MAX_ELEVATION * angle.sin(), angle)
+}
+
+// The Perez et al. sky luminance distribution for each of the `(Y, x, y)` channels.
+#[allow(clippy::too_many_arguments)]
+fn perez(cos_theta: f32, gamma: f32, cos_gamma: f32, a: Vec3, b: Vec3, c: Vec3, d: Vec3, e: Vec3) -> Vec3 {
+    let exp3 = |v: Vec3| vec3(v.x.exp(), v.y.exp(), v.z.exp());
+    let horizon = Vec3::ONE + a * exp3(b / cos_theta);
+    let circumsolar = Vec3::ONE + c * exp3(d * gamma) + e * (cos_gamma * cos_gamma);
+    horizon * circumsolar
+}
+
+// Approximate RGB transmittance of sunlight through the atmosphere for the sun at `theta_s`
+// from the zenith, due to Rayleigh and aerosol scattering.
+fn sun_transmittance(theta_s: f32, turbidity: f32) -> Vec3 {
+    let theta_deg = theta_s.to_degrees();
+    let relative_optical_mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_deg).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let alpha = 1.3;
+    let tau = |wavelength_um: f32| {
+        let rayleigh = 0.008735 * wavelength_um.powf(-4.08);
+        let aerosol = beta * wavelength_um.powf(-alpha);
+        (-relative_optical_mass * (rayleigh + aerosol)).exp()
+    };
+    vec3(tau(0.65), tau(0.55), tau(0.45))
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).max(0.0).min(1.0);
+    t * t * (3.0 - 2.0 * t)
+}
@@ -0,0 +1,139 @@
+//! Helpers for tracing rays at a single wavelength rather than with RGB throughput.
+//!
+//! Each camera ray samples a wavelength, RGB albedos are uplifted to a reflectance at that
+//! wavelength and the resulting radiance is splatted into CIE XYZ before being converted back to
+//! linear sRGB for display.
+
+use spirv_std::{
+    glam::{vec3, Vec3},
+    num_traits::Float,
+};
+
+/// The shortest wavelength sampled in nanometres.
+pub const WAVELENGTH_MIN: f32 = 380.0;
+/// The longest wavelength sampled in nanometres.
+pub const WAVELENGTH_MAX: f32 = 720.0;
+
+/// The wavelength of the Fraunhofer d line in nanometres, at which `Dielectric::ref_idx` is given.
+pub const WAVELENGTH_D: f32 = 587.6;
+/// The wavelength of the Fraunhofer F line in nanometres.
+pub const WAVELENGTH_F: f32 = 486.1;
+/// The wavelength of the Fraunhofer C line in nanometres.
+pub const WAVELENGTH_C: f32 = 656.3;
+
+// The integral of the CIE Y matching function over the sampled range, used to normalise so that a
+// constant spectrum of 1.0 has a luminance of 1.0.
+const CIE_Y_INTEGRAL: f32 = 106.911_87;
+
+// Linear sRGB of a constant spectrum of 1.0 after normalising by `CIE_Y_INTEGRAL`. Dividing by
+// this is a simple von Kries style white balance so that RGB white round-trips to white.
+const WHITE_RGB: [f32; 3] = [1.200_622, 0.949_736, 0.907_766];
+
+// Smits' "An RGB to Spectrum Conversion for Reflectances" basis spectra, in 10 equal bins over
+// `WAVELENGTH_MIN..WAVELENGTH_MAX`.
+const SMITS_BINS: usize = 10;
+const SMITS_WHITE: [f32; SMITS_BINS] = [1.0, 1.0, 0.9999, 0.9993, 0.9992, 0.9998, 1.0, 1.0, 1.0, 1.0];
+const SMITS_CYAN: [f32; SMITS_BINS] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0, 0.0, 0.0];
+const SMITS_MAGENTA: [f32; SMITS_BINS] = [1.0, 1.0, 0.9685, 0.2229, 0.0, 0.0458, 0.8369, 1.0, 1.0, 0.9959];
+const SMITS_YELLOW: [f32; SMITS_BINS] = [0.0001, 0.0, 0.1088, 0.6651, 1.0, 1.0, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f32; SMITS_BINS] = [0.1012, 0.0515, 0.0, 0.0, 0.0, 0.0, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f32; SMITS_BINS] = [0.0, 0.0, 0.0273, 0.7937, 1.0, 0.9418, 0.1719, 0.0, 0.0, 0.0025];
+const SMITS_BLUE: [f32; SMITS_BINS] = [1.0, 1.0, 0.8916, 0.3323, 0.0, 0.0, 0.0003, 0.0369, 0.0483, 0.0496];
+
+/// Map a uniform random number in `0.0..1.0` to a wavelength in nanometres.
+pub fn sample_wavelength(u: f32) -> f32 {
+    WAVELENGTH_MIN + u * (WAVELENGTH_MAX - WAVELENGTH_MIN)
+}
+
+/// The probability density of `sample_wavelength` producing any one wavelength.
+pub fn wavelength_pdf() -> f32 {
+    1.0 / (WAVELENGTH_MAX - WAVELENGTH_MIN)
+}
+
+/// The CIE 1931 colour matching functions at the given wavelength in nanometres.
+///
+/// Uses the multi-lobe gaussian fit from Wyman, Sloan & Shirley's "Simple Analytic Approximations
+/// to the CIE XYZ Color Matching Functions".
+pub fn cie_xyz(wavelength: f32) -> Vec3 {
+    let l = wavelength;
+    let x = 1.056 * gaussian(l, 599.8, 37.9, 31.0) + 0.362 * gaussian(l, 442.0, 16.0, 26.7)
+        - 0.065 * gaussian(l, 501.1, 20.4, 26.2);
+    let y = 0.821 * gaussian(l, 568.8, 46.9, 40.5) + 0.286 * gaussian(l, 530.9, 16.3, 31.1);
+    let z = 1.217 * gaussian(l, 437.0, 11.8, 36.0) + 0.681 * gaussian(l, 459.0, 26.0, 13.8);
+    vec3(x, y, z)
+}
+
+/// Convert CIE XYZ to linear sRGB (D65).
+pub fn xyz_to_linear_srgb(xyz: Vec3) -> Vec3 {
+    vec3(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    )
+}
+
+/// Convert radiance carried at a single sampled wavelength to a linear sRGB estimate.
+///
+/// Averaging the results over many uniformly sampled wavelengths converges to the colour of the
+/// full spectrum.
+pub fn to_linear_srgb(radiance: f32, wavelength: f32) -> Vec3 {
+    let xyz = cie_xyz(wavelength) * (radiance / (wavelength_pdf() * CIE_Y_INTEGRAL));
+    let [wr, wg, wb] = WHITE_RGB;
+    xyz_to_linear_srgb(xyz) / vec3(wr, wg, wb)
+}
+
+/// Uplift a linear RGB colour to a spectral value at the given wavelength in nanometres.
+///
+/// Uses Smits' method, which produces smooth spectra for reflectances and scales linearly for
+/// emitters.
+pub fn rgb_to_spectrum(rgb: Vec3, wavelength: f32) -> f32 {
+    let bin = (wavelength - WAVELENGTH_MIN) / (WAVELENGTH_MAX - WAVELENGTH_MIN) * SMITS_BINS as f32;
+    let i = (bin.max(0.0) as usize).min(SMITS_BINS - 1);
+    let (r, g, b) = (rgb.x, rgb.y, rgb.z);
+    if r <= g && r <= b {
+        let s = r * SMITS_WHITE[i];
+        if g <= b {
+            s + (g - r) * SMITS_CYAN[i] + (b - g) * SMITS_BLUE[i]
+        } else {
+            s + (b - r) * SMITS_CYAN[i] + (g - b) * SMITS_GREEN[i]
+        }
+    } else if g <= r && g <= b {
+        let s = g * SMITS_WHITE[i];
+        if r <= b {
+            s + (r - g) * SMITS_MAGENTA[i] + (b - r) * SMITS_BLUE[i]
+        } else {
+            s + (b - g) * SMITS_MAGENTA[i] + (r - b) * SMITS_RED[i]
+        }
+    } else {
+        let s = b * SMITS_WHITE[i];
+        if r <= g {
+            s + (r - b) * SMITS_YELLOW[i] + (g - r) * SMITS_GREEN[i]
+        } else {
+            s + (g - b) * SMITS_YELLOW[i] + (r - g) * SMITS_RED[i]
+        }
+    }
+}
+
+/// Index of refraction from the two-term Cauchy equation `n = a + b / λ²` with `λ` in µm.
+pub fn cauchy_ior(a: f32, b: f32, wavelength: f32) -> f32 {
+    let l_um = wavelength * 1e-3;
+    a + b / (l_um * l_um)
+}
+
+/// Index of refraction from the three-term Sellmeier equation with `λ` in µm.
+///
+/// `b` and `c` hold the `B1..B3` and `C1..C3` coefficients (`C` in µm²) as found in glass
+/// catalogues.
+pub fn sellmeier_ior(b: Vec3, c: Vec3, wavelength: f32) -> f32 {
+    let l_um = wavelength * 1e-3;
+    let l2 = l_um * l_um;
+    let n2 = 1.0 + b.x * l2 / (l2 - c.x) + b.y * l2 / (l2 - c.y) + b.z * l2 / (l2 - c.z);
+    n2.sqrt()
+}
+
+/// A piecewise gaussian with a different width either side of the mean.
+fn gaussian(x: f32, mu: f32, sigma_lo: f32, sigma_hi: f32) -> f32 {
+    let sigma = if x < mu { sigma_lo } else { sigma_hi };
+    let t = (x - mu) / sigma;
+    (-0.5 * t * t).exp()
+}
@@ -0,0 +1,101 @@
+//! Exposure and tone mapping operators mapping scene-referred radiance to display values.
+
+use spirv_std::{
+    glam::{vec3, Vec3},
+    num_traits::Float,
+};
+
+/// `ShaderConstants::tonemap` value that only clamps to the displayable range.
+pub const CLAMP: u32 = 0;
+/// `ShaderConstants::tonemap` value selecting `reinhard`.
+pub const REINHARD: u32 = 1;
+/// `ShaderConstants::tonemap` value selecting `aces_filmic`.
+pub const ACES_FILMIC: u32 = 2;
+/// `ShaderConstants::tonemap` value selecting `agx`.
+pub const AGX: u32 = 3;
+
+/// Scale linear radiance by `2^ev`, then map it to linear display values in `0.0..=1.0`.
+///
+/// The result still needs sRGB encoding, e.g. by writing to an `*Srgb` texture format.
+pub fn tonemap(color: Vec3, operator: u32, exposure_ev: f32) -> Vec3 {
+    let color = color * exposure_ev.exp2();
+    let mapped = match operator {
+        REINHARD => reinhard(color),
+        ACES_FILMIC => aces_filmic(color),
+        AGX => agx(color),
+        _ => color,
+    };
+    mapped.max(Vec3::ZERO).min(Vec3::ONE)
+}
+
+/// The simple per-channel Reinhard operator `c / (1 + c)`.
+pub fn reinhard(color: Vec3) -> Vec3 {
+    color / (Vec3::ONE + color)
+}
+
+/// Stephen Hill's fit of the ACES reference rendering and output device transforms.
+pub fn aces_filmic(color: Vec3) -> Vec3 {
+    let v = vec3(
+        0.59719 * color.x + 0.35458 * color.y + 0.04823 * color.z,
+        0.07600 * color.x + 0.90834 * color.y + 0.01566 * color.z,
+        0.02840 * color.x + 0.13383 * color.y + 0.83777 * color.z,
+    );
+    let a = v * (v + Vec3::splat(0.0245786)) - Vec3::splat(0.000090537);
+    let b = v * (0.983729 * v + Vec3::splat(0.4329510)) + Vec3::splat(0.238081);
+    let v = a / b;
+    vec3(
+        1.60475 * v.x - 0.53108 * v.y - 0.07367 * v.z,
+        -0.10208 * v.x + 1.10813 * v.y - 0.00605 * v.z,
+        -0.00327 * v.x - 0.07276 * v.y + 1.07602 * v.z,
+    )
+}
+
+/// Troy Sobotka's AgX with the base look, using the common polynomial fit of its contrast curve.
+pub fn agx(color: Vec3) -> Vec3 {
+    const MIN_EV: f32 = -12.47393;
+    const MAX_EV: f32 = 4.026069;
+
+    // Inset into the AgX working space.
+    let v = vec3(
+        0.842479 * color.x + 0.078434 * color.y + 0.079224 * color.z,
+        0.042328 * color.x + 0.878469 * color.y + 0.079166 * color.z,
+        0.042376 * color.x + 0.078434 * color.y + 0.879143 * color.z,
+    );
+
+    // Log2 encode and apply the sigmoid.
+    let log2 = |x: f32| (x.max(1e-10).log2().max(MIN_EV).min(MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+    let contrast = |x: f32| {
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    };
+    let v = vec3(contrast(log2(v.x)), contrast(log2(v.y)), contrast(log2(v.z)));
+
+    // Outset and undo the curve's display encoding to return linear values.
+    let v = vec3(
+        1.196879 * v.x - 0.098021 * v.y - 0.099030 * v.z,
+        -0.052897 * v.x + 1.151903 * v.y - 0.098961 * v.z,
+        -0.052972 * v.x - 0.098043 * v.y + 1.151074 * v.z,
+    );
+    let v = v.max(Vec3::ZERO);
+    vec3(v.x.powf(2.2), v.y.powf(2.2), v.z.powf(2.2))
+}
+
+/// The sRGB transfer function, encoding a linear value in `0.0..=1.0`.
+pub fn linear_to_srgb(x: f32) -> f32 {
+    if x <= 0.0031308 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// The inverse of `linear_to_srgb`.
+pub fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
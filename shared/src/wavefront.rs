@@ -0,0 +1,333 @@
+//! A wavefront formulation of `color`, splitting each bounce into separate kernels.
+//!
+//! The megakernel traces a whole path per invocation, so neighbouring invocations that hit
+//! different materials diverge. Here the paths of a wavefront instead live in a buffer of
+//! `PathState`s and move between queues: each bounce intersects every queued ray, sorts the hits
+//! into a queue per `MaterialKind`, shades each material queue in its own dispatch and finally
+//! traces the shadow rays queued while shading.
+//!
+//! Shading `Lambertian` hits also samples the environment directly through those shadow rays,
+//! combining them with the rays that escape after scattering by multiple importance sampling. This
+//! makes it a different estimator from `color`, which agrees with it in expectation, as checked by
+//! `tests/wavefront.rs`, and usually has less noise under bright environments.
+
+use crate::sampler::{Sampler, Samplers};
+use crate::{
+    spectral, BsdfSample, Camera, Environment, Hit, HitData, Material, MaterialInfo, MaterialKind,
+    PathTermination, Ray, ShaderConstants,
+};
+use bytemuck::{Pod, Zeroable};
+use spirv_std::arch::atomic_i_add;
+use spirv_std::glam::{vec2, Vec3, Vec3A};
+use spirv_std::memory::{Scope, Semantics};
+
+// The queues of rays to intersect are 0 and 1, alternating between bounces, see `rays_in`.
+
+/// The queue of hits on `Lambertian` materials.
+pub const LAMBERTIAN: u32 = 2;
+/// The queue of hits on `Metal` materials.
+pub const METAL: u32 = 3;
+/// The queue of hits on `Dielectric` materials.
+pub const DIELECTRIC: u32 = 4;
+/// The queue of hits on `Volume` materials.
+pub const VOLUME: u32 = 5;
+/// The queue of shadow rays.
+pub const SHADOW: u32 = 6;
+/// The number of queues.
+pub const QUEUE_COUNT: u32 = 7;
+
+/// The maximum number of paths in a wavefront. Larger regions are traced in several wavefronts.
+pub const CAPACITY: u32 = 1 << 18;
+
+/// The length in `u32`s of the queue buffer, which begins with a counter for each queue followed
+/// by the queues themselves.
+pub const QUEUE_BUFFER_LEN: u32 = QUEUE_COUNT + QUEUE_COUNT * CAPACITY;
+
+/// The width of the workgroups declared by the wavefront kernels, except the two that work on
+/// the queue counters.
+pub const WORKGROUP_SIZE: u32 = 64;
+
+/// The length in `u32`s of the buffer of indirect dispatch arguments, `[x, y, z]` for each queue.
+pub const DISPATCH_ARGS_LEN: u32 = QUEUE_COUNT * 3;
+
+// Rays leave surfaces from slightly above them to avoid hitting the surface they left.
+const T_MIN: f32 = 0.001;
+
+/// The push constants of the wavefront kernels.
+#[derive(Copy, Clone, Default, Pod, Zeroable)]
+#[repr(C)]
+pub struct WavefrontConstants {
+    pub scene: ShaderConstants,
+    /// The index within `ShaderConstants::compute_region` of the pixel of the first path.
+    pub first_path: u32,
+    /// The number of paths, one per pixel, in the wavefront.
+    pub path_count: u32,
+    /// The sample of each pixel being traced, in `0..rays_per_pixel`.
+    pub sample: u32,
+    /// Selects the ray queue read by this bounce, the other being written.
+    pub parity: u32,
+}
+
+impl WavefrontConstants {
+    /// The queue of rays intersected by this bounce.
+    pub fn rays_in(&self) -> u32 {
+        self.parity & 1
+    }
+
+    /// The queue of rays to intersect in the next bounce.
+    pub fn rays_out(&self) -> u32 {
+        self.rays_in() ^ 1
+    }
+}
+
+/// The state of the path traced for one pixel of a wavefront.
+///
+/// The app sizes the path buffer with `size_of::<PathState>()`, so vectors are `Vec3A`s, which
+/// unlike `Vec3` are laid out the same on the host and the GPU. See `layout`.
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub struct PathState {
+    /// The sum of the clamped radiance of the pixel's samples so far.
+    pub pixel_radiance: Vec3A,
+    /// The sum of the bounces of the pixel's samples so far.
+    pub pixel_bounces: u32,
+
+    pub sampler: Samplers,
+    /// The wavelength traced in nanometres, or zero when tracing RGB.
+    pub wavelength: f32,
+    pub origin: Vec3A,
+    pub direction: Vec3A,
+    pub throughput: Vec3A,
+    /// The radiance gathered by the sample so far.
+    pub radiance: Vec3A,
+    pub bounces: u32,
+    /// The solid angle density of the last scattered direction, or zero if it was chosen from a
+    /// delta distribution and so can't be sampled by shadow rays.
+    pub scatter_pdf: f32,
+
+    pub hit_t: f32,
+    pub hit_p: Vec3A,
+    pub hit_normal: Vec3A,
+    pub material_index: u32,
+
+    pub shadow_direction: Vec3A,
+    /// The radiance added if the shadow ray escapes.
+    pub shadow_radiance: Vec3A,
+}
+
+/// The offset in the queue buffer of the first element of `queue`.
+pub fn queue_offset(queue: u32) -> usize {
+    (QUEUE_COUNT + queue * CAPACITY) as usize
+}
+
+/// Append the index of a path to `queue`.
+pub fn push(queues: &mut [u32], queue: u32, path: u32) {
+    let slot = unsafe {
+        atomic_i_add::<u32, { Scope::Device as u32 }, { Semantics::NONE.bits() }>(&mut queues[queue as usize], 1)
+    };
+    queues[queue_offset(queue) + slot as usize] = path;
+}
+
+/// Read the index of the path at `index` in `queue`, returning `false` past the end of the queue.
+pub fn queued(queues: &[u32], queue: u32, index: u32, path: &mut u32) -> bool {
+    if index >= queues[queue as usize] {
+        return false;
+    }
+    *path = queues[queue_offset(queue) + index as usize];
+    true
+}
+
+/// Write the arguments of an indirect dispatch over `queue`, with enough workgroups for the paths
+/// it holds, to `args` at `3 * queue`. An empty queue dispatches none.
+pub fn dispatch_args(queues: &[u32], queue: u32, args: &mut [u32]) {
+    let i = 3 * queue as usize;
+    args[i] = (queues[queue as usize] + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+    args[i + 1] = 1;
+    args[i + 2] = 1;
+}
+
+/// The pixel traced by the path at `index`, counting from `WavefrontConstants::first_path`.
+pub fn path_pixel(constants: &WavefrontConstants, index: u32) -> [u32; 2] {
+    let [x, y, w, _] = constants.scene.compute_region;
+    let i = constants.first_path + index;
+    [x + i % w, y + i / w]
+}
+
+/// The power heuristic weight of a sample from the strategy with density `pdf`, where the other
+/// strategy has density `other_pdf`.
+pub fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+impl PathState {
+    /// Begin a new sample of the pixel, generating its camera ray as `main_fs` does.
+    ///
+    /// The previous sample is added to the pixel's sums, which are instead reset on the first.
+    pub fn generate(&mut self, constants: &ShaderConstants, cam: &Camera, pixel: [u32; 2], sample: u32) {
+        if sample == 0 {
+            self.pixel_radiance = Vec3A::ZERO;
+            self.pixel_bounces = 0;
+        } else {
+            self.finish_sample(constants.radiance_clamp);
+        }
+        let [w_px, h_px] = constants.view_size_pixels;
+        let first_sample = constants.frame.wrapping_mul(constants.rays_per_pixel);
+        let mut sampler = Samplers::new(constants.sampler, pixel, first_sample);
+        sampler.start_sample(first_sample.wrapping_add(sample));
+        let jitter = sampler.next_2d();
+        let uv = vec2(
+            (pixel[0] as f32 + 0.5 + jitter.x) / w_px as f32,
+            ((h_px as f32 - pixel[1] as f32 - 0.5) + jitter.y) / h_px as f32,
+        );
+        let ray = cam.ray(&mut sampler, uv);
+        self.wavelength = if constants.spectral != 0 {
+            spectral::sample_wavelength(sampler.next_1d())
+        } else {
+            0.0
+        };
+        self.sampler = sampler;
+        self.origin = ray.origin().into();
+        self.direction = ray.direction().into();
+        self.throughput = Vec3A::ONE;
+        self.radiance = Vec3A::ZERO;
+        self.bounces = 0;
+        self.scatter_pdf = 0.0;
+    }
+
+    /// Intersect the path's ray with the world, returning the queue of the kind of material hit.
+    ///
+    /// Returns `false` if the ray escaped, adding the environment's radiance and ending the path.
+    pub fn extend(&mut self, world: impl Copy + Hit, env: impl Copy + Environment, queue: &mut u32) -> bool {
+        let ray = self.ray();
+        let mut hit = HitData::default();
+        if world.hit(&ray, T_MIN, core::f32::MAX, &mut hit) {
+            self.hit_t = hit.t;
+            self.hit_p = hit.p.into();
+            self.hit_normal = hit.normal.into();
+            self.material_index = hit.material.index;
+            *queue = match hit.material.kind() {
+                MaterialKind::Lambertian => LAMBERTIAN,
+                MaterialKind::Metal => METAL,
+                MaterialKind::Dielectric => DIELECTRIC,
+                MaterialKind::Volume => VOLUME,
+            };
+            return true;
+        }
+        // Weigh against the shadow ray that could have sampled this direction.
+        let direction = self.direction.into();
+        let weight = if self.scatter_pdf > 0.0 {
+            power_heuristic(self.scatter_pdf, env.pdf(direction))
+        } else {
+            1.0
+        };
+        let radiance = self.spectrum(env.radiance(direction));
+        self.radiance += self.throughput * Vec3A::from(radiance) * weight;
+        false
+    }
+
+    /// Scatter the path off the `material` it hit, adding any light it emits, returning whether the
+    /// path continues.
+    ///
+    /// With `sample_env`, also samples a direction towards the environment, returning a shadow ray
+    /// via `shadow`. This is only worthwhile for materials that aren't delta distributions.
+    pub fn shade(
+        &mut self,
+        material: impl Copy + Material,
+        kind: MaterialKind,
+        termination: PathTermination,
+        env: impl Copy + Environment,
+        sample_env: bool,
+        shadow: &mut bool,
+    ) -> bool {
+        *shadow = false;
+        let ray = self.ray();
+        let hit = HitData {
+            t: self.hit_t,
+            p: self.hit_p.into(),
+            normal: self.hit_normal.into(),
+            material: MaterialInfo::new(kind, self.material_index),
+        };
+        let emitted = self.spectrum(material.emitted(&hit));
+        self.radiance += self.throughput * Vec3A::from(emitted);
+        if self.bounces >= termination.bounce_limit {
+            return false;
+        }
+
+        if sample_env {
+            let mut env_pdf = 0.0;
+            let wi = env.sample(self.sampler.next_2d(), &mut env_pdf);
+            let mut scatter_pdf = 0.0;
+            let f = material.eval(&ray, &hit, wi, &mut scatter_pdf);
+            let cos = hit.normal.dot(wi).abs();
+            if env_pdf > 0.0 && scatter_pdf > 0.0 {
+                let weight = power_heuristic(env_pdf, scatter_pdf);
+                let radiance = self.spectrum(f * env.radiance(wi)) * cos * weight / env_pdf;
+                self.shadow_direction = wi.into();
+                self.shadow_radiance = self.throughput * Vec3A::from(radiance);
+                *shadow = true;
+            }
+        }
+
+        let mut sample = BsdfSample::default();
+        let scattered = if self.wavelength > 0.0 {
+            material.scatter_spectral(&ray, &hit, self.wavelength, &mut self.sampler, &mut sample)
+        } else {
+            material.scatter(&ray, &hit, &mut self.sampler, &mut sample)
+        };
+        if !scattered {
+            return false;
+        }
+        self.throughput *= Vec3A::from(sample.weight(hit.normal));
+        self.origin = sample.origin.into();
+        self.direction = sample.direction.into();
+        self.scatter_pdf = if sample.delta { 0.0 } else { sample.pdf };
+        self.bounces += 1;
+
+        let p = termination.continue_probability(self.bounces, self.throughput.max_element());
+        if p < 1.0 {
+            if self.sampler.next_1d() >= p {
+                return false;
+            }
+            self.throughput /= p;
+        }
+        true
+    }
+
+    /// Trace the shadow ray queued by `shade`, adding its radiance if it escapes the world.
+    pub fn trace_shadow(&mut self, world: impl Copy + Hit) {
+        let ray = Ray::new(self.hit_p.into(), self.shadow_direction.into());
+        let mut hit = HitData::default();
+        if !world.hit(&ray, T_MIN, core::f32::MAX, &mut hit) {
+            self.radiance += self.shadow_radiance;
+        }
+    }
+
+    /// Add the finished sample to the pixel's sums, clamping it as `main_fs` does.
+    pub fn finish_sample(&mut self, radiance_clamp: f32) {
+        let radiance = if self.wavelength > 0.0 {
+            spectral::to_linear_srgb(self.radiance.x, self.wavelength)
+        } else {
+            self.radiance.into()
+        };
+        self.pixel_radiance += Vec3A::from(crate::clamp_radiance(radiance, radiance_clamp));
+        self.pixel_bounces += self.bounces;
+    }
+
+    fn ray(&self) -> Ray {
+        Ray::new(self.origin.into(), self.direction.into())
+    }
+
+    // The value of an RGB quantity at the traced wavelength, in every component, when spectral.
+    fn spectrum(&self, rgb: Vec3) -> Vec3 {
+        if self.wavelength > 0.0 {
+            Vec3::splat(spectral::rgb_to_spectrum(rgb, self.wavelength))
+        } else {
+            rgb
+        }
+    }
+}
@@ -0,0 +1,276 @@
+//! The canonical scenes of the golden image tests, their CPU reference render and the comparison
+//! of renders with the reference images in `tests/golden`.
+//!
+//! Also used by the app's GPU golden image test, which renders the same scenes with `main_cs`.
+
+#![allow(dead_code)]
+
+use glam::{vec2, vec3, Vec3};
+use image::{Rgb, RgbImage};
+use shared::debug::PixelStats;
+use shared::sky::PreethamSky;
+use shared::tonemap::{linear_to_srgb, tonemap};
+use shared::{
+    environment, sampler, trace_pixel, Dielectric, Lambertian, MaterialInfo, MaterialKind, Materials,
+    Metal, ShaderConstants, Sphere, Volume, World,
+};
+use std::path::{Path, PathBuf};
+
+/// The size of every golden image, small enough to render on the CPU in a debug build.
+pub const SIZE: [u32; 2] = [96, 64];
+
+/// Set to re-render the reference images instead of comparing with them.
+pub const UPDATE_ENV: &str = "NANNOU_RAYTRACER_UPDATE_GOLDEN";
+
+/// A scene and the constants to render it with.
+pub struct GoldenScene {
+    pub name: &'static str,
+    pub constants: ShaderConstants,
+    pub spheres: Vec<Sphere>,
+    pub lambertian: Vec<Lambertian>,
+    pub metal: Vec<Metal>,
+    pub dielectric: Vec<Dielectric>,
+    pub volume: Vec<Volume>,
+}
+
+/// The canonical scenes, each exercising a different part of the renderer.
+pub fn scenes() -> Vec<GoldenScene> {
+    let constants = ShaderConstants {
+        view_size_pixels: SIZE,
+        rays_per_pixel: 16,
+        ray_bounce_limit: 8,
+        roulette_depth: 3,
+        sampler: sampler::SOBOL,
+        environment: environment::SKY,
+        env_intensity: 1.0,
+        sun_elevation: 0.6,
+        sun_azimuth: 1.0,
+        turbidity: 2.5,
+        tonemap: shared::tonemap::ACES_FILMIC,
+        sphere_count: 8,
+        camera_from: [0.25, 1.125, 0.0],
+        camera_to: [0.0, 1.0, -3.0],
+        vfov: core::f32::consts::PI * 0.5,
+        focus_dist: (vec3(0.25, 1.125, 0.0) - vec3(0.0, 1.0, -3.0)).length() - 0.25,
+        ..Default::default()
+    };
+    vec![
+        GoldenScene {
+            name: "spheres",
+            ..spheres(constants, 0.0)
+        },
+        GoldenScene {
+            name: "spectral",
+            constants: ShaderConstants {
+                spectral: 1,
+                ..constants
+            },
+            ..spheres(constants, 0.02)
+        },
+        GoldenScene {
+            name: "depth_of_field",
+            constants: ShaderConstants {
+                sampler: sampler::PCG,
+                aperture: 0.1,
+                ..constants
+            },
+            ..spheres(constants, 0.0)
+        },
+        GoldenScene {
+            name: "lit_box",
+            ..lit_box(constants)
+        },
+    ]
+}
+
+// A glass, two metal and a small diffuse sphere in a box of huge diffuse spheres, as in the default
+// scene.
+fn spheres(constants: ShaderConstants, cauchy_b: f32) -> GoldenScene {
+    let sphere = |center: [f32; 3], radius, kind, index| Sphere {
+        center,
+        radius,
+        material: MaterialInfo::new(kind, index),
+    };
+    GoldenScene {
+        name: "",
+        constants,
+        spheres: vec![
+            sphere([0.0, 1.0, -3.0], 0.5, MaterialKind::Metal, 0),
+            sphere([-1.0, 0.0, -2.0], 0.5, MaterialKind::Dielectric, 0),
+            sphere([1.0, 0.0, -1.5], 0.5, MaterialKind::Metal, 1),
+            sphere([0.5, 1.5, -2.0], 0.1, MaterialKind::Lambertian, 2),
+            sphere([0.0, -1000.5, -1.0], 1000.0, MaterialKind::Lambertian, 2),
+            sphere([-22.0, 0.0, -1.0], 20.0, MaterialKind::Lambertian, 0),
+            sphere([22.0, 0.0, -1.0], 20.0, MaterialKind::Lambertian, 1),
+            sphere([0.0, 0.0, -24.0], 20.0, MaterialKind::Lambertian, 2),
+        ],
+        lambertian: vec![
+            Lambertian::new(vec3(1.0, 0.1, 0.1)),
+            Lambertian::new(vec3(0.1, 1.0, 0.1)),
+            Lambertian::new(vec3(0.9, 0.9, 0.9)),
+        ],
+        metal: vec![
+            Metal::new(vec3(0.8, 0.6, 0.2), 0.0),
+            Metal::new(vec3(0.2, 0.6, 0.8), 0.05),
+        ],
+        dielectric: vec![Dielectric::with_dispersion(1.5, cauchy_b)],
+        volume: vec![],
+    }
+}
+
+// A closed box lit only by an emissive sphere in its ceiling, holding a sphere of fog and a diffuse
+// sphere, as in the Cornell box and volume scenes.
+fn lit_box(constants: ShaderConstants) -> GoldenScene {
+    let sphere = |center: [f32; 3], radius, kind, index| Sphere {
+        center,
+        radius,
+        material: MaterialInfo::new(kind, index),
+    };
+    GoldenScene {
+        name: "",
+        constants: ShaderConstants {
+            rays_per_pixel: 32,
+            sphere_count: 9,
+            camera_from: [0.0, 1.0, 0.9],
+            camera_to: [0.0, 1.0, -2.0],
+            vfov: 55f32.to_radians(),
+            focus_dist: 2.9,
+            ..constants
+        },
+        spheres: vec![
+            sphere([0.0, -1000.0, 0.0], 1000.0, MaterialKind::Lambertian, 0),
+            sphere([0.0, 1002.0, 0.0], 1000.0, MaterialKind::Lambertian, 0),
+            sphere([0.0, 1.0, -1002.0], 1000.0, MaterialKind::Lambertian, 0),
+            sphere([0.0, 1.0, 1001.0], 1000.0, MaterialKind::Lambertian, 0),
+            sphere([-1001.0, 1.0, 0.0], 1000.0, MaterialKind::Lambertian, 1),
+            sphere([1001.0, 1.0, 0.0], 1000.0, MaterialKind::Lambertian, 2),
+            sphere([0.0, 2.35, -1.0], 0.6, MaterialKind::Lambertian, 3),
+            sphere([-0.4, 0.45, -1.2], 0.45, MaterialKind::Volume, 0),
+            sphere([0.5, 0.3, -0.7], 0.3, MaterialKind::Lambertian, 0),
+        ],
+        lambertian: vec![
+            Lambertian::new(vec3(0.73, 0.73, 0.73)),
+            Lambertian::new(vec3(0.65, 0.05, 0.05)),
+            Lambertian::new(vec3(0.12, 0.45, 0.15)),
+            Lambertian::with_emission(Vec3::ZERO, Vec3::splat(5.0)),
+        ],
+        metal: vec![],
+        dielectric: vec![],
+        volume: vec![Volume::new(vec3(0.9, 0.9, 0.9), 3.0)],
+    }
+}
+
+impl GoldenScene {
+    /// Render the scene's radiance on the CPU with the same sampling loop as `main_cs`.
+    pub fn render_cpu(&self) -> Vec<Vec3> {
+        let c = &self.constants;
+        let [w, h] = c.view_size_pixels;
+        let cam = c.camera();
+        let world = World {
+            spheres: &self.spheres,
+            count: c.sphere_count,
+        };
+        let materials = Materials {
+            lambertian: &self.lambertian,
+            metal: &self.metal,
+            dielectric: &self.dielectric,
+            volume: &self.volume,
+        };
+        let env = PreethamSky::new(c.sun_elevation, c.sun_azimuth, c.turbidity, c.env_intensity);
+        let mut pixels = Vec::with_capacity((w * h) as usize);
+        for y in 0..h {
+            for x in 0..w {
+                let frag_coord = vec2(x as f32 + 0.5, y as f32 + 0.5);
+                let mut stats = PixelStats::default();
+                let radiance =
+                    trace_pixel(c, &cam, frag_coord, c.rays_per_pixel, world, materials, env, &mut stats);
+                pixels.push(radiance);
+            }
+        }
+        pixels
+    }
+
+    /// Tone map and sRGB encode a render of the scene as the post-process pass does.
+    pub fn to_image(&self, radiance: &[Vec3]) -> RgbImage {
+        let [w, h] = self.constants.view_size_pixels;
+        RgbImage::from_fn(w, h, |x, y| {
+            let c = tonemap(radiance[(y * w + x) as usize], self.constants.tonemap, self.constants.exposure);
+            let encode = |v: f32| (linear_to_srgb(v) * 255.0).round() as u8;
+            Rgb([encode(c.x), encode(c.y), encode(c.z)])
+        })
+    }
+
+    /// The path of the scene's reference image.
+    pub fn reference_path(&self) -> PathBuf {
+        reference_dir().join(format!("{}.png", self.name))
+    }
+}
+
+/// The directory of the reference images, relative to either crate's manifest.
+pub fn reference_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../shared/tests/golden")
+}
+
+/// The root-mean-square difference of two images in `0.0..=1.0`, or `None` if their sizes differ.
+pub fn rmse(a: &RgbImage, b: &RgbImage) -> Option<f64> {
+    if a.dimensions() != b.dimensions() {
+        return None;
+    }
+    let sum: f64 = a
+        .as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(&a, &b)| {
+            let d = (a as f64 - b as f64) / 255.0;
+            d * d
+        })
+        .sum();
+    Some((sum / a.as_raw().len() as f64).sqrt())
+}
+
+/// The absolute difference of two images of the same size, scaled up to make small errors visible.
+pub fn diff_image(a: &RgbImage, b: &RgbImage) -> RgbImage {
+    RgbImage::from_fn(a.width(), a.height(), |x, y| {
+        let (pa, pb) = (a.get_pixel(x, y), b.get_pixel(x, y));
+        let d = |i: usize| ((pa[i] as i32 - pb[i] as i32).abs() * 4).min(255) as u8;
+        Rgb([d(0), d(1), d(2)])
+    })
+}
+
+/// Compare `actual` with `expected`, returning an error describing the failure if their RMSE
+/// exceeds `tolerance`.
+///
+/// On failure `actual` and the difference are saved to `out_dir` as `<name>.<label>.png` and
+/// `<name>.<label>.diff.png`.
+pub fn compare(
+    name: &str,
+    label: &str,
+    actual: &RgbImage,
+    expected: &RgbImage,
+    tolerance: f64,
+    out_dir: &Path,
+) -> Result<(), String> {
+    let error = match rmse(actual, expected) {
+        Some(error) if error <= tolerance => return Ok(()),
+        Some(error) => format!("{}: {} RMSE {:.5} exceeds {:.5}", name, label, error, tolerance),
+        None => format!(
+            "{}: {} is {:?} but expected {:?}",
+            name,
+            label,
+            actual.dimensions(),
+            expected.dimensions()
+        ),
+    };
+    std::fs::create_dir_all(out_dir).map_err(|err| format!("{}: {}", error, err))?;
+    let actual_path = out_dir.join(format!("{}.{}.png", name, label));
+    actual.save(&actual_path).map_err(|err| format!("{}: {}", error, err))?;
+    let mut message = format!("{}, saved {}", error, actual_path.display());
+    if actual.dimensions() == expected.dimensions() {
+        let diff_path = out_dir.join(format!("{}.{}.diff.png", name, label));
+        diff_image(actual, expected)
+            .save(&diff_path)
+            .map_err(|err| format!("{}: {}", error, err))?;
+        message = format!("{} and {}", message, diff_path.display());
+    }
+    Err(message)
+}
@@ -0,0 +1,40 @@
+//! Golden image tests rendering the canonical scenes on the CPU and comparing them with the
+//! checked-in reference images in `tests/golden`.
+//!
+//! After an intended change to the output, re-render the references with
+//! `NANNOU_RAYTRACER_UPDATE_GOLDEN=1 cargo test -p nannou-raytracer-shared --test golden` and
+//! check the new images before committing them.
+
+mod common;
+
+use std::path::Path;
+
+// The renders are deterministic, so this only allows for floating point differences between
+// platforms and compilers.
+const TOLERANCE: f64 = 0.01;
+
+#[test]
+fn cpu_matches_references() {
+    let update = std::env::var_os(common::UPDATE_ENV).is_some();
+    let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    let mut failures = vec![];
+    for scene in common::scenes() {
+        let actual = scene.to_image(&scene.render_cpu());
+        let path = scene.reference_path();
+        if update {
+            actual.save(&path).unwrap();
+            continue;
+        }
+        let expected = match image::open(&path) {
+            Ok(expected) => expected.to_rgb8(),
+            Err(err) => {
+                failures.push(format!("{}: failed to open {}: {}", scene.name, path.display(), err));
+                continue;
+            }
+        };
+        if let Err(failure) = common::compare(scene.name, "cpu", &actual, &expected, TOLERANCE, &out_dir) {
+            failures.push(failure);
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,148 @@
+//! Checks that the types written to the GPU by the app have the std430 layout the shader expects.
+//!
+//! Sizes and alignments are also asserted at compile time in `shared::layout`, for the host and
+//! SPIR-V targets both. Field offsets can only be checked here, on the host.
+
+use shared::denoise::DenoiseConstants;
+use shared::wavefront::WavefrontConstants;
+use shared::{Dielectric, Lambertian, MaterialInfo, MaterialKind, Metal, ShaderConstants, Sphere, Volume};
+use std::mem::{align_of, size_of};
+
+// The offset in bytes of a field from the start of its struct.
+macro_rules! offset_of {
+    ($ty:ty, $field:ident) => {{
+        let value = std::mem::MaybeUninit::<$ty>::uninit();
+        let base = value.as_ptr();
+        let field = unsafe { std::ptr::addr_of!((*base).$field) };
+        field as usize - base as usize
+    }};
+}
+
+// Assert the size and alignment of a type, and the offset of each of its fields in order.
+macro_rules! assert_layout {
+    ($ty:ty, size: $size:expr, align: $align:expr, { $($field:ident: $offset:expr),* $(,)? }) => {
+        assert_eq!(size_of::<$ty>(), $size, "size of {}", stringify!($ty));
+        assert_eq!(align_of::<$ty>(), $align, "alignment of {}", stringify!($ty));
+        $(
+            assert_eq!(
+                offset_of!($ty, $field),
+                $offset,
+                "offset of {}::{}",
+                stringify!($ty),
+                stringify!($field),
+            );
+        )*
+    };
+}
+
+#[test]
+fn shader_constants() {
+    assert_layout!(ShaderConstants, size: 164, align: 4, {
+        view_size_pixels: 0,
+        mouse_pixels: 8,
+        time: 16,
+        frame: 20,
+        compute_region: 24,
+        rays_per_pixel: 40,
+        ray_bounce_limit: 44,
+        roulette_depth: 48,
+        radiance_clamp: 52,
+        sampler: 56,
+        spectral: 60,
+        accumulate: 64,
+        accumulated_frames: 68,
+        adaptive_threshold: 72,
+        environment: 76,
+        env_rotation: 80,
+        env_intensity: 84,
+        env_map_size: 88,
+        sun_elevation: 96,
+        sun_azimuth: 100,
+        turbidity: 104,
+        exposure: 108,
+        tonemap: 112,
+        aov: 116,
+        debug_mode: 120,
+        sphere_count: 124,
+        camera_from: 128,
+        camera_to: 140,
+        vfov: 152,
+        aperture: 156,
+        focus_dist: 160,
+    });
+}
+
+#[test]
+fn wavefront_constants() {
+    assert_layout!(WavefrontConstants, size: 180, align: 4, {
+        scene: 0,
+        first_path: 164,
+        path_count: 168,
+        sample: 172,
+        parity: 176,
+    });
+}
+
+#[test]
+fn denoise_constants() {
+    assert_layout!(DenoiseConstants, size: 96, align: 4, {
+        view_size_pixels: 0,
+        camera_from: 8,
+        camera_to: 20,
+        prev_camera_from: 32,
+        prev_camera_to: 44,
+        vfov: 56,
+        prev_vfov: 60,
+        history_valid: 64,
+        alpha: 68,
+        moments_alpha: 72,
+        step_size: 76,
+        remodulate: 80,
+        phi_color: 84,
+        phi_normal: 88,
+        phi_depth: 92,
+    });
+}
+
+#[test]
+fn scene_types() {
+    assert_layout!(Sphere, size: 24, align: 4, { center: 0, radius: 12, material: 16 });
+    assert_layout!(MaterialInfo, size: 8, align: 4, { kind: 0, index: 4 });
+    assert_layout!(Lambertian, size: 24, align: 4, { albedo: 0, emission: 12 });
+    assert_layout!(Metal, size: 16, align: 4, { albedo: 0, fuzz: 12 });
+    assert_layout!(Dielectric, size: 16, align: 4, { ref_idx: 0, cauchy_b: 12 });
+    assert_layout!(Volume, size: 16, align: 4, { albedo: 0, density: 12 });
+}
+
+#[test]
+fn sphere_bytes() {
+    let sphere = Sphere {
+        center: [1.0, 2.0, 3.0],
+        radius: 4.0,
+        material: MaterialInfo::new(MaterialKind::Dielectric, 5),
+    };
+    let words: &[u32] = bytemuck::cast_slice(bytemuck::bytes_of(&sphere));
+    let expected = [
+        1.0f32.to_bits(),
+        2.0f32.to_bits(),
+        3.0f32.to_bits(),
+        4.0f32.to_bits(),
+        2,
+        5,
+    ];
+    assert_eq!(words, expected);
+}
+
+#[test]
+fn material_kind_round_trip() {
+    for &kind in &[
+        MaterialKind::Lambertian,
+        MaterialKind::Metal,
+        MaterialKind::Dielectric,
+        MaterialKind::Volume,
+    ] {
+        assert_eq!(MaterialInfo::new(kind, 0).kind() as u32, kind as u32);
+    }
+    // Unknown kinds read as Lambertian rather than selecting no material.
+    assert_eq!(MaterialInfo { kind: 7, index: 0 }.kind() as u32, MaterialKind::Lambertian as u32);
+}
@@ -0,0 +1,310 @@
+//! Tests of the geometric and optical primitives in `shared`, and white furnace tests checking that
+//! each `Material` conserves energy.
+
+use glam::{vec2, vec3, Vec2, Vec3};
+use shared::environment::uniform_sphere;
+use shared::sampler::{self, Sampler, Samplers};
+use shared::{
+    color, reflect, refract, schlick, Camera, Dielectric, Environment, Hit, HitData, Lambertian,
+    MaterialInfo, MaterialKind, Materials, Metal, PathStats, PathTermination, Ray, Sphere, Volume,
+    World,
+};
+use std::f32::consts::{FRAC_PI_2, PI};
+
+const EPSILON: f32 = 1e-5;
+
+fn assert_close(a: f32, b: f32, epsilon: f32) {
+    assert!((a - b).abs() <= epsilon, "{} differs from {} by more than {}", a, b, epsilon);
+}
+
+fn assert_close_vec(a: Vec3, b: Vec3, epsilon: f32) {
+    assert!(a.abs_diff_eq(b, epsilon), "{} differs from {} by more than {}", a, b, epsilon);
+}
+
+// Random unit vectors, the same on every run.
+fn directions(n: u32) -> Vec<Vec3> {
+    let mut sampler = Samplers::new(sampler::PCG, [0, 0], 0);
+    (0..n)
+        .map(|i| {
+            sampler.start_sample(i);
+            uniform_sphere(sampler.next_2d())
+        })
+        .collect()
+}
+
+// The unit direction at `theta` from `-n` towards the x axis, for `n` along y.
+fn incident(theta: f32) -> Vec3 {
+    vec3(theta.sin(), -theta.cos(), 0.0)
+}
+
+#[test]
+fn reflect_mirrors_about_the_normal() {
+    let n = vec3(0.0, 1.0, 0.0);
+    assert_close_vec(reflect(vec3(1.0, -1.0, 0.0), n), vec3(1.0, 1.0, 0.0), EPSILON);
+    for v in directions(256) {
+        let r = reflect(v, n);
+        assert_close(r.length(), 1.0, EPSILON);
+        assert_close(r.dot(n), -v.dot(n), EPSILON);
+        assert_close_vec(r - r.dot(n) * n, v - v.dot(n) * n, EPSILON);
+        assert_close_vec(reflect(r, n), v, EPSILON);
+    }
+}
+
+#[test]
+fn refract_obeys_snells_law() {
+    let n = vec3(0.0, 1.0, 0.0);
+    for &(n1, n2) in &[(1.0, 1.5), (1.5, 1.0), (1.0, 2.4), (1.33, 1.5)] {
+        for v in directions(256) {
+            // Only rays travelling against the normal, as `Dielectric` flips it to ensure.
+            let v = if v.dot(n) > 0.0 { -v } else { v };
+            let sin_i = v.cross(n).length();
+            let mut r = Vec3::ZERO;
+            if !refract(v, n, n1 / n2, &mut r) {
+                // Total internal reflection only happens beyond the critical angle.
+                assert!(n1 * sin_i >= n2 * (1.0 - 1e-4), "refraction failed at sin {} for {}/{}", sin_i, n1, n2);
+                continue;
+            }
+            let sin_t = r.cross(n).length();
+            assert_close(r.length(), 1.0, 1e-4);
+            assert_close(n1 * sin_i, n2 * sin_t, 1e-4);
+            assert!(r.dot(n) < 0.0, "refracted ray turned back");
+            // The refracted ray stays in the plane of incidence.
+            assert_close(r.dot(v.cross(n)), 0.0, 1e-4);
+        }
+    }
+}
+
+#[test]
+fn refract_reflects_totally_beyond_the_critical_angle() {
+    let n = vec3(0.0, 1.0, 0.0);
+    let critical = (1.0f32 / 1.5).asin();
+    let mut r = Vec3::ZERO;
+    assert!(refract(incident(critical - 0.01), n, 1.5, &mut r));
+    assert!(!refract(incident(critical + 0.01), n, 1.5, &mut r));
+    assert!(!refract(incident(FRAC_PI_2 - 0.01), n, 1.5, &mut r));
+    // Leaving glass at normal incidence doesn't bend the ray.
+    assert!(refract(incident(0.0), n, 1.5, &mut r));
+    assert_close_vec(r, incident(0.0), EPSILON);
+}
+
+#[test]
+fn schlick_approximates_fresnel_reflectance() {
+    // At normal incidence, `((n1 - n2) / (n1 + n2))^2`.
+    assert_close(schlick(1.0, 1.5), 0.04, EPSILON);
+    assert_close(schlick(1.0, 1.0), 0.0, EPSILON);
+    // Grazing rays are reflected entirely.
+    assert_close(schlick(0.0, 1.5), 1.0, EPSILON);
+    let mut last = 1.0;
+    for i in 0..=100 {
+        let reflectance = schlick(i as f32 / 100.0, 1.5);
+        assert!(reflectance <= last && reflectance >= 0.04 - EPSILON);
+        last = reflectance;
+    }
+}
+
+#[test]
+fn camera_rays_pass_through_the_frame_corners() {
+    let from = vec3(1.0, 2.0, 3.0);
+    let vfov = FRAC_PI_2;
+    let aspect = 2.0;
+    for &focus_dist in &[1.0, 4.0] {
+        let cam = Camera::new(from, from + vec3(0.0, 0.0, -1.0), Vec3::Y, vfov, aspect, 0.0, focus_dist);
+        // With a vertical field of view of 90°, the corners are at one unit up or down and two
+        // across per unit forward.
+        let corners = [
+            (vec2(0.0, 0.0), vec3(-2.0, -1.0, -1.0)),
+            (vec2(1.0, 0.0), vec3(2.0, -1.0, -1.0)),
+            (vec2(0.0, 1.0), vec3(-2.0, 1.0, -1.0)),
+            (vec2(1.0, 1.0), vec3(2.0, 1.0, -1.0)),
+            (vec2(0.5, 0.5), vec3(0.0, 0.0, -1.0)),
+        ];
+        for &(uv, direction) in &corners {
+            let ray = cam.pinhole_ray(uv);
+            assert_close_vec(ray.origin(), from, EPSILON);
+            assert_close_vec(ray.direction(), direction * focus_dist, 1e-4);
+        }
+    }
+}
+
+#[test]
+fn camera_lens_rays_converge_on_the_focal_plane() {
+    let from = vec3(0.0, 1.0, 0.0);
+    let to = vec3(0.0, 1.0, -3.0);
+    let aperture = 0.5;
+    let cam = Camera::new(from, to, Vec3::Y, 1.0, 1.5, aperture, 3.0);
+    let mut sampler = Samplers::new(sampler::PCG, [0, 0], 0);
+    for i in 0..256 {
+        sampler.start_sample(i);
+        let uv = sampler.next_2d();
+        let pinhole = cam.pinhole_ray(uv);
+        let ray = cam.ray(&mut sampler, uv);
+        assert_close_vec(ray.point_at_parameter(1.0), pinhole.point_at_parameter(1.0), 1e-4);
+        let offset = ray.origin() - from;
+        assert!(offset.length() <= aperture * 0.5 + EPSILON);
+        assert_close(offset.z, 0.0, EPSILON);
+    }
+}
+
+fn sphere(center: Vec3, radius: f32) -> Sphere {
+    Sphere {
+        center: center.into(),
+        radius,
+        material: MaterialInfo::new(MaterialKind::Metal, 3),
+    }
+}
+
+#[test]
+fn sphere_hit_from_outside() {
+    let s = sphere(vec3(0.0, 0.0, -5.0), 1.0);
+    let mut hit = HitData::default();
+    // The direction needn't be normalised, scaling `t` instead.
+    for &scale in &[1.0, 2.0] {
+        let ray = Ray::new(Vec3::ZERO, vec3(0.0, 0.0, -scale));
+        assert!(s.hit(&ray, 0.001, f32::MAX, &mut hit));
+        assert_close(hit.t, 4.0 / scale, EPSILON);
+        assert_close_vec(hit.p, vec3(0.0, 0.0, -4.0), EPSILON);
+        assert_close_vec(hit.normal, vec3(0.0, 0.0, 1.0), EPSILON);
+        assert_eq!(hit.material.kind, MaterialKind::Metal as u32);
+        assert_eq!(hit.material.index, 3);
+    }
+
+    // Misses beside, behind and beyond `t_max`.
+    let ray = Ray::new(Vec3::ZERO, vec3(0.0, 0.0, -1.0));
+    assert!(!s.hit(&Ray::new(Vec3::ZERO, vec3(0.0, 1.0, -1.0)), 0.001, f32::MAX, &mut hit));
+    assert!(!s.hit(&Ray::new(Vec3::ZERO, vec3(0.0, 0.0, 1.0)), 0.001, f32::MAX, &mut hit));
+    assert!(!s.hit(&ray, 0.001, 3.5, &mut hit));
+}
+
+#[test]
+fn sphere_hit_from_inside() {
+    let center = vec3(1.0, -2.0, 3.0);
+    let s = sphere(center, 2.0);
+    let mut hit = HitData::default();
+    for d in directions(256) {
+        // The near intersection is behind the ray, so the far one is hit with an outward normal.
+        let origin = center + d * 0.5;
+        let ray = Ray::new(origin, d);
+        assert!(s.hit(&ray, 0.001, f32::MAX, &mut hit));
+        assert!(hit.t > 0.0);
+        assert_close((hit.p - center).length(), 2.0, 1e-4);
+        assert_close_vec(hit.normal, (hit.p - center) / 2.0, 1e-4);
+        assert!(hit.normal.dot(d) > 0.0);
+    }
+}
+
+// An environment of uniform unit radiance, for white furnace tests.
+#[derive(Copy, Clone)]
+struct Furnace;
+
+impl Environment for Furnace {
+    fn radiance(self, _: Vec3) -> Vec3 {
+        Vec3::ONE
+    }
+
+    fn sample(self, u: Vec2, pdf: &mut f32) -> Vec3 {
+        *pdf = 1.0 / (4.0 * PI);
+        uniform_sphere(u)
+    }
+
+    fn pdf(self, _: Vec3) -> f32 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+// The mean radiance of rays towards a white sphere of the given material lit by the furnace.
+fn furnace_radiance(materials: Materials, kind: MaterialKind) -> Vec3 {
+    let spheres = [Sphere {
+        center: [0.0, 0.0, -3.0],
+        radius: 1.0,
+        material: MaterialInfo::new(kind, 0),
+    }];
+    let world = World {
+        spheres: &spheres,
+        count: 1,
+    };
+    let termination = PathTermination {
+        bounce_limit: 64,
+        roulette_depth: 0,
+    };
+    let mut sampler = Samplers::new(sampler::SOBOL, [0, 0], 0);
+    let n = 4096;
+    let mut sum = Vec3::ZERO;
+    for i in 0..n {
+        sampler.start_sample(i);
+        // Aim within the sphere's silhouette so that every ray hits it.
+        let u = sampler.next_2d();
+        let target = vec3(0.0, 0.0, -3.0) + 0.9 * vec3(u.x * 2.0 - 1.0, u.y * 2.0 - 1.0, 0.0) / 2f32.sqrt();
+        let ray = Ray::new(Vec3::ZERO, target);
+        let mut stats = PathStats::default();
+        sum += color(termination, &mut sampler, ray, world, materials, Furnace, &mut stats);
+        assert!(stats.bounces > 0, "the ray missed the sphere");
+    }
+    sum / n as f32
+}
+
+fn white_materials<'a>(
+    lambertian: &'a [Lambertian],
+    metal: &'a [Metal],
+    dielectric: &'a [Dielectric],
+    volume: &'a [Volume],
+) -> Materials<'a> {
+    Materials {
+        lambertian,
+        metal,
+        dielectric,
+        volume,
+    }
+}
+
+const KINDS: [MaterialKind; 4] = [
+    MaterialKind::Lambertian,
+    MaterialKind::Metal,
+    MaterialKind::Dielectric,
+    MaterialKind::Volume,
+];
+
+#[test]
+fn white_furnace() {
+    let lambertian = [Lambertian::new(Vec3::ONE)];
+    let metal = [Metal::new(Vec3::ONE, 0.0)];
+    let dielectric = [Dielectric::new(1.5)];
+    let volume = [Volume::new(Vec3::ONE, 2.0)];
+    let materials = white_materials(&lambertian, &metal, &dielectric, &volume);
+    // A white sphere that doesn't absorb light disappears into the furnace.
+    for &kind in &KINDS {
+        assert_close_vec(furnace_radiance(materials, kind), Vec3::ONE, 1e-3);
+    }
+}
+
+#[test]
+fn furnace_never_creates_energy() {
+    let lambertian = [Lambertian::new(vec3(0.5, 0.8, 1.0))];
+    // Fuzzed reflections below the surface are absorbed, losing energy.
+    let metal = [Metal::new(Vec3::ONE, 0.5)];
+    let dielectric = [Dielectric::with_dispersion(1.7, 0.02)];
+    // Paths may scatter many times within the medium, losing energy each time except in the
+    // channel with a white albedo.
+    let volume = [Volume::new(vec3(0.5, 0.8, 1.0), 2.0)];
+    let materials = white_materials(&lambertian, &metal, &dielectric, &volume);
+    let lambertian = furnace_radiance(materials, MaterialKind::Lambertian);
+    assert_close_vec(lambertian, vec3(0.5, 0.8, 1.0), 1e-3);
+    let metal = furnace_radiance(materials, MaterialKind::Metal);
+    assert!(metal.max_element() <= 1.0 + 1e-3 && metal.min_element() > 0.5);
+    let dielectric = furnace_radiance(materials, MaterialKind::Dielectric);
+    assert_close_vec(dielectric, Vec3::ONE, 1e-3);
+    let volume = furnace_radiance(materials, MaterialKind::Volume);
+    assert!(volume.x < volume.y && volume.y < 1.0, "the medium never scattered: {}", volume);
+    assert!((volume.z - 1.0).abs() < 1e-3);
+}
+
+#[test]
+fn emission_adds_to_furnace() {
+    let emission = vec3(2.0, 3.0, 4.0);
+    let lambertian = [Lambertian::with_emission(Vec3::ZERO, emission)];
+    let materials = white_materials(&lambertian, &[], &[], &[]);
+    // The black emitter hides the furnace behind it, so only its own light is seen.
+    assert_close_vec(furnace_radiance(materials, MaterialKind::Lambertian), emission, 1e-3);
+    let lambertian = [Lambertian::with_emission(Vec3::ONE, emission)];
+    let materials = white_materials(&lambertian, &[], &[], &[]);
+    assert_close_vec(furnace_radiance(materials, MaterialKind::Lambertian), Vec3::ONE + emission, 1e-3);
+}
@@ -0,0 +1,235 @@
+//! Statistical tests measuring the quality of the samplers in `shared::sampler`.
+
+use shared::sampler::{self, Sampler, Samplers};
+
+const KINDS: [(u32, &str); 4] = [
+    (sampler::PCG, "PCG"),
+    (sampler::SOBOL, "Sobol"),
+    (sampler::HALTON, "Halton"),
+    (sampler::BLUE_NOISE, "blue noise"),
+];
+
+// The number of dimensions checked per sample, enough for the camera and a few bounces.
+const DIMENSIONS: usize = 12;
+
+// Take `n` samples of `DIMENSIONS` dimensions at a pixel, as `main_fs` does.
+fn samples(kind: u32, pixel: [u32; 2], first_index: u32, n: u32) -> Vec<[f32; DIMENSIONS]> {
+    let mut sampler = Samplers::new(kind, pixel, first_index);
+    (0..n)
+        .map(|i| {
+            sampler.start_sample(first_index.wrapping_add(i));
+            let mut sample = [0.0; DIMENSIONS];
+            for x in sample.iter_mut() {
+                *x = sampler.next_1d();
+            }
+            sample
+        })
+        .collect()
+}
+
+fn dimension(samples: &[[f32; DIMENSIONS]], d: usize) -> Vec<f32> {
+    samples.iter().map(|s| s[d]).collect()
+}
+
+fn mean(xs: &[f32]) -> f64 {
+    xs.iter().map(|&x| x as f64).sum::<f64>() / xs.len() as f64
+}
+
+fn variance(xs: &[f32]) -> f64 {
+    let m = mean(xs);
+    xs.iter().map(|&x| (x as f64 - m).powi(2)).sum::<f64>() / (xs.len() - 1) as f64
+}
+
+fn correlation(xs: &[f32], ys: &[f32]) -> f64 {
+    let (mx, my) = (mean(xs), mean(ys));
+    let cov: f64 = xs.iter().zip(ys).map(|(&x, &y)| (x as f64 - mx) * (y as f64 - my)).sum();
+    cov / (xs.len() - 1) as f64 / (variance(xs) * variance(ys)).sqrt()
+}
+
+// The largest correlation accepted between `n` independent values, allowing for the hundreds of
+// comparisons made: the sample correlation has a standard deviation of about `1 / sqrt(n)`.
+fn max_correlation(n: usize) -> f64 {
+    4.5 / (n as f64).sqrt()
+}
+
+// Pearson's chi-squared statistic of the values against a uniform distribution over `bins`.
+fn chi_squared(xs: &[f32], bins: usize) -> f64 {
+    let mut counts = vec![0usize; bins];
+    for &x in xs {
+        counts[((x * bins as f32) as usize).min(bins - 1)] += 1;
+    }
+    let expected = xs.len() as f64 / bins as f64;
+    counts.iter().map(|&c| (c as f64 - expected).powi(2) / expected).sum()
+}
+
+// The L2 star discrepancy of 2D points by Warnock's formula.
+fn l2_star_discrepancy(points: &[(f32, f32)]) -> f64 {
+    let n = points.len() as f64;
+    let single: f64 = points
+        .iter()
+        .map(|&(x, y)| (1.0 - (x as f64).powi(2)) * (1.0 - (y as f64).powi(2)))
+        .sum();
+    let mut pairs = 0.0;
+    for &(xi, yi) in points {
+        for &(xj, yj) in points {
+            pairs += (1.0 - xi.max(xj) as f64) * (1.0 - yi.max(yj) as f64);
+        }
+    }
+    (1.0 / 9.0 - single / (2.0 * n) + pairs / (n * n)).sqrt()
+}
+
+// The variance of the mean error of single sample estimates over 4x4 blocks of pixels, which
+// drops where the error between neighbouring pixels cancels out.
+fn block_error_variance(kind: u32, d: usize) -> f64 {
+    let block_means: Vec<f64> = (0..16 * 16)
+        .map(|b| {
+            let [bx, by] = [b % 16 * 4, b / 16 * 4];
+            let errors = (0..16).map(|p| samples(kind, [bx + p % 4, by + p / 4], 0, 1)[0][d] as f64 - 0.5);
+            errors.sum::<f64>() / 16.0
+        })
+        .collect();
+    block_means.iter().map(|m| m * m).sum::<f64>() / block_means.len() as f64
+}
+
+// The RMS error over pixels of integrating a smooth 2D function with `n` samples per pixel.
+fn rms_integration_error(kind: u32, n: u32) -> f64 {
+    // The integral of `x * y` over the unit square.
+    let expected = 0.25;
+    let pixels = 64;
+    let sum: f64 = (0..pixels)
+        .map(|p| {
+            let samples = samples(kind, [p, p * 7 + 3], 0, n);
+            let estimate = samples.iter().map(|s| (s[0] * s[1]) as f64).sum::<f64>() / n as f64;
+            (estimate - expected).powi(2)
+        })
+        .sum();
+    (sum / pixels as f64).sqrt()
+}
+
+#[test]
+fn samples_in_unit_interval() {
+    for &(kind, name) in &KINDS {
+        for (p, first_index) in [([0, 0], 0), ([1919, 1079], 0), ([17, 5], u32::MAX - 64)] {
+            for sample in samples(kind, p, first_index, 128) {
+                for x in sample.iter() {
+                    assert!((0.0..1.0).contains(x), "{} produced {}", name, x);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn samples_deterministic() {
+    for &(kind, name) in &KINDS {
+        let a = samples(kind, [12, 34], 256, 16);
+        let b = samples(kind, [12, 34], 256, 16);
+        assert_eq!(a, b, "{} isn't reproducible", name);
+    }
+}
+
+#[test]
+fn dimensions_uniform() {
+    // The 0.999 quantile of the chi-squared distribution with 63 degrees of freedom.
+    let critical = 103.4;
+    for &(kind, name) in &KINDS {
+        let samples = samples(kind, [3, 7], 0, 4096);
+        for d in 0..DIMENSIONS {
+            let chi2 = chi_squared(&dimension(&samples, d), 64);
+            assert!(chi2 < critical, "{} dimension {}: chi-squared {:.1}", name, d, chi2);
+        }
+    }
+}
+
+#[test]
+fn dimensions_mean_and_variance() {
+    for &(kind, name) in &KINDS {
+        let samples = samples(kind, [42, 9], 0, 4096);
+        for d in 0..DIMENSIONS {
+            let xs = dimension(&samples, d);
+            let (m, v) = (mean(&xs), variance(&xs));
+            assert!((m - 0.5).abs() < 0.015, "{} dimension {}: mean {:.4}", name, d, m);
+            assert!((v - 1.0 / 12.0).abs() < 0.005, "{} dimension {}: variance {:.4}", name, d, v);
+        }
+    }
+}
+
+#[test]
+fn dimensions_uncorrelated() {
+    for &(kind, name) in &KINDS {
+        let samples = samples(kind, [8, 13], 0, 4096);
+        for a in 0..DIMENSIONS {
+            for b in a + 1..DIMENSIONS {
+                let r = correlation(&dimension(&samples, a), &dimension(&samples, b));
+                assert!(r.abs() < max_correlation(samples.len()), "{} dimensions {} and {}: correlation {:.3}", name, a, b, r);
+            }
+        }
+    }
+}
+
+#[test]
+fn pixels_uncorrelated() {
+    // Blue noise correlates neighbours by design, see `blue_noise_error_is_high_frequency`.
+    for &(kind, name) in &KINDS[..3] {
+        let a = samples(kind, [100, 100], 0, 4096);
+        for neighbour in [[101, 100], [100, 101], [612, 100]] {
+            let b = samples(kind, neighbour, 0, 4096);
+            for d in 0..DIMENSIONS {
+                let r = correlation(&dimension(&a, d), &dimension(&b, d));
+                assert!(r.abs() < max_correlation(a.len()), "{} dimension {} at {:?}: correlation {:.3}", name, d, neighbour, r);
+            }
+        }
+    }
+}
+
+#[test]
+fn blue_noise_error_is_high_frequency() {
+    for d in 0..DIMENSIONS {
+        let white = block_error_variance(sampler::PCG, d);
+        let blue = block_error_variance(sampler::BLUE_NOISE, d);
+        assert!(blue < white / 2.0, "dimension {}: block error variance {:.2e} against PCG's {:.2e}", d, blue, white);
+    }
+}
+
+#[test]
+fn low_discrepancy_samplers_stratify_pairs() {
+    let n = 256;
+    let discrepancy = |kind, d: usize| {
+        let samples = samples(kind, [5, 11], 0, n);
+        let points: Vec<_> = samples.iter().map(|s| (s[d], s[d + 1])).collect();
+        l2_star_discrepancy(&points)
+    };
+    // The expected L2 star discrepancy of uniform random points is sqrt((1/4 - 1/9) / n).
+    let random = ((0.25 - 1.0 / 9.0) / n as f64).sqrt();
+    let pcg = discrepancy(sampler::PCG, 0);
+    assert!(pcg < random * 2.0 && pcg > random * 0.25, "PCG: discrepancy {:.5}", pcg);
+    for &(kind, name) in &KINDS[1..] {
+        for d in [0, 2, 4] {
+            let ld = discrepancy(kind, d);
+            assert!(ld < random * 0.5, "{} dimensions {}, {}: discrepancy {:.5}", name, d, d + 1, ld);
+        }
+    }
+}
+
+#[test]
+fn low_discrepancy_samplers_converge_faster() {
+    let pcg = rms_integration_error(sampler::PCG, 256);
+    for &(kind, name) in &KINDS[1..] {
+        let coarse = rms_integration_error(kind, 64);
+        let fine = rms_integration_error(kind, 256);
+        // Random sampling only halves the error for four times the samples.
+        assert!(fine < coarse / 2.5, "{}: error {:.2e} to {:.2e}", name, coarse, fine);
+        assert!(fine < pcg / 4.0, "{}: error {:.2e} against PCG's {:.2e}", name, fine, pcg);
+    }
+}
+
+#[test]
+fn accumulated_frames_continue_the_sequence() {
+    // Frames offset the first index, so consecutive frames of 64 samples should stratify as well
+    // as one frame of 256.
+    for &(kind, name) in &KINDS[1..] {
+        let frames: Vec<_> = (0..4).flat_map(|f| samples(kind, [21, 2], f * 64, 64)).collect();
+        let whole = samples(kind, [21, 2], 0, 256);
+        assert_eq!(frames, whole, "{} frames don't continue the sequence", name);
+    }
+}
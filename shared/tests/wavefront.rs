@@ -0,0 +1,148 @@
+//! Tests that the wavefront kernels' path tracing in `shared::wavefront` agrees with `color`.
+//!
+//! The two are different estimators, as the wavefront also samples the environment from
+//! `Lambertian` surfaces and weighs those samples against escaping rays by multiple importance
+//! sampling. They should only agree in expectation, so the means of many paths are compared.
+
+use glam::{vec3, Vec2, Vec3, Vec3A};
+use shared::environment::uniform_sphere;
+use shared::sampler::{self, Sampler, Samplers};
+use shared::wavefront::{self, PathState};
+use shared::{
+    color, Dielectric, Environment, Lambertian, MaterialInfo, MaterialKind, Materials, Metal,
+    PathStats, PathTermination, Ray, Sphere, Volume, World,
+};
+use std::f32::consts::PI;
+
+// Paths traced by each estimator for each ray.
+const PATHS: u32 = 1 << 15;
+
+// The largest relative difference allowed between the means, well above their standard error.
+const TOLERANCE: f32 = 0.03;
+
+// A bright sky over a dim ground, sampled from the upper hemisphere only. Directions below the
+// horizon can only be reached by scattering, which exercises both sides of the MIS weights.
+#[derive(Copy, Clone)]
+struct Sky;
+
+impl Environment for Sky {
+    fn radiance(self, direction: Vec3) -> Vec3 {
+        if direction.y > 0.0 {
+            vec3(1.0, 1.5, 2.0) * (1.0 + 4.0 * direction.y)
+        } else {
+            Vec3::splat(0.1)
+        }
+    }
+
+    fn sample(self, u: Vec2, pdf: &mut f32) -> Vec3 {
+        let d = uniform_sphere(u);
+        *pdf = 1.0 / (2.0 * PI);
+        vec3(d.x, d.y.abs(), d.z)
+    }
+
+    fn pdf(self, direction: Vec3) -> f32 {
+        if direction.y > 0.0 {
+            1.0 / (2.0 * PI)
+        } else {
+            0.0
+        }
+    }
+}
+
+const TERMINATION: PathTermination = PathTermination {
+    bounce_limit: 8,
+    roulette_depth: 3,
+};
+
+// Trace a path as the wavefront kernels do, running each kernel's step for the one path.
+fn wavefront_radiance(sampler: Samplers, ray: Ray, world: World, materials: Materials) -> Vec3 {
+    let mut state = PathState {
+        sampler,
+        origin: ray.origin().into(),
+        direction: ray.direction().into(),
+        throughput: Vec3A::ONE,
+        ..Default::default()
+    };
+    let mut queue = 0;
+    while state.extend(world, Sky, &mut queue) {
+        let kind = match queue {
+            wavefront::LAMBERTIAN => MaterialKind::Lambertian,
+            wavefront::METAL => MaterialKind::Metal,
+            wavefront::DIELECTRIC => MaterialKind::Dielectric,
+            _ => MaterialKind::Volume,
+        };
+        // Only `wavefront_shade_lambertian_cs` samples the environment.
+        let sample_env = queue == wavefront::LAMBERTIAN;
+        let mut shadow = false;
+        let continues = state.shade(materials, kind, TERMINATION, Sky, sample_env, &mut shadow);
+        if shadow {
+            state.trace_shadow(world);
+        }
+        if !continues {
+            break;
+        }
+    }
+    state.radiance.into()
+}
+
+#[test]
+fn wavefront_agrees_with_color() {
+    let lambertian = [Lambertian::new(vec3(0.5, 0.5, 0.5)), Lambertian::new(vec3(0.8, 0.3, 0.2))];
+    let metal = [Metal::new(vec3(0.8, 0.8, 0.9), 0.2)];
+    let dielectric = [Dielectric::new(1.5)];
+    let volume = [Volume::new(vec3(0.9, 0.9, 0.9), 1.0)];
+    let materials = Materials {
+        lambertian: &lambertian,
+        metal: &metal,
+        dielectric: &dielectric,
+        volume: &volume,
+    };
+    let sphere = |center: Vec3, radius, kind, index| Sphere {
+        center: center.into(),
+        radius,
+        material: MaterialInfo::new(kind, index),
+    };
+    let spheres = [
+        sphere(vec3(0.0, -1000.0, 0.0), 1000.0, MaterialKind::Lambertian, 0),
+        sphere(vec3(-2.0, 1.0, -4.0), 1.0, MaterialKind::Lambertian, 1),
+        sphere(vec3(0.0, 1.0, -4.0), 1.0, MaterialKind::Metal, 0),
+        sphere(vec3(2.0, 1.0, -4.0), 1.0, MaterialKind::Dielectric, 0),
+        sphere(vec3(0.0, 0.5, -2.0), 0.5, MaterialKind::Volume, 0),
+    ];
+    let world = World {
+        spheres: &spheres,
+        count: spheres.len() as u32,
+    };
+
+    // Rays towards each sphere and the ground between them.
+    let origin = vec3(0.0, 1.0, 2.0);
+    let targets = [
+        vec3(0.0, 0.0, -1.0),
+        vec3(-2.0, 1.0, -4.0),
+        vec3(0.0, 1.0, -4.0),
+        vec3(2.0, 1.0, -4.0),
+        vec3(0.0, 0.5, -2.0),
+    ];
+    for (i, &target) in targets.iter().enumerate() {
+        let ray = Ray::new(origin, (target - origin).normalize());
+        let (mut megakernel, mut wavefront) = (Vec3::ZERO, Vec3::ZERO);
+        for path in 0..PATHS {
+            let mut sampler = Samplers::new(sampler::SOBOL, [i as u32, 0], 0);
+            sampler.start_sample(path);
+            let mut stats = PathStats::default();
+            megakernel += color(TERMINATION, &mut sampler, ray, world, materials, Sky, &mut stats);
+            let mut sampler = Samplers::new(sampler::SOBOL, [i as u32, 1], 0);
+            sampler.start_sample(path);
+            wavefront += wavefront_radiance(sampler, ray, world, materials);
+        }
+        let (megakernel, wavefront) = (megakernel / PATHS as f32, wavefront / PATHS as f32);
+        let difference = (wavefront - megakernel).abs().max_element() / megakernel.max_element();
+        assert!(
+            difference < TOLERANCE,
+            "towards {}: the wavefront's mean {} differs from color's {}",
+            target,
+            wavefront,
+            megakernel,
+        );
+    }
+}